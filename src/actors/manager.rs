@@ -1,22 +1,258 @@
 use crate::actors::message::{
-    ActivateTask, BroadcastNotification, CreateActor, ForwardToActor, GetActorCount,
-    InteractWithActor, InteractWithUser, QueryActorState, TrackTaskProgress,
+    ActivateTask, ActorDetails, ActorPage, ActorProfile, ActorReply, BroadcastNotification,
+    BroadcastReport, Consult, ConsultActor, CreateActor, DeleteActor, FetchHistoricalInteractions,
+    ForgetUser, ForwardToActor, GetActorCount, GetActorDetails, GetProfile, GetUserActor, GoalPlan,
+    HuddleContribution, HuddleReport, InspectActor, InteractWithActor, InteractWithUser,
+    ListActors, ListUserActors, PlanGoal, QueryActorState, ReactToChange, RowChanged, TeamHuddle,
+    TrackTaskProgress, UpdateActor,
 };
 use crate::actors::user_actor::UserActor;
+use crate::services::context::{
+    count_tokens, message_tokens, PromptBuilder, Trim, MESSAGE_OVERHEAD, REPLY_PRIMING, REQUIRED,
+};
+use crate::services::notifications::{resolve_recipients, send_notification, NewNotification};
+use crate::services::usage::{meter, store_calls};
+use crate::services::vector_store::{knowledge_namespace, CHAT_NAMESPACE};
+use crate::services::Services;
 use actix::prelude::*;
-use std::collections::HashMap;
+use futures::future::join_all;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use uuid::Uuid;
 
+/// How many hops a consultation may take (A asks B, B asks C, ...).
+const MAX_CONSULT_DEPTH: usize = 2;
+/// Most tokens the huddle summary may take.
+const HUDDLE_SUMMARY_TOKENS: usize = 300;
+const HUDDLE_PROMPT: &str = "You coordinate a team of life coaches. Combine their advice into \
+    one consistent, prioritized answer for the user, resolving any conflicts.";
+
+#[derive(Clone)]
+struct ActorEntry {
+    addr: Addr<UserActor>,
+    user_id: String,
+    expertise: String,
+    pending: Arc<AtomicUsize>, // Requests sent to the actor that have not been answered yet
+}
+
+/// Counts a request as pending for as long as it is alive.
+struct PendingGuard(Arc<AtomicUsize>);
+
+impl PendingGuard {
+    fn new(pending: &Arc<AtomicUsize>) -> Self {
+        pending.fetch_add(1, Ordering::SeqCst);
+        PendingGuard(pending.clone())
+    }
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl ActorEntry {
+    /// Sends a message to the actor, counting it in the backlog until it is answered.
+    async fn request<M>(&self, msg: M) -> Result<M::Result, MailboxError>
+    where
+        M: Message + Send + 'static,
+        M::Result: Send,
+        UserActor: Handler<M>,
+    {
+        let _guard = PendingGuard::new(&self.pending);
+        self.addr.send(msg).await
+    }
+}
+
+/// How many actors a user may have for each expertise. Read from `ACTOR_LIMITS`, e.g.
+/// "default=1,personal development=3"; expertise is matched case-insensitively.
+#[derive(Debug, Clone)]
+pub struct ActorLimits {
+    pub default: usize,
+    pub per_expertise: HashMap<String, usize>,
+}
+
+impl ActorLimits {
+    pub fn from_env() -> Self {
+        ActorLimits::parse(&std::env::var("ACTOR_LIMITS").unwrap_or_default())
+    }
+
+    pub fn parse(spec: &str) -> Self {
+        let mut limits = ActorLimits {
+            default: 1,
+            per_expertise: HashMap::new(),
+        };
+        for (expertise, limit) in spec.split(',').filter_map(|entry| entry.split_once('=')) {
+            let Ok(limit) = limit.trim().parse() else {
+                println!("Warning: ignoring invalid actor limit '{}'", limit);
+                continue;
+            };
+            match expertise.trim().to_lowercase().as_str() {
+                "default" => limits.default = limit,
+                expertise => {
+                    limits.per_expertise.insert(expertise.to_string(), limit);
+                }
+            }
+        }
+        limits
+    }
+
+    pub fn limit_for(&self, expertise: &str) -> usize {
+        self.per_expertise
+            .get(&expertise.trim().to_lowercase())
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
 pub struct Manager {
-    actors: HashMap<String, Addr<UserActor>>, // Map user_id to their UserActor
+    actors: HashMap<String, ActorEntry>, // Map actor_id to the UserActor and its owner
+    limits: ActorLimits,
+    services: Services,
 }
 
 impl Manager {
-    pub fn new() -> Self {
+    pub fn new(services: Services) -> Self {
+        Manager::with_limits(services, ActorLimits::from_env())
+    }
+
+    pub fn with_limits(services: Services, limits: ActorLimits) -> Self {
         Manager {
             actors: HashMap::new(),
+            limits,
+            services,
         }
     }
+
+    /// Fails if the user already has as many `expertise` actors as allowed, not counting
+    /// `except_actor_id`. `stored` are the user's saved actors: those from before a restart
+    /// aren't running, but still count.
+    fn check_expertise_limit(
+        &self,
+        user_id: &str,
+        expertise: &str,
+        except_actor_id: Option<&str>,
+        stored: &[Value],
+    ) -> Result<(), String> {
+        let limit = self.limits.limit_for(expertise);
+        let same_expertise = |other: &str| other.trim().eq_ignore_ascii_case(expertise.trim());
+        let running = self
+            .actors
+            .iter()
+            .filter(|(_, entry)| entry.user_id == user_id && same_expertise(&entry.expertise))
+            .map(|(actor_id, _)| actor_id.clone());
+        let saved = stored
+            .iter()
+            .filter(|row| {
+                // A running actor's expertise may have changed since it was saved.
+                let id = row["id"].as_str().unwrap_or_default();
+                !self.actors.contains_key(id)
+                    && same_expertise(row["specialty"].as_str().unwrap_or_default())
+            })
+            .filter_map(|row| row["id"].as_str().map(str::to_string));
+        let existing = running
+            .chain(saved)
+            .filter(|actor_id| Some(actor_id.as_str()) != except_actor_id)
+            .collect::<HashSet<_>>()
+            .len();
+        if existing >= limit {
+            return Err(format!(
+                "User {} already has {} {} actor(s), the maximum allowed",
+                user_id, existing, expertise
+            ));
+        }
+        Ok(())
+    }
+
+    /// The actor with the given id, if it belongs to `user_id`.
+    fn owned_actor(&self, user_id: &str, actor_id: &str) -> Result<ActorEntry, String> {
+        self.actors
+            .get(actor_id)
+            .filter(|entry| entry.user_id == user_id)
+            .cloned()
+            .ok_or_else(|| format!("No actor found for user {} and actor {}", user_id, actor_id))
+    }
+
+    /// The coach responsible for a goal: the goal's assigned actor or, when none is running,
+    /// the user's actor whose expertise matches the goal's category.
+    fn coach_for(
+        &self,
+        user_id: &str,
+        agent_id: Option<&str>,
+        category: Option<&str>,
+    ) -> Option<ActorEntry> {
+        if let Some(entry) = agent_id.and_then(|id| self.owned_actor(user_id, id).ok()) {
+            return Some(entry);
+        }
+        let category = category?.trim().to_lowercase();
+        self.actors
+            .values()
+            .find(|entry| {
+                entry.user_id == user_id && entry.expertise.trim().to_lowercase() == category
+            })
+            .cloned()
+    }
+
+    fn user_actors(&self, user_id: &str) -> Vec<ActorEntry> {
+        self.actors
+            .values()
+            .filter(|entry| entry.user_id == user_id)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Fetches the profile of every actor in `actors`, skipping any that have stopped.
+async fn fetch_profiles(actors: Vec<ActorEntry>) -> Vec<(ActorEntry, ActorProfile)> {
+    let profiles = join_all(actors.iter().map(|entry| entry.addr.send(GetProfile))).await;
+    actors
+        .into_iter()
+        .zip(profiles)
+        .filter_map(|(entry, profile)| profile.ok().map(|profile| (entry, profile)))
+        .collect()
+}
+
+/// Saves the actor's profile to its `ai_agents` row.
+async fn persist_actor(services: &Services, profile: &ActorProfile) -> Result<(), String> {
+    let Some(db) = &services.db else {
+        return Ok(());
+    };
+    db.save_actor(json!({
+        "id": profile.id,
+        "user_id": profile.user_id,
+        "name": profile.name,
+        "personality": profile.personality,
+        "specialty": profile.expertise,
+        "goals": profile.goals,
+        "knowledge_base": profile.knowledge_base,
+        "avatar_url": profile.picture_url,
+    }))
+    .await
+}
+
+/// The user's saved actors; none without a database.
+async fn stored_actors(services: &Services, user_id: &str) -> Result<Vec<Value>, String> {
+    match &services.db {
+        Some(db) => db.list_user_actors(user_id).await,
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Fetches the state of every actor in `actors`, including its current backlog.
+async fn fetch_details(actors: Vec<ActorEntry>) -> Vec<ActorDetails> {
+    let states = join_all(actors.iter().map(|entry| entry.addr.send(GetActorDetails))).await;
+    actors
+        .into_iter()
+        .zip(states)
+        .filter_map(|(entry, state)| {
+            state.ok().map(|mut state| {
+                state.mailbox_backlog = entry.pending.load(Ordering::SeqCst);
+                state
+            })
+        })
+        .collect()
 }
 
 impl Actor for Manager {
@@ -24,20 +260,56 @@ impl Actor for Manager {
 }
 
 impl Handler<BroadcastNotification> for Manager {
-    type Result = Result<(), String>;
+    type Result = ResponseFuture<Result<BroadcastReport, String>>;
 
     fn handle(&mut self, msg: BroadcastNotification, _: &mut Context<Self>) -> Self::Result {
-        for (actor_id, actor_addr) in &self.actors {
-            if msg.recipients.is_empty() || msg.recipients.contains(actor_id) {
-                let notification = msg.message.clone();
-                let recipient_actor = actor_addr.clone();
-                recipient_actor.do_send(InteractWithActor {
-                    actor_id: actor_id.clone(),
-                    message: notification,
-                });
+        let services = self.services.clone();
+        Box::pin(async move {
+            let db = services
+                .db
+                .clone()
+                .ok_or_else(|| "Database is not configured".to_string())?;
+            let recipients =
+                resolve_recipients(db.as_ref(), &msg.recipients, msg.segment.as_ref()).await?;
+            println!("Broadcasting '{}' to {} users", msg.title, recipients.len());
+
+            let title = if msg.title.is_empty() {
+                "Announcement".to_string()
+            } else {
+                msg.title
+            };
+            let results = join_all(recipients.iter().map(|user_id| {
+                send_notification(
+                    &services,
+                    NewNotification {
+                        user_id: user_id.clone(),
+                        title: title.clone(),
+                        body: msg.message.clone(),
+                        kind: "broadcast".to_string(),
+                        actor_id: None,
+                        data: json!({}),
+                    },
+                )
+            }))
+            .await;
+
+            let mut report = BroadcastReport {
+                recipients: recipients.len(),
+                delivered: 0,
+                failed: Vec::new(),
+            };
+            for (user_id, result) in recipients.into_iter().zip(results) {
+                match result {
+                    Ok(row) if row["delivered_at"].is_string() => report.delivered += 1,
+                    Ok(_) => {}
+                    Err(e) => {
+                        println!("Warning: notification for {} failed: {}", user_id, e);
+                        report.failed.push(user_id);
+                    }
+                }
             }
-        }
-        Ok(())
+            Ok(report)
+        })
     }
 }
 
@@ -45,7 +317,7 @@ impl Handler<QueryActorState> for Manager {
     type Result = Result<String, String>;
 
     fn handle(&mut self, msg: QueryActorState, _: &mut Context<Self>) -> Self::Result {
-        if let Some(actor) = self.actors.get(&msg.actor_id) {
+        if self.actors.contains_key(&msg.actor_id) {
             Ok(format!("Actor {} is active", msg.actor_id))
         } else {
             Err(format!("Actor {} not found", msg.actor_id))
@@ -84,30 +356,42 @@ impl Handler<TrackTaskProgress> for Manager {
 }
 
 impl Handler<CreateActor> for Manager {
-    type Result = Result<Uuid, String>;
+    type Result = ResponseActFuture<Self, Result<Uuid, String>>;
 
     fn handle(&mut self, msg: CreateActor, _: &mut Context<Self>) -> Self::Result {
-        let actor_id = Uuid::new_v4();
-        if self.actors.contains_key(&actor_id.to_string()) {
-            return Err(format!(
-                "Actor for user {} already exists with actor_id {}",
-                msg.user_id, actor_id
-            ));
-        }
-        let actor = UserActor::new(
-            actor_id,
-            msg.user_id.clone(),
-            msg.name,
-            msg.personality,
-            msg.picture_url,
-            msg.expertise,
-            msg.goals,
-            msg.knowledge_base,
-        )
-        .start();
+        let services = self.services.clone();
+        let user_id = msg.user_id.clone();
+        let stored = async move { stored_actors(&services, &user_id).await };
+
+        // The limit is checked again once the stored actors are in, against the actors
+        // running by then, so concurrent creates can't both slip under it.
+        Box::pin(stored.into_actor(self).map(move |stored, manager, ctx| {
+            manager.check_expertise_limit(&msg.user_id, &msg.expertise, None, &stored?)?;
+            let actor_id = Uuid::new_v4();
+            let user_id = msg.user_id.clone();
+            let expertise = msg.expertise.clone();
+            let user_actor = UserActor::new(actor_id, msg, manager.services.clone(), ctx.address());
+            let profile = user_actor.profile();
+            let actor = user_actor.start();
+
+            let services = manager.services.clone();
+            actix::spawn(async move {
+                if let Err(e) = persist_actor(&services, &profile).await {
+                    println!("Warning: failed to save actor {}: {}", profile.id, e);
+                }
+            });
 
-        self.actors.insert(actor_id.to_string(), actor);
-        Ok(actor_id)
+            manager.actors.insert(
+                actor_id.to_string(),
+                ActorEntry {
+                    addr: actor,
+                    user_id,
+                    expertise,
+                    pending: Arc::new(AtomicUsize::new(0)),
+                },
+            );
+            Ok(actor_id)
+        }))
     }
 }
 
@@ -120,29 +404,492 @@ impl Handler<GetActorCount> for Manager {
 }
 
 impl Handler<ForwardToActor> for Manager {
-    type Result = ResponseFuture<Result<String, String>>;
+    type Result = ResponseFuture<Result<ActorReply, String>>;
 
     fn handle(&mut self, msg: ForwardToActor, _: &mut Context<Self>) -> Self::Result {
         println!("Handle Forward To Actor");
         let user_id = msg.user_id.clone();
         let actor_id = msg.actor_id.clone();
         let query = msg.query;
+        let access_token = msg.access_token;
+        let response_schema = msg.response_schema;
 
-        if let Some(actor) = self.actors.get(&actor_id) {
-            let actor_addr = actor.clone();
-            Box::pin(async move {
-                actor_addr
-                    .send(InteractWithUser { user_id, query })
-                    .await
-                    .unwrap_or_else(|_| Err("Actor failed to respond".to_string()))
-            })
-        } else {
-            Box::pin(async move {
+        match self.actors.get(&actor_id) {
+            Some(entry) if entry.user_id == user_id => {
+                let entry = entry.clone();
+                Box::pin(async move {
+                    entry
+                        .request(InteractWithUser {
+                            user_id,
+                            query,
+                            access_token,
+                            response_schema,
+                        })
+                        .await
+                        .unwrap_or_else(|_| Err("Actor failed to respond".to_string()))
+                })
+            }
+            _ => Box::pin(async move {
                 Err(format!(
                     "No actor found for user {} and actor {}",
                     user_id, actor_id
                 ))
+            }),
+        }
+    }
+}
+
+impl Handler<FetchHistoricalInteractions> for Manager {
+    type Result = ResponseFuture<Result<Vec<serde_json::Value>, String>>;
+
+    fn handle(&mut self, msg: FetchHistoricalInteractions, _: &mut Context<Self>) -> Self::Result {
+        match self.actors.get(&msg.actor_id) {
+            Some(entry) if entry.user_id == msg.user_id => {
+                let actor_addr = entry.addr.clone();
+                Box::pin(async move {
+                    actor_addr
+                        .send(msg)
+                        .await
+                        .unwrap_or_else(|_| Err("Actor failed to respond".to_string()))
+                })
+            }
+            _ => Box::pin(async move {
+                Err(format!(
+                    "No actor found for user {} and actor {}",
+                    msg.user_id, msg.actor_id
+                ))
+            }),
+        }
+    }
+}
+
+impl Handler<InspectActor> for Manager {
+    type Result = ResponseFuture<Result<ActorDetails, String>>;
+
+    fn handle(&mut self, msg: InspectActor, _: &mut Context<Self>) -> Self::Result {
+        let entry = self.actors.get(&msg.actor_id).cloned();
+        Box::pin(async move {
+            let entry = entry.ok_or_else(|| format!("Actor {} not found", msg.actor_id))?;
+            fetch_details(vec![entry])
+                .await
+                .pop()
+                .ok_or_else(|| format!("Actor {} is not responding", msg.actor_id))
+        })
+    }
+}
+
+impl Handler<ListActors> for Manager {
+    type Result = ResponseFuture<ActorPage>;
+
+    fn handle(&mut self, msg: ListActors, _: &mut Context<Self>) -> Self::Result {
+        let actors: Vec<ActorEntry> = self
+            .actors
+            .values()
+            .filter(|entry| msg.user_id.as_ref().is_none_or(|id| *id == entry.user_id))
+            .cloned()
+            .collect();
+
+        Box::pin(async move {
+            let expertise = msg.expertise.map(|e| e.to_lowercase());
+            let personality = msg.personality.map(|p| p.to_lowercase());
+            let mut states: Vec<ActorDetails> = fetch_details(actors)
+                .await
+                .into_iter()
+                .filter(|state| {
+                    expertise
+                        .as_ref()
+                        .is_none_or(|e| state.profile.expertise.to_lowercase().contains(e))
+                        && personality
+                            .as_ref()
+                            .is_none_or(|p| state.profile.personality.to_lowercase() == *p)
+                })
+                .collect();
+            states
+                .sort_by(|a, b| (&a.created_at, a.profile.id).cmp(&(&b.created_at, b.profile.id)));
+
+            let page = msg.page.max(1);
+            let per_page = msg.per_page.max(1);
+            let total = states.len();
+            let actors = states
+                .into_iter()
+                .skip((page - 1) * per_page)
+                .take(per_page)
+                .collect();
+            ActorPage {
+                actors,
+                total,
+                page,
+                per_page,
+            }
+        })
+    }
+}
+
+impl Handler<GetUserActor> for Manager {
+    type Result = ResponseFuture<Result<ActorProfile, String>>;
+
+    fn handle(&mut self, msg: GetUserActor, _: &mut Context<Self>) -> Self::Result {
+        let entry = self.owned_actor(&msg.user_id, &msg.actor_id);
+        Box::pin(async move {
+            entry?
+                .addr
+                .send(GetProfile)
+                .await
+                .map_err(|_| "Actor failed to respond".to_string())
+        })
+    }
+}
+
+impl Handler<UpdateActor> for Manager {
+    type Result = ResponseActFuture<Self, Result<ActorProfile, String>>;
+
+    fn handle(&mut self, msg: UpdateActor, _: &mut Context<Self>) -> Self::Result {
+        let entry = self.owned_actor(&msg.user_id, &msg.actor_id);
+        let services = self.services.clone();
+        let actor_id = msg.actor_id.clone();
+        let user_id = msg.user_id.clone();
+        let stored = async move {
+            let entry = entry?;
+            let stored = stored_actors(&services, &user_id).await?;
+            Ok::<_, String>((entry, stored, services))
+        };
+
+        let update = stored
+            .into_actor(self)
+            .map(move |result, manager: &mut Manager, _| {
+                let (entry, stored, services) = result?;
+                if let Some(expertise) = &msg.changes.expertise {
+                    manager.check_expertise_limit(
+                        &msg.user_id,
+                        expertise,
+                        Some(&msg.actor_id),
+                        &stored,
+                    )?;
+                }
+                Ok::<_, String>((entry, msg, services))
             })
+            .then(|checked, manager, _| {
+                async move {
+                    let (entry, msg, services) = checked?;
+                    let profile = entry
+                        .request(msg)
+                        .await
+                        .unwrap_or_else(|_| Err("Actor failed to respond".to_string()))?;
+                    persist_actor(&services, &profile).await?;
+                    Ok(profile)
+                }
+                .into_actor(manager)
+            });
+
+        Box::pin(update.map(move |result, manager, _| {
+            if let (Ok(profile), Some(entry)) = (&result, manager.actors.get_mut(&actor_id)) {
+                entry.expertise = profile.expertise.clone();
+            }
+            result
+        }))
+    }
+}
+
+impl Handler<DeleteActor> for Manager {
+    type Result = ResponseActFuture<Self, Result<(), String>>;
+
+    fn handle(&mut self, msg: DeleteActor, _: &mut Context<Self>) -> Self::Result {
+        let entry = self.owned_actor(&msg.user_id, &msg.actor_id);
+        let services = self.services.clone();
+        let actor_id = msg.actor_id.clone();
+
+        // Clean up storage first so a failed delete can be retried with the actor still running.
+        let cleanup = async move {
+            let entry = entry?;
+            if let Some(db) = &services.db {
+                db.delete_actor(&msg.actor_id).await?;
+            }
+            if let Some(vectors) = &services.vectors {
+                vectors
+                    .delete(CHAT_NAMESPACE, json!({ "actor_id": msg.actor_id }))
+                    .await?;
+                vectors
+                    .delete(&knowledge_namespace(&msg.actor_id), json!({}))
+                    .await?;
+            }
+            Ok::<_, String>((entry, msg))
+        };
+
+        Box::pin(cleanup.into_actor(self).map(move |result, manager, _| {
+            let (entry, msg) = result?;
+            manager.actors.remove(&actor_id);
+            entry.addr.do_send(msg);
+            Ok(())
+        }))
+    }
+}
+
+impl Handler<ForgetUser> for Manager {
+    type Result = usize;
+
+    fn handle(&mut self, msg: ForgetUser, _: &mut Context<Self>) -> Self::Result {
+        let actor_ids: Vec<String> = self
+            .actors
+            .iter()
+            .filter(|(_, entry)| entry.user_id == msg.user_id)
+            .map(|(actor_id, _)| actor_id.clone())
+            .collect();
+        for actor_id in &actor_ids {
+            if let Some(entry) = self.actors.remove(actor_id) {
+                entry.addr.do_send(DeleteActor {
+                    user_id: msg.user_id.clone(),
+                    actor_id: actor_id.clone(),
+                });
+            }
         }
+        println!(
+            "Stopped {} actor(s) of user {}",
+            actor_ids.len(),
+            msg.user_id
+        );
+        actor_ids.len()
+    }
+}
+
+impl Handler<ListUserActors> for Manager {
+    type Result = ResponseFuture<Vec<ActorProfile>>;
+
+    fn handle(&mut self, msg: ListUserActors, _: &mut Context<Self>) -> Self::Result {
+        let actors = self.user_actors(&msg.user_id);
+        Box::pin(async move {
+            fetch_profiles(actors)
+                .await
+                .into_iter()
+                .map(|(_, profile)| profile)
+                .collect()
+        })
     }
 }
+
+impl Handler<ConsultActor> for Manager {
+    type Result = ResponseFuture<Result<ActorReply, String>>;
+
+    fn handle(&mut self, msg: ConsultActor, _: &mut Context<Self>) -> Self::Result {
+        println!(
+            "Actor {} consulting '{}' (depth {})",
+            msg.from_actor_id,
+            msg.colleague,
+            msg.trail.len()
+        );
+        let actors = self.user_actors(&msg.user_id);
+
+        Box::pin(async move {
+            if msg.trail.len() > MAX_CONSULT_DEPTH {
+                return Err(format!(
+                    "Consultation depth limit of {} reached",
+                    MAX_CONSULT_DEPTH
+                ));
+            }
+
+            let profiles = fetch_profiles(actors).await;
+            let from = profiles
+                .iter()
+                .find(|(_, profile)| profile.id.to_string() == msg.from_actor_id)
+                .map(|(_, profile)| profile.clone())
+                .ok_or_else(|| format!("Actor {} not found", msg.from_actor_id))?;
+
+            let wanted = msg.colleague.to_lowercase();
+            let (target, target_profile) = profiles
+                .iter()
+                .filter(|(_, profile)| profile.id != from.id)
+                .find(|(_, profile)| {
+                    profile.id.to_string() == wanted
+                        || profile.name.to_lowercase() == wanted
+                        || profile.expertise.to_lowercase().contains(&wanted)
+                        || wanted.contains(&profile.expertise.to_lowercase())
+                })
+                .ok_or_else(|| format!("No colleague matching '{}'", msg.colleague))?;
+
+            if msg.trail.contains(&target_profile.id.to_string()) {
+                return Err(format!(
+                    "{} is already part of this consultation",
+                    target_profile.name
+                ));
+            }
+
+            target
+                .request(Consult {
+                    note: format!(
+                        "Your colleague {} ({}) is consulting you about the user you both coach. \
+                        Answer with concise advice from your own area of expertise.",
+                        from.name, from.expertise
+                    ),
+                    question: msg.question,
+                    trail: msg.trail,
+                })
+                .await
+                .unwrap_or_else(|_| Err("Actor failed to respond".to_string()))
+        })
+    }
+}
+
+impl Handler<RowChanged> for Manager {
+    type Result = ();
+
+    fn handle(&mut self, msg: RowChanged, _: &mut Context<Self>) {
+        let change = msg.change;
+        let (Some(event), Some(user_id)) = (change.event(), change.user_id.as_deref()) else {
+            return;
+        };
+        let Some(coach) = self.coach_for(
+            user_id,
+            change.agent_id.as_deref(),
+            change.goal_category.as_deref(),
+        ) else {
+            println!("No coach for user {} to react to {}", user_id, event.name());
+            return;
+        };
+
+        actix::spawn(async move {
+            let result = coach
+                .request(ReactToChange { event })
+                .await
+                .unwrap_or_else(|_| Err("Actor failed to respond".to_string()));
+            if let Err(e) = result {
+                println!("Warning: coach failed to react to a row change: {}", e);
+            }
+        });
+    }
+}
+
+impl Handler<PlanGoal> for Manager {
+    type Result = ResponseFuture<Result<GoalPlan, String>>;
+
+    fn handle(&mut self, msg: PlanGoal, _: &mut Context<Self>) -> Self::Result {
+        let coach = self.coach_for(
+            &msg.user_id,
+            msg.goal["agent_id"].as_str(),
+            msg.goal["category"].as_str(),
+        );
+        Box::pin(async move {
+            let coach = coach.ok_or_else(|| "No coach is running for this goal".to_string())?;
+            coach
+                .request(msg)
+                .await
+                .unwrap_or_else(|_| Err("Actor failed to respond".to_string()))
+        })
+    }
+}
+
+impl Handler<TeamHuddle> for Manager {
+    type Result = ResponseFuture<Result<HuddleReport, String>>;
+
+    fn handle(&mut self, msg: TeamHuddle, _: &mut Context<Self>) -> Self::Result {
+        let actors = self.user_actors(&msg.user_id);
+        let services = self.services.clone();
+
+        Box::pin(async move {
+            let profiles = fetch_profiles(actors).await;
+            if profiles.is_empty() {
+                return Err(format!("No actors found for user {}", msg.user_id));
+            }
+
+            // Everyone is already in the huddle, so nobody needs to consult a colleague.
+            let trail: Vec<String> = profiles.iter().map(|(_, p)| p.id.to_string()).collect();
+            let replies = join_all(profiles.iter().map(|(entry, _)| {
+                entry.request(Consult {
+                    note:
+                        "The user asked their whole coaching team this question in a team huddle. \
+                        Give your perspective from your own area of expertise in a few sentences."
+                            .to_string(),
+                    question: msg.query.clone(),
+                    trail: trail.clone(),
+                })
+            }))
+            .await;
+
+            let contributions: Vec<HuddleContribution> = profiles
+                .into_iter()
+                .zip(replies)
+                .map(|((_, profile), reply)| {
+                    let reply =
+                        reply.unwrap_or_else(|_| Err("Actor failed to respond".to_string()));
+                    HuddleContribution {
+                        actor_id: profile.id,
+                        name: profile.name,
+                        expertise: profile.expertise,
+                        response: reply.as_ref().ok().map(|r| r.response.clone()),
+                        error: reply.err(),
+                    }
+                })
+                .collect();
+
+            let summary = summarize_huddle(&services, &msg.user_id, &msg.query, &contributions)
+                .await
+                .map_err(|e| println!("Warning: huddle summary failed: {}", e))
+                .ok();
+
+            Ok(HuddleReport {
+                query: msg.query,
+                contributions,
+                summary,
+            })
+        })
+    }
+}
+
+/// Merges the coaches' answers into one coordinated plan.
+async fn summarize_huddle(
+    services: &Services,
+    user_id: &str,
+    query: &str,
+    contributions: &[HuddleContribution],
+) -> Result<String, String> {
+    let advice: Vec<String> = contributions
+        .iter()
+        .filter_map(|c| {
+            c.response
+                .as_ref()
+                .map(|response| format!("{} ({}): {}", c.name, c.expertise, response))
+        })
+        .collect();
+    if advice.len() < 2 {
+        return Err("Not enough contributions to summarize".to_string());
+    }
+
+    // The answers are cut from the last one back when they don't fit with the question.
+    let budget = services
+        .context
+        .budget(&services.llm.models(), HUDDLE_SUMMARY_TOKENS);
+    let prompt = PromptBuilder::new()
+        .fixed(count_tokens(HUDDLE_PROMPT) + 2 * MESSAGE_OVERHEAD + REPLY_PRIMING)
+        .section("advice", 0, Trim::End, advice)
+        .framing(0, 1)
+        .section(
+            "query",
+            REQUIRED,
+            Trim::End,
+            vec![format!("Question: {}\n\n", query)],
+        )
+        .fit(budget.prompt);
+    let messages = vec![
+        json!({ "role": "system", "content": HUDDLE_PROMPT }),
+        json!({
+            "role": "user",
+            "content": format!("{}{}", prompt.text("query", ""), prompt.text("advice", "\n\n"))
+        }),
+    ];
+    let max_tokens = budget
+        .max_tokens(message_tokens(&messages))
+        .min(HUDDLE_SUMMARY_TOKENS);
+
+    let completion = services
+        .llm
+        .chat(json!({
+            "messages": messages,
+            "max_tokens": max_tokens,
+            "temperature": 0.5
+        }))
+        .await?;
+    let call = meter(services, &completion);
+    store_calls(services, user_id, None, None, "huddle", &[call]).await;
+
+    completion
+        .content
+        .ok_or_else(|| "No response text found".to_string())
+}
@@ -1,3 +1,8 @@
+use crate::services::llm::TokenUsage;
+use crate::services::moderation::ModerationFlag;
+use crate::services::notifications::Segment;
+use crate::services::realtime::{CoachEvent, RowChange};
+use crate::services::structured::ResponseSchema;
 use actix::prelude::*;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
@@ -12,7 +17,8 @@ pub struct InteractWithActor {
 #[derive(Message, Serialize, Deserialize)]
 #[rtype(result = "Result<Uuid, String>")]
 pub struct CreateActor {
-    pub user_id: String,
+    #[serde(skip_deserializing)]
+    pub user_id: String, // Taken from the caller's token, never from a body
     pub name: String,
     pub personality: String,
     pub expertise: String,
@@ -21,11 +27,42 @@ pub struct CreateActor {
     pub picture_url: Option<String>,
 }
 
+/// An actor's answer, along with the LLM provider and model that produced it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActorReply {
+    pub response: String,
+    pub provider: String,
+    pub model: String,
+    #[serde(default)]
+    pub usage: TokenUsage, // Summed over every model call made for this reply
+    #[serde(default)]
+    pub cost_usd: f64, // Priced with the configured table, in USD
+    #[serde(default)]
+    pub citations: Vec<Citation>, // Knowledge documents the response refers to as [n]
+    #[serde(default)]
+    pub moderation: Vec<ModerationFlag>, // What the guardrails caught in the query or reply
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<serde_json::Value>, // Follows the interaction's response schema
+}
+
+/// A knowledge document passage cited in a reply.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Citation {
+    pub index: usize, // The n in the [n] marker
+    pub document_id: String,
+    pub title: String,
+    pub chunk_index: usize,
+}
+
 #[derive(Message, Serialize, Deserialize)]
-#[rtype(result = "Result<String, String>")]
+#[rtype(result = "Result<ActorReply, String>")]
 pub struct InteractWithUser {
     pub user_id: String,
     pub query: String,
+    #[serde(skip)]
+    pub access_token: Option<String>, // The caller's JWT; tools use it to act as the user
+    #[serde(default)]
+    pub response_schema: Option<ResponseSchema>,
 }
 
 #[derive(Message, Serialize, Deserialize, Debug)]
@@ -35,6 +72,13 @@ pub struct ActivateTask {
     pub parameters: Option<serde_json::Value>, // Allows for flexible task input
 }
 
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "Result<(), String>")]
+pub struct ScheduleReminder {
+    pub message: String,
+    pub remind_at: String, // RFC 3339 timestamp
+}
+
 #[derive(Message, Serialize, Deserialize)]
 #[rtype(result = "Result<(), String>")]
 pub struct StoreInteraction {
@@ -58,14 +102,27 @@ pub struct TrackTaskProgress {
 #[derive(Message, Serialize, Deserialize)]
 #[rtype(result = "Result<Vec<serde_json::Value>, String>")]
 pub struct FetchHistoricalInteractions {
+    pub user_id: String,
     pub actor_id: String,
 }
 
 #[derive(Message, Serialize, Deserialize)]
-#[rtype(result = "Result<(), String>")]
+#[rtype(result = "Result<BroadcastReport, String>")]
 pub struct BroadcastNotification {
+    #[serde(default)]
+    pub title: String,
     pub message: String,
-    pub recipients: Vec<String>, // Actor or user IDs
+    #[serde(default)]
+    pub recipients: Vec<String>, // User IDs; empty means every user
+    #[serde(default)]
+    pub segment: Option<Segment>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BroadcastReport {
+    pub recipients: usize,
+    pub delivered: usize,    // Pushed to at least one connected client
+    pub failed: Vec<String>, // Users whose notification could not be stored
 }
 
 #[derive(Message, Serialize, Deserialize)]
@@ -87,13 +144,231 @@ pub struct QueryActorState {
 }
 
 #[derive(Message, Serialize, Deserialize)]
-#[rtype(result = "Result<String, String>")]
+#[rtype(result = "Result<ActorReply, String>")]
 pub struct ForwardToActor {
-    pub user_id: String,
+    #[serde(skip_deserializing)]
+    pub user_id: String, // Taken from the caller's token, never from a body
     pub actor_id: String,
     pub query: String,
+    #[serde(skip)]
+    pub access_token: Option<String>, // Set by authenticated routes, never read from a body
+    #[serde(default)]
+    pub response_schema: Option<ResponseSchema>, // Asks for a structured payload with the reply
 }
 
 #[derive(Message, Serialize, Deserialize)]
 #[rtype(result = "usize")]
 pub struct GetActorCount;
+
+/// Snapshot of an actor's profile.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActorProfile {
+    pub id: Uuid,
+    pub user_id: String,
+    pub name: String,
+    pub personality: String,
+    pub expertise: String,
+    pub goals: Vec<String>,
+    pub knowledge_base: String,
+    pub picture_url: Option<String>,
+}
+
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "ActorProfile")]
+pub struct GetProfile;
+
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "Result<ActorProfile, String>")]
+pub struct GetUserActor {
+    pub user_id: String,
+    pub actor_id: String,
+}
+
+/// Profile fields to change; fields left out keep their current value.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ActorChanges {
+    pub name: Option<String>,
+    pub personality: Option<String>,
+    pub expertise: Option<String>,
+    pub goals: Option<Vec<String>>,
+    pub knowledge_base: Option<String>,
+    pub picture_url: Option<String>,
+}
+
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "Result<ActorProfile, String>")]
+pub struct UpdateActor {
+    pub user_id: String,
+    pub actor_id: String,
+    pub changes: ActorChanges,
+}
+
+/// Stops an actor and deletes its database rows and stored embeddings.
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "Result<(), String>")]
+pub struct DeleteActor {
+    pub user_id: String,
+    pub actor_id: String,
+}
+
+/// Runtime state of an actor, as shown to admins.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActorDetails {
+    #[serde(flatten)]
+    pub profile: ActorProfile,
+    pub created_at: String,
+    pub last_active_at: Option<String>,
+    pub history_len: usize,     // Exchanges held in the conversation buffer
+    pub mailbox_backlog: usize, // Requests sent to the actor that have not been answered yet
+    pub total_interactions: u64,
+    pub tokens_used: TokenUsage,
+}
+
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "ActorDetails")]
+pub struct GetActorDetails;
+
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "Result<ActorDetails, String>")]
+pub struct InspectActor {
+    pub actor_id: String,
+}
+
+/// Lists actors across all users. Filters are optional; `page` starts at 1.
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "ActorPage")]
+pub struct ListActors {
+    pub user_id: Option<String>,
+    pub expertise: Option<String>,
+    pub personality: Option<String>,
+    pub page: usize,
+    pub per_page: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActorPage {
+    pub actors: Vec<ActorDetails>,
+    pub total: usize,
+    pub page: usize,
+    pub per_page: usize,
+}
+
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "Vec<ActorProfile>")]
+pub struct ListUserActors {
+    pub user_id: String,
+}
+
+/// Stops all of a user's actors without touching storage, once their data has been erased.
+/// Returns how many were running.
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "usize")]
+pub struct ForgetUser {
+    pub user_id: String,
+}
+
+/// Asks the manager to route a question from one actor to another actor of the same user.
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "Result<ActorReply, String>")]
+pub struct ConsultActor {
+    pub user_id: String,
+    pub from_actor_id: String,
+    pub colleague: String, // Actor id, name or expertise
+    pub question: String,
+    pub trail: Vec<String>, // Actor ids already involved in this consultation chain
+}
+
+/// A question from a colleague or a team huddle, delivered to the consulted actor.
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "Result<ActorReply, String>")]
+pub struct Consult {
+    pub note: String, // Explains to the model who is asking and why
+    pub question: String,
+    pub trail: Vec<String>,
+}
+
+/// A task or goal row changed in the database, from any client.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RowChanged {
+    pub change: RowChange,
+}
+
+/// Asks the coach responsible for a goal to react to a change, e.g. celebrate a completion.
+#[derive(Message)]
+#[rtype(result = "Result<(), String>")]
+pub struct ReactToChange {
+    pub event: CoachEvent,
+}
+
+/// Tells an actor that one of its tools changed a row, so it doesn't react to its own
+/// change when the database reports it. `key` has the form of `CoachEvent::key`.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ExpectChange {
+    pub key: String,
+}
+
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "Result<HuddleReport, String>")]
+pub struct TeamHuddle {
+    pub user_id: String,
+    pub query: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HuddleContribution {
+    pub actor_id: Uuid,
+    pub name: String,
+    pub expertise: String,
+    pub response: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HuddleReport {
+    pub query: String,
+    pub contributions: Vec<HuddleContribution>,
+    pub summary: Option<String>,
+}
+
+/// A task a coach proposes for a goal. The user accepts proposals as they are or edited, and
+/// only accepted ones become rows in `tasks`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskProposal {
+    pub title: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub priority: String, // high, medium or low
+    pub duration: i32,    // Minutes
+    pub xp_reward: i32,
+    #[serde(default)]
+    pub ai_assignable: bool, // A coach could do the task without the user
+}
+
+/// Asks the coach of a goal to propose tasks for it. Nothing is written to the database.
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "Result<GoalPlan, String>")]
+pub struct PlanGoal {
+    pub user_id: String,
+    pub goal: serde_json::Value,
+    pub tasks: Vec<serde_json::Value>, // The goal's existing tasks, so they aren't proposed again
+    pub instructions: Option<String>,  // What the user wants from the plan, in their words
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GoalPlan {
+    pub actor_id: Uuid,
+    pub actor_name: String,
+    pub tasks: Vec<TaskProposal>,
+    pub provider: String,
+    pub model: String,
+    #[serde(default)]
+    pub usage: TokenUsage,
+}
+
+/// The proposals a user keeps from a plan, possibly edited. Proposals left out are rejected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AcceptedTasks {
+    pub tasks: Vec<TaskProposal>,
+}
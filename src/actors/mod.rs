@@ -1,3 +1,5 @@
 pub mod manager;
 pub mod message;
+pub mod tools;
 pub mod user_actor;
+pub mod validation;
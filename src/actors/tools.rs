@@ -0,0 +1,420 @@
+use crate::actors::manager::Manager;
+use crate::actors::message::{ConsultActor, ExpectChange, ListUserActors, ScheduleReminder};
+use crate::actors::user_actor::{UserActor, MAX_REMINDER_MINUTES};
+use crate::services::database::Database;
+use crate::services::notifications::{send_notification, NewNotification};
+use crate::services::vector_store::CHAT_NAMESPACE;
+use crate::services::Services;
+use actix::Addr;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Everything a tool needs to act on behalf of the actor that called it.
+#[derive(Clone)]
+pub struct ToolContext {
+    pub user_id: String,
+    pub actor_id: Uuid,
+    pub actor: Addr<UserActor>,
+    pub manager: Addr<Manager>,
+    pub trail: Vec<String>, // Actors involved in the current consultation chain
+    pub services: Services,
+    pub access_token: Option<String>, // The user's JWT when the request came from them
+}
+
+pub type ToolFuture = Pin<Box<dyn Future<Output = Result<Value, String>> + Send>>;
+pub type ToolHandler = fn(ToolContext, Value) -> ToolFuture;
+
+pub struct Tool {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub parameters: Value, // JSON schema of the arguments
+    pub handler: ToolHandler,
+}
+
+/// Server-side tools that actors can call through OpenAI-style function calling.
+pub struct ToolRegistry {
+    tools: HashMap<&'static str, Tool>,
+}
+
+impl ToolRegistry {
+    pub fn new() -> Self {
+        ToolRegistry {
+            tools: HashMap::new(),
+        }
+    }
+
+    pub fn register(&mut self, tool: Tool) {
+        self.tools.insert(tool.name, tool);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    /// Tool definitions in the `tools` format of the chat completions API.
+    pub fn definitions(&self) -> Vec<Value> {
+        let mut tools: Vec<&Tool> = self.tools.values().collect();
+        tools.sort_by_key(|tool| tool.name);
+        tools
+            .into_iter()
+            .map(|tool| {
+                json!({
+                    "type": "function",
+                    "function": {
+                        "name": tool.name,
+                        "description": tool.description,
+                        "parameters": tool.parameters,
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Runs a tool call. `arguments` is the JSON-encoded string produced by the model.
+    pub async fn execute(&self, name: &str, arguments: &str, ctx: ToolContext) -> Value {
+        let Some(tool) = self.tools.get(name) else {
+            return json!({ "error": format!("Unknown tool: {}", name) });
+        };
+        let args: Value = match serde_json::from_str(arguments) {
+            Ok(args) => args,
+            Err(e) => return json!({ "error": format!("Invalid arguments: {}", e) }),
+        };
+
+        println!("Actor {} calling tool {} with {}", ctx.actor_id, name, args);
+        match (tool.handler)(ctx, args).await {
+            Ok(result) => result,
+            Err(err) => json!({ "error": err }),
+        }
+    }
+}
+
+impl Default for ToolRegistry {
+    /// The built-in coaching tools.
+    fn default() -> Self {
+        let mut registry = ToolRegistry::new();
+        registry.register(Tool {
+            name: "list_goals",
+            description: "List the user's goals with their ids, categories, XP and status.",
+            parameters: json!({ "type": "object", "properties": {} }),
+            handler: list_goals,
+        });
+        registry.register(Tool {
+            name: "create_task",
+            description: "Add a task to one of the user's goals.",
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "goal": { "type": "string", "description": "Goal id, title or category" },
+                    "title": { "type": "string" },
+                    "description": { "type": "string" },
+                    "duration": { "type": "integer", "description": "Duration in minutes" },
+                    "priority": { "type": "string", "enum": ["high", "medium", "low"] },
+                    "xp_reward": { "type": "integer" }
+                },
+                "required": ["goal", "title"]
+            }),
+            handler: create_task,
+        });
+        registry.register(Tool {
+            name: "complete_task",
+            description: "Mark one of the user's tasks as completed and award its XP.",
+            parameters: json!({
+                "type": "object",
+                "properties": { "task_id": { "type": "string" } },
+                "required": ["task_id"]
+            }),
+            handler: complete_task,
+        });
+        registry.register(Tool {
+            name: "get_progress",
+            description: "Look up the user's overall level and XP and the XP of each goal.",
+            parameters: json!({ "type": "object", "properties": {} }),
+            handler: get_progress,
+        });
+        registry.register(Tool {
+            name: "search_interactions",
+            description: "Search past conversations between the user and this coach.",
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string" },
+                    "limit": { "type": "integer", "description": "Defaults to 5" }
+                },
+                "required": ["query"]
+            }),
+            handler: search_interactions,
+        });
+        registry.register(Tool {
+            name: "schedule_reminder",
+            description: "Schedule a reminder message for the user, at most 7 days ahead.",
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "message": { "type": "string" },
+                    "in_minutes": { "type": "integer", "minimum": 1, "maximum": MAX_REMINDER_MINUTES },
+                    "remind_at": { "type": "string", "description": "RFC 3339 timestamp" }
+                },
+                "required": ["message"]
+            }),
+            handler: schedule_reminder,
+        });
+        registry.register(Tool {
+            name: "notify_user",
+            description: "Send the user a message outside the chat, through their inbox and \
+                whichever email, push or webhook channels they have enabled.",
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "title": { "type": "string" },
+                    "message": { "type": "string" }
+                },
+                "required": ["title", "message"]
+            }),
+            handler: notify_user,
+        });
+        registry.register(Tool {
+            name: "list_colleagues",
+            description: "List the user's other coaches and their areas of expertise.",
+            parameters: json!({ "type": "object", "properties": {} }),
+            handler: list_colleagues,
+        });
+        registry.register(Tool {
+            name: "consult_colleague",
+            description:
+                "Ask another of the user's coaches for advice from their area of expertise.",
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "colleague": { "type": "string", "description": "Coach name, id or expertise" },
+                    "question": { "type": "string" }
+                },
+                "required": ["colleague", "question"]
+            }),
+            handler: consult_colleague,
+        });
+        registry
+    }
+}
+
+/// The database as the user sees it when their token is known, so RLS bounds what the
+/// model can reach; otherwise, as for consultations, the shared handle.
+fn database(ctx: &ToolContext) -> Result<Arc<dyn Database>, String> {
+    match &ctx.access_token {
+        Some(token) => ctx.services.user_db(token),
+        None => ctx
+            .services
+            .db
+            .clone()
+            .ok_or_else(|| "Database is not configured".to_string()),
+    }
+}
+
+fn str_arg<'a>(args: &'a Value, name: &str) -> Result<&'a str, String> {
+    args[name]
+        .as_str()
+        .filter(|value| !value.trim().is_empty())
+        .ok_or_else(|| format!("Missing argument: {}", name))
+}
+
+fn list_goals(ctx: ToolContext, _: Value) -> ToolFuture {
+    Box::pin(async move {
+        let goals = database(&ctx)?.list_goals(&ctx.user_id).await?;
+        Ok(json!({ "goals": goals }))
+    })
+}
+
+fn create_task(ctx: ToolContext, args: Value) -> ToolFuture {
+    Box::pin(async move {
+        let db = database(&ctx)?;
+        let goal_ref = str_arg(&args, "goal")?.to_lowercase();
+        let goals = db.list_goals(&ctx.user_id).await?;
+        let goal = goals
+            .iter()
+            .find(|goal| goal["id"].as_str() == Some(goal_ref.as_str()))
+            .or_else(|| {
+                goals.iter().find(|goal| {
+                    ["title", "category"].iter().any(|field| {
+                        goal[field].as_str().map(str::to_lowercase) == Some(goal_ref.clone())
+                    })
+                })
+            })
+            .ok_or_else(|| format!("No goal matching '{}'", goal_ref))?;
+
+        let mut task = json!({
+            "goal_id": goal["id"],
+            "title": str_arg(&args, "title")?,
+            "description": args["description"],
+            "duration": args["duration"],
+            "priority": args["priority"],
+        });
+        if let Some(xp_reward) = args["xp_reward"].as_i64() {
+            task["xp_reward"] = json!(xp_reward);
+        }
+
+        let task_id = db.add_task(task).await?;
+        Ok(json!({ "task_id": task_id, "goal": goal["title"] }))
+    })
+}
+
+fn complete_task(ctx: ToolContext, args: Value) -> ToolFuture {
+    Box::pin(async move {
+        let task_id = str_arg(&args, "task_id")?;
+        // Sent first: the database may report the change before `complete_task` returns.
+        ctx.actor.do_send(ExpectChange {
+            key: format!("tasks:{}", task_id),
+        });
+        let (xp_awarded, level) = database(&ctx)?.complete_task(&ctx.user_id, task_id).await?;
+        Ok(json!({ "task_id": task_id, "xp_awarded": xp_awarded, "level": level }))
+    })
+}
+
+fn get_progress(ctx: ToolContext, _: Value) -> ToolFuture {
+    Box::pin(async move {
+        let db = database(&ctx)?;
+        let user = db.get_user(&ctx.user_id).await?.unwrap_or_default();
+        let goals: Vec<Value> = db
+            .list_goals(&ctx.user_id)
+            .await?
+            .into_iter()
+            .map(|goal| {
+                json!({
+                    "title": goal["title"],
+                    "category": goal["category"],
+                    "xp": goal["xp"],
+                    "level": goal["level"],
+                })
+            })
+            .collect();
+        Ok(json!({ "level": user["level"], "total_xp": user["total_xp"], "goals": goals }))
+    })
+}
+
+fn search_interactions(ctx: ToolContext, args: Value) -> ToolFuture {
+    Box::pin(async move {
+        let query = str_arg(&args, "query")?;
+        let limit = args["limit"].as_u64().unwrap_or(5).clamp(1, 20) as usize;
+        let store = ctx
+            .services
+            .vectors
+            .clone()
+            .ok_or_else(|| "Vector store is not configured".to_string())?;
+
+        let embedding = ctx.services.embeddings.embed(query).await?;
+        let matches = store
+            .query(
+                CHAT_NAMESPACE,
+                embedding,
+                limit,
+                json!({ "user_id": ctx.user_id, "actor_id": ctx.actor_id.to_string() }),
+            )
+            .await?;
+        let interactions: Vec<Value> = matches
+            .into_iter()
+            .map(|m| {
+                json!({
+                    "query": m.metadata["query"],
+                    "response": m.metadata["response"],
+                    "relevance": m.score,
+                })
+            })
+            .collect();
+        Ok(json!({ "interactions": interactions }))
+    })
+}
+
+fn schedule_reminder(ctx: ToolContext, args: Value) -> ToolFuture {
+    Box::pin(async move {
+        let message = str_arg(&args, "message")?.to_string();
+        let remind_at = match (args["in_minutes"].as_i64(), args["remind_at"].as_str()) {
+            (Some(minutes), _) => chrono::Duration::try_minutes(minutes.max(1))
+                .and_then(|delay| Utc::now().checked_add_signed(delay))
+                .ok_or_else(|| format!("in_minutes is out of range: {}", minutes))?,
+            (None, Some(at)) => DateTime::parse_from_rfc3339(at)
+                .map_err(|e| format!("Invalid remind_at: {}", e))?
+                .with_timezone(&Utc),
+            (None, None) => return Err("Either in_minutes or remind_at is required".into()),
+        };
+        if remind_at <= Utc::now() {
+            return Err(format!(
+                "remind_at must be in the future, it is now {}",
+                Utc::now().to_rfc3339()
+            ));
+        }
+
+        ctx.actor
+            .send(ScheduleReminder {
+                message: message.clone(),
+                remind_at: remind_at.to_rfc3339(),
+            })
+            .await
+            .map_err(|_| "Actor failed to schedule reminder".to_string())??;
+        Ok(json!({ "scheduled_for": remind_at.to_rfc3339(), "message": message }))
+    })
+}
+
+fn notify_user(ctx: ToolContext, args: Value) -> ToolFuture {
+    Box::pin(async move {
+        let notification = send_notification(
+            &ctx.services,
+            NewNotification {
+                user_id: ctx.user_id.clone(),
+                title: str_arg(&args, "title")?.to_string(),
+                body: str_arg(&args, "message")?.to_string(),
+                kind: "actor".to_string(),
+                actor_id: Some(ctx.actor_id.to_string()),
+                data: json!({}),
+            },
+        )
+        .await?;
+        Ok(json!({
+            "notification_id": notification["id"],
+            "channels": notification["channel_deliveries"],
+        }))
+    })
+}
+
+fn list_colleagues(ctx: ToolContext, _: Value) -> ToolFuture {
+    Box::pin(async move {
+        let actors = ctx
+            .manager
+            .send(ListUserActors {
+                user_id: ctx.user_id.clone(),
+            })
+            .await
+            .map_err(|_| "Manager failed to respond".to_string())?;
+        let colleagues: Vec<Value> = actors
+            .into_iter()
+            .filter(|profile| profile.id != ctx.actor_id)
+            .map(|profile| {
+                json!({
+                    "id": profile.id,
+                    "name": profile.name,
+                    "expertise": profile.expertise,
+                })
+            })
+            .collect();
+        Ok(json!({ "colleagues": colleagues }))
+    })
+}
+
+fn consult_colleague(ctx: ToolContext, args: Value) -> ToolFuture {
+    Box::pin(async move {
+        let reply = ctx
+            .manager
+            .send(ConsultActor {
+                user_id: ctx.user_id.clone(),
+                from_actor_id: ctx.actor_id.to_string(),
+                colleague: str_arg(&args, "colleague")?.to_string(),
+                question: str_arg(&args, "question")?.to_string(),
+                trail: ctx.trail.clone(),
+            })
+            .await
+            .map_err(|_| "Manager failed to respond".to_string())??;
+        Ok(json!({ "advice": reply.response }))
+    })
+}
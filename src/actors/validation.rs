@@ -0,0 +1,460 @@
+use crate::actors::message::{
+    AcceptedTasks, ActivateTask, ActorChanges, BroadcastNotification, CreateActor, ForwardToActor,
+    TaskProposal, TeamHuddle,
+};
+use crate::services::channels::{check_destination, is_push_service, NotificationPreferences};
+use crate::services::planning::MAX_PROPOSALS;
+use crate::services::structured::{ReplySchema, ResponseSchema};
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Tones an actor can take; matches the `ai_agents.personality` check constraint.
+pub const PERSONALITIES: &[&str] = &["stern", "empathetic", "balanced"];
+
+/// Longest query sent to an actor, in characters. Keeps a single request's prompt cost bounded.
+pub const MAX_QUERY_CHARS: usize = 4000;
+const MAX_ID_CHARS: usize = 64;
+const MAX_NAME_CHARS: usize = 100;
+const MAX_EXPERTISE_CHARS: usize = 100;
+const MAX_GOALS: usize = 20;
+const MAX_GOAL_CHARS: usize = 200;
+const MAX_KNOWLEDGE_BASE_CHARS: usize = 10_000;
+const MAX_URL_CHARS: usize = 2048;
+const MAX_TITLE_CHARS: usize = 200;
+const MAX_MESSAGE_CHARS: usize = 2000;
+const MAX_RECIPIENTS: usize = 10_000;
+const MAX_TASK_PARAMETERS_BYTES: usize = 16 * 1024;
+const MAX_SCHEMA_NAME_CHARS: usize = 64;
+const MAX_SCHEMA_BYTES: usize = 16 * 1024;
+const MAX_TASK_TITLE_CHARS: usize = 200;
+const MAX_TASK_DESCRIPTION_CHARS: usize = 2000;
+/// Matches the `tasks.priority` check constraint.
+const TASK_PRIORITIES: &[&str] = &["high", "medium", "low"];
+const MAX_TASK_DURATION_MINUTES: i32 = 24 * 60;
+const MAX_TASK_XP_REWARD: i32 = 1000;
+/// Channels users can enable; each needs its own setting to deliver to.
+const NOTIFICATION_CHANNELS: &[&str] = &["email", "push", "webhook"];
+const MAX_EMAIL_CHARS: usize = 254;
+
+/// A rule a field failed. `code` is stable for clients; `message` is for people.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub code: &'static str,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ValidationErrors {
+    pub errors: Vec<FieldError>,
+}
+
+/// Checks a payload before it reaches an actor, reporting every field that breaks a rule.
+pub trait Validate {
+    fn validate(&self) -> Result<(), ValidationErrors>;
+}
+
+/// Collects field errors. Each field reports only the first rule it fails.
+#[derive(Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Validator::default()
+    }
+
+    pub fn field<'a>(&'a mut self, name: &str, value: &'a str) -> Field<'a> {
+        Field {
+            errors: &mut self.errors,
+            name: name.to_string(),
+            value,
+            failed: false,
+        }
+    }
+
+    /// Checks the list's length; `each` is then applied to every item as `name[i]`.
+    pub fn list(
+        &mut self,
+        name: &str,
+        items: &[String],
+        max_items: usize,
+        each: impl Fn(Field<'_>),
+    ) {
+        if items.len() > max_items {
+            self.errors.push(FieldError {
+                field: name.to_string(),
+                code: "too_many",
+                message: format!("must have at most {} items", max_items),
+            });
+            return;
+        }
+        for (i, item) in items.iter().enumerate() {
+            each(self.field(&format!("{}[{}]", name, i), item));
+        }
+    }
+
+    pub fn error(&mut self, name: &str, code: &'static str, message: String) {
+        self.errors.push(FieldError {
+            field: name.to_string(),
+            code,
+            message,
+        });
+    }
+
+    /// Checks that a number lies within `min..=max`.
+    pub fn range(&mut self, name: &str, value: i32, min: i32, max: i32) {
+        if !(min..=max).contains(&value) {
+            self.error(
+                name,
+                "out_of_range",
+                format!("must be between {} and {}", min, max),
+            );
+        }
+    }
+
+    /// Adds the errors of a nested payload, naming its fields `name.field`.
+    pub fn nested(&mut self, name: &str, result: Result<(), ValidationErrors>) {
+        if let Err(nested) = result {
+            self.errors
+                .extend(nested.errors.into_iter().map(|error| FieldError {
+                    field: format!("{}.{}", name, error.field),
+                    ..error
+                }));
+        }
+    }
+
+    pub fn finish(self) -> Result<(), ValidationErrors> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors {
+                errors: self.errors,
+            })
+        }
+    }
+}
+
+/// Rules for one string field, meant to be chained: `v.field("name", &name).required()`.
+pub struct Field<'a> {
+    errors: &'a mut Vec<FieldError>,
+    name: String,
+    value: &'a str,
+    failed: bool,
+}
+
+impl Field<'_> {
+    fn check(mut self, ok: bool, code: &'static str, message: impl FnOnce() -> String) -> Self {
+        if !self.failed && !ok {
+            self.errors.push(FieldError {
+                field: self.name.clone(),
+                code,
+                message: message(),
+            });
+            self.failed = true;
+        }
+        self
+    }
+
+    pub fn required(self) -> Self {
+        let ok = !self.value.trim().is_empty();
+        self.check(ok, "required", || "must not be empty".to_string())
+    }
+
+    pub fn max_chars(self, max: usize) -> Self {
+        let ok = self.value.chars().count() <= max;
+        self.check(ok, "too_long", || {
+            format!("must be at most {} characters", max)
+        })
+    }
+
+    pub fn one_of(self, allowed: &[&str]) -> Self {
+        let ok = allowed.contains(&self.value);
+        self.check(ok, "not_allowed", || {
+            format!("must be one of: {}", allowed.join(", "))
+        })
+    }
+
+    pub fn uuid(self) -> Self {
+        let ok = Uuid::parse_str(self.value).is_ok();
+        self.check(ok, "invalid_uuid", || "must be a UUID".to_string())
+    }
+
+    /// Only checks the shape; whether mail arrives is up to the address.
+    pub fn email(self) -> Self {
+        let ok = self
+            .value
+            .split_once('@')
+            .is_some_and(|(local, domain)| !local.is_empty() && domain.contains('.'));
+        self.check(ok, "invalid_email", || {
+            "must be an email address".to_string()
+        })
+    }
+
+    pub fn url(self) -> Self {
+        let ok = reqwest::Url::parse(self.value)
+            .is_ok_and(|url| matches!(url.scheme(), "http" | "https"));
+        self.check(ok, "invalid_url", || {
+            "must be an http or https URL".to_string()
+        })
+    }
+}
+
+impl Validate for CreateActor {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut v = Validator::new();
+        v.field("user_id", &self.user_id)
+            .required()
+            .max_chars(MAX_ID_CHARS);
+        v.field("name", &self.name)
+            .required()
+            .max_chars(MAX_NAME_CHARS);
+        v.field("personality", &self.personality)
+            .one_of(PERSONALITIES);
+        v.field("expertise", &self.expertise)
+            .required()
+            .max_chars(MAX_EXPERTISE_CHARS);
+        v.list("goals", &self.goals, MAX_GOALS, |goal| {
+            goal.required().max_chars(MAX_GOAL_CHARS);
+        });
+        v.field("knowledge_base", &self.knowledge_base)
+            .max_chars(MAX_KNOWLEDGE_BASE_CHARS);
+        if let Some(url) = &self.picture_url {
+            v.field("picture_url", url).max_chars(MAX_URL_CHARS).url();
+        }
+        v.finish()
+    }
+}
+
+impl Validate for ActorChanges {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut v = Validator::new();
+        if let Some(name) = &self.name {
+            v.field("name", name).required().max_chars(MAX_NAME_CHARS);
+        }
+        if let Some(personality) = &self.personality {
+            v.field("personality", personality).one_of(PERSONALITIES);
+        }
+        if let Some(expertise) = &self.expertise {
+            v.field("expertise", expertise)
+                .required()
+                .max_chars(MAX_EXPERTISE_CHARS);
+        }
+        if let Some(goals) = &self.goals {
+            v.list("goals", goals, MAX_GOALS, |goal| {
+                goal.required().max_chars(MAX_GOAL_CHARS);
+            });
+        }
+        if let Some(knowledge_base) = &self.knowledge_base {
+            v.field("knowledge_base", knowledge_base)
+                .max_chars(MAX_KNOWLEDGE_BASE_CHARS);
+        }
+        if let Some(url) = &self.picture_url {
+            v.field("picture_url", url).max_chars(MAX_URL_CHARS).url();
+        }
+        v.finish()
+    }
+}
+
+impl Validate for ForwardToActor {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut v = Validator::new();
+        v.field("user_id", &self.user_id)
+            .required()
+            .max_chars(MAX_ID_CHARS);
+        v.field("actor_id", &self.actor_id).uuid();
+        v.field("query", &self.query)
+            .required()
+            .max_chars(MAX_QUERY_CHARS);
+        if let Some(schema) = &self.response_schema {
+            v.nested("response_schema", schema.validate());
+        }
+        v.finish()
+    }
+}
+
+impl Validate for ResponseSchema {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut v = Validator::new();
+        v.field("name", &self.name)
+            .required()
+            .max_chars(MAX_SCHEMA_NAME_CHARS);
+        if !self
+            .name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            v.error(
+                "name",
+                "invalid_name",
+                "must contain only letters, digits, underscores and dashes".to_string(),
+            );
+        }
+        if self.schema.to_string().len() > MAX_SCHEMA_BYTES {
+            v.error(
+                "schema",
+                "too_large",
+                format!("must be at most {} bytes", MAX_SCHEMA_BYTES),
+            );
+        } else if let Err(e) = ReplySchema::new(self) {
+            v.error("schema", "invalid_schema", e);
+        }
+        v.finish()
+    }
+}
+
+impl Validate for TeamHuddle {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut v = Validator::new();
+        v.field("user_id", &self.user_id)
+            .required()
+            .max_chars(MAX_ID_CHARS);
+        v.field("query", &self.query)
+            .required()
+            .max_chars(MAX_QUERY_CHARS);
+        v.finish()
+    }
+}
+
+impl Validate for ActivateTask {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut v = Validator::new();
+        v.field("task_id", &self.task_id).uuid();
+        if let Some(parameters) = &self.parameters {
+            let size = parameters.to_string().len();
+            if size > MAX_TASK_PARAMETERS_BYTES {
+                v.error(
+                    "parameters",
+                    "too_large",
+                    format!("must be at most {} bytes", MAX_TASK_PARAMETERS_BYTES),
+                );
+            }
+        }
+        v.finish()
+    }
+}
+
+impl Validate for BroadcastNotification {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut v = Validator::new();
+        v.field("title", &self.title).max_chars(MAX_TITLE_CHARS);
+        v.field("message", &self.message)
+            .required()
+            .max_chars(MAX_MESSAGE_CHARS);
+        v.list("recipients", &self.recipients, MAX_RECIPIENTS, |user_id| {
+            user_id.required().max_chars(MAX_ID_CHARS);
+        });
+        if let Some(segment) = &self.segment {
+            if let (Some(min), Some(max)) = (segment.min_level, segment.max_level) {
+                if min > max {
+                    v.error(
+                        "segment.min_level",
+                        "out_of_range",
+                        "must not be greater than segment.max_level".to_string(),
+                    );
+                }
+            }
+            if segment.inactive_days.is_some_and(|days| days < 0) {
+                v.error(
+                    "segment.inactive_days",
+                    "out_of_range",
+                    "must not be negative".to_string(),
+                );
+            }
+        }
+        v.finish()
+    }
+}
+
+impl Validate for TaskProposal {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut v = Validator::new();
+        v.field("title", &self.title)
+            .required()
+            .max_chars(MAX_TASK_TITLE_CHARS);
+        if let Some(description) = &self.description {
+            v.field("description", description)
+                .max_chars(MAX_TASK_DESCRIPTION_CHARS);
+        }
+        v.field("priority", &self.priority).one_of(TASK_PRIORITIES);
+        v.range("duration", self.duration, 1, MAX_TASK_DURATION_MINUTES);
+        v.range("xp_reward", self.xp_reward, 0, MAX_TASK_XP_REWARD);
+        v.finish()
+    }
+}
+
+impl Validate for AcceptedTasks {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut v = Validator::new();
+        if self.tasks.is_empty() {
+            v.error("tasks", "required", "must not be empty".to_string());
+        } else if self.tasks.len() > MAX_PROPOSALS {
+            v.error(
+                "tasks",
+                "too_many",
+                format!("must have at most {} items", MAX_PROPOSALS),
+            );
+        }
+        for (i, task) in self.tasks.iter().enumerate() {
+            v.nested(&format!("tasks[{}]", i), task.validate());
+        }
+        v.finish()
+    }
+}
+
+impl Validate for NotificationPreferences {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut v = Validator::new();
+        v.list(
+            "channels",
+            &self.channels,
+            NOTIFICATION_CHANNELS.len(),
+            |channel| {
+                channel.one_of(NOTIFICATION_CHANNELS);
+            },
+        );
+        if let Some(email) = &self.email {
+            v.field("email", email).max_chars(MAX_EMAIL_CHARS).email();
+        }
+        // The server POSTs to these URLs, so they must not lead it to internal services.
+        if let Some(url) = &self.webhook_url {
+            v.field("webhook_url", url).max_chars(MAX_URL_CHARS);
+            if let Err(err) = check_destination(url) {
+                v.error("webhook_url", "forbidden_url", err);
+            }
+        }
+        if let Some(subscription) = &self.push_subscription {
+            v.field("push_subscription.endpoint", &subscription.endpoint)
+                .max_chars(MAX_URL_CHARS);
+            match check_destination(&subscription.endpoint) {
+                Ok(endpoint) if is_push_service(&endpoint) => {}
+                Ok(_) => v.error(
+                    "push_subscription.endpoint",
+                    "forbidden_url",
+                    "must be on a known push service".to_string(),
+                ),
+                Err(err) => v.error("push_subscription.endpoint", "forbidden_url", err),
+            }
+        }
+        // An enabled channel needs somewhere to deliver to.
+        for (channel, field, configured) in [
+            ("email", "email", self.email.is_some()),
+            (
+                "push",
+                "push_subscription",
+                self.push_subscription.is_some(),
+            ),
+            ("webhook", "webhook_url", self.webhook_url.is_some()),
+        ] {
+            if self.is_enabled(channel) && !configured {
+                v.error(
+                    field,
+                    "required",
+                    format!("must be set when the {} channel is enabled", channel),
+                );
+            }
+        }
+        v.finish()
+    }
+}
@@ -0,0 +1,58 @@
+//! Procuvita's coaching engine: AI coach actors, the services they rely on and the HTTP API.
+//!
+//! Embed it by building [`Services`], starting a [`Manager`] and either sending it messages
+//! directly or serving [`build_app`].
+
+pub mod actors;
+pub mod routes;
+pub mod services;
+
+pub use actors::manager::{ActorLimits, Manager};
+pub use actors::message;
+pub use actors::user_actor::UserActor;
+pub use routes::configure_routes;
+pub use services::{Backends, Services};
+
+use actix::{Actor, Addr};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::middleware::from_fn;
+use actix_web::{web, App, Error};
+use routes::rate_limit::limit_by_ip;
+use services::realtime::RealtimeListener;
+
+/// Starts the actor manager that owns every coach actor.
+pub fn start_manager(services: Services) -> Addr<Manager> {
+    Manager::new(services).start()
+}
+
+/// Starts listening for task and goal changes so coaches can react to them. Stays off,
+/// with a warning, when no database URL is configured.
+pub fn start_realtime(manager: Addr<Manager>) {
+    match RealtimeListener::from_env() {
+        Ok(listener) => {
+            actix::spawn(listener.run(manager));
+        }
+        Err(e) => println!("Warning: realtime updates disabled: {}", e),
+    }
+}
+
+/// Builds the application with all routes and middleware. Used by the server and by tests.
+pub fn build_app(
+    manager: web::Data<Addr<Manager>>,
+    services: web::Data<Services>,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = Error,
+        InitError = (),
+    >,
+> {
+    App::new()
+        .app_data(manager)
+        .app_data(services)
+        .wrap(from_fn(limit_by_ip))
+        .configure(configure_routes)
+}
@@ -1,21 +1,20 @@
-pub mod actors;
-pub mod routes;
-use actix::Actor;
-use actix_web::{web, App, HttpServer};
-use actors::manager::Manager;
-use routes::configure_routes;
+use actix_web::{web, HttpServer};
+use procuvita_backend::{build_app, start_manager, start_realtime, Services};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let manager = Manager::new().start();
+    let services = Services::from_env();
+    if let Some(db) = &services.db {
+        db.check_schema().await.map_err(std::io::Error::other)?;
+        println!("Database schema check passed");
+    }
+    let manager = start_manager(services.clone());
+    start_realtime(manager.clone());
     let manager_data = web::Data::new(manager);
+    let services_data = web::Data::new(services);
 
-    HttpServer::new(move || {
-        App::new()
-            .app_data(manager_data.clone())
-            .configure(configure_routes)
-    })
-    .bind("127.0.0.1:8080")?
-    .run()
-    .await
+    HttpServer::new(move || build_app(manager_data.clone(), services_data.clone()))
+        .bind("127.0.0.1:8080")?
+        .run()
+        .await
 }
@@ -1,3 +0,0 @@
-pub mod actors;
-pub mod routes;
-pub mod services;
@@ -0,0 +1,106 @@
+use crate::actors::manager::Manager;
+use crate::routes::auth::AuthenticatedUser;
+use crate::routes::rate_limit::rate_limited_response;
+use crate::services::audit::{record, AuditEvent, RequestOrigin};
+use crate::services::privacy::{erase_user, export_user};
+use crate::services::usage::{usage_report, UsageQuery};
+use crate::services::Services;
+use actix::Addr;
+use actix_web::{web, HttpResponse, Responder};
+use serde_json::json;
+
+/// Everything stored about the caller, as a JSON file download.
+pub async fn export_data(
+    manager: web::Data<Addr<Manager>>,
+    services: web::Data<Services>,
+    user: AuthenticatedUser,
+    origin: RequestOrigin,
+) -> impl Responder {
+    if let Err(retry_after) = services.user_limiter.check(&user.user_id) {
+        return rate_limited_response(retry_after);
+    }
+    let db = match services.user_db(&user.token) {
+        Ok(db) => db,
+        Err(err) => return HttpResponse::ServiceUnavailable().json(err),
+    };
+    match export_user(&services, db.as_ref(), &manager, &user.user_id).await {
+        Ok(archive) => {
+            record(
+                &services,
+                AuditEvent::user("user.export")
+                    .target("user", &user.user_id)
+                    .origin(&origin),
+            )
+            .await;
+            HttpResponse::Ok()
+                .insert_header((
+                    "Content-Disposition",
+                    format!(
+                        "attachment; filename=\"procuvita-export-{}.json\"",
+                        user.user_id
+                    ),
+                ))
+                .json(archive)
+        }
+        Err(err) => HttpResponse::InternalServerError().json(err),
+    }
+}
+
+/// Erases the caller's data everywhere it is stored and stops their actors.
+pub async fn delete_account(
+    manager: web::Data<Addr<Manager>>,
+    services: web::Data<Services>,
+    user: AuthenticatedUser,
+    origin: RequestOrigin,
+) -> impl Responder {
+    if services.db.is_none() {
+        return HttpResponse::ServiceUnavailable().json("Database is not configured");
+    }
+    match erase_user(&services, &manager, &user.user_id).await {
+        Ok(summary) => {
+            record(
+                &services,
+                AuditEvent::user("user.erase")
+                    .target("user", &user.user_id)
+                    .details(summary.clone())
+                    .origin(&origin),
+            )
+            .await;
+            HttpResponse::Ok().json(json!({
+                "message": "Account data erased",
+                "erased": summary
+            }))
+        }
+        Err(err) => HttpResponse::InternalServerError().json(err),
+    }
+}
+
+/// The caller's LLM usage and cost, summed per actor, model or day.
+pub async fn get_usage(
+    services: web::Data<Services>,
+    user: AuthenticatedUser,
+    query: web::Query<UsageQuery>,
+) -> impl Responder {
+    let mut query = query.into_inner();
+    if let Err(err) = query.groups() {
+        return HttpResponse::BadRequest().json(err);
+    }
+    query.user_id = Some(user.user_id.clone());
+    let db = match services.user_db(&user.token) {
+        Ok(db) => db,
+        Err(err) => return HttpResponse::ServiceUnavailable().json(err),
+    };
+    match usage_report(db.as_ref(), &query).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(err) => HttpResponse::InternalServerError().json(err),
+    }
+}
+
+pub fn configure_account_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/me")
+            .route("", web::delete().to(delete_account))
+            .route("/export", web::get().to(export_data))
+            .route("/usage", web::get().to(get_usage)),
+    );
+}
@@ -1,14 +1,38 @@
 use crate::actors::manager::Manager;
-use crate::actors::message::{CreateActor, ForwardToActor, GetActorCount};
+use crate::actors::message::{
+    ActorChanges, CreateActor, DeleteActor, FetchHistoricalInteractions, ForwardToActor,
+    GetActorCount, GetUserActor, ListUserActors, TeamHuddle, UpdateActor,
+};
+use crate::routes::auth::AuthenticatedUser;
+use crate::routes::rate_limit::{rate_limited_response, user_limit_response};
+use crate::routes::validation::invalid_payload_response;
+use crate::services::audit::{record, AuditEvent, RequestOrigin};
+use crate::services::Services;
 use actix::Addr;
 use actix_web::{web, HttpResponse, Responder};
+use serde::Deserialize;
 use serde_json::json;
 
 pub async fn create_actor(
     manager: web::Data<Addr<Manager>>,
+    services: web::Data<Services>,
+    user: AuthenticatedUser,
+    origin: RequestOrigin,
     payload: web::Json<CreateActor>,
 ) -> impl Responder {
-    let create_msg = payload.into_inner();
+    let create_msg = CreateActor {
+        user_id: user.user_id,
+        ..payload.into_inner()
+    };
+    if let Some(response) = invalid_payload_response(&create_msg) {
+        return response;
+    }
+    if let Err(retry_after) = services.user_limiter.check(&create_msg.user_id) {
+        return rate_limited_response(retry_after);
+    }
+    let event = AuditEvent::user("actor.create")
+        .details(json!({ "name": create_msg.name, "personality": create_msg.personality }))
+        .origin(&origin);
 
     let result = manager
         .send(create_msg)
@@ -16,19 +40,34 @@ pub async fn create_actor(
         .unwrap_or_else(|_| Err("Failed to create actor".to_string()));
 
     match result {
-        Ok(actor_id) => HttpResponse::Ok().json(json!({
-            "message": "Actor created successfully",
-            "actor_id": actor_id
-        })),
+        Ok(actor_id) => {
+            record(&services, event.target("actor", &actor_id.to_string())).await;
+            HttpResponse::Ok().json(json!({
+                "message": "Actor created successfully",
+                "actor_id": actor_id
+            }))
+        }
         Err(err) => HttpResponse::BadRequest().json(err),
     }
 }
 
 pub async fn interact_with_actor(
     manager: web::Data<Addr<Manager>>,
+    services: web::Data<Services>,
+    user: AuthenticatedUser,
     payload: web::Json<ForwardToActor>,
 ) -> impl Responder {
-    let forward_msg = payload.into_inner();
+    let forward_msg = ForwardToActor {
+        user_id: user.user_id,
+        access_token: Some(user.token),
+        ..payload.into_inner()
+    };
+    if let Some(response) = invalid_payload_response(&forward_msg) {
+        return response;
+    }
+    if let Some(response) = user_limit_response(&services, &forward_msg.user_id).await {
+        return response;
+    }
 
     let result = manager
         .send(forward_msg)
@@ -41,6 +80,285 @@ pub async fn interact_with_actor(
     }
 }
 
+#[derive(Deserialize)]
+pub struct HuddleRequest {
+    pub query: String,
+}
+
+/// Asks all of the caller's actors the same question and merges their answers.
+pub async fn team_huddle(
+    manager: web::Data<Addr<Manager>>,
+    services: web::Data<Services>,
+    user: AuthenticatedUser,
+    payload: web::Json<HuddleRequest>,
+) -> impl Responder {
+    let huddle_msg = TeamHuddle {
+        user_id: user.user_id,
+        query: payload.into_inner().query,
+    };
+    if let Some(response) = invalid_payload_response(&huddle_msg) {
+        return response;
+    }
+    if let Some(response) = user_limit_response(&services, &huddle_msg.user_id).await {
+        return response;
+    }
+
+    let result = manager
+        .send(huddle_msg)
+        .await
+        .unwrap_or_else(|_| Err("Failed to run team huddle".to_string()));
+
+    match result {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(err) => HttpResponse::BadRequest().json(err),
+    }
+}
+
+/// Recent exchanges with one of the caller's actors. Other users' actors are not found.
+pub async fn actor_history(
+    manager: web::Data<Addr<Manager>>,
+    user: AuthenticatedUser,
+    path: web::Path<String>,
+) -> impl Responder {
+    let result = manager
+        .send(FetchHistoricalInteractions {
+            user_id: user.user_id,
+            actor_id: path.into_inner(),
+        })
+        .await
+        .unwrap_or_else(|_| Err("Failed to fetch actor history".to_string()));
+
+    match result {
+        Ok(history) => HttpResponse::Ok().json(json!({ "interactions": history })),
+        Err(err) => HttpResponse::NotFound().json(err),
+    }
+}
+
+pub async fn list_templates(services: web::Data<Services>) -> impl Responder {
+    let Some(db) = services.db.clone() else {
+        return HttpResponse::ServiceUnavailable().json("Database is not configured");
+    };
+    match db.list_actor_templates().await {
+        Ok(templates) => HttpResponse::Ok().json(json!({ "templates": templates })),
+        Err(err) => HttpResponse::InternalServerError().json(err),
+    }
+}
+
+/// Template fields the caller wants to replace in their copy.
+#[derive(Deserialize, Default)]
+pub struct TemplateOverrides {
+    pub name: Option<String>,
+    pub personality: Option<String>,
+    pub goals: Option<Vec<String>>,
+    pub knowledge_base: Option<String>,
+}
+
+/// Creates a personal actor for the caller from one of the seeded `ai_agents` rows.
+pub async fn create_from_template(
+    manager: web::Data<Addr<Manager>>,
+    services: web::Data<Services>,
+    user: AuthenticatedUser,
+    origin: RequestOrigin,
+    path: web::Path<String>,
+    payload: Option<web::Json<TemplateOverrides>>,
+) -> impl Responder {
+    if let Err(retry_after) = services.user_limiter.check(&user.user_id) {
+        return rate_limited_response(retry_after);
+    }
+    let db = match services.user_db(&user.token) {
+        Ok(db) => db,
+        Err(err) => return HttpResponse::ServiceUnavailable().json(err),
+    };
+    let agent_id = path.into_inner();
+    let template = match db.get_actor_template(&agent_id).await {
+        Ok(Some(template)) => template,
+        Ok(None) => return HttpResponse::NotFound().json(format!("No template {}", agent_id)),
+        Err(err) => return HttpResponse::InternalServerError().json(err),
+    };
+
+    let overrides = payload.map(|p| p.into_inner()).unwrap_or_default();
+    let text = |field: &str| template[field].as_str().unwrap_or_default().to_string();
+    let create_msg = CreateActor {
+        user_id: user.user_id,
+        name: overrides.name.unwrap_or_else(|| text("name")),
+        personality: overrides.personality.unwrap_or_else(|| text("personality")),
+        expertise: text("specialty"),
+        goals: overrides.goals.unwrap_or_else(|| {
+            serde_json::from_value(template["goals"].clone()).unwrap_or_default()
+        }),
+        knowledge_base: overrides
+            .knowledge_base
+            .unwrap_or_else(|| text("knowledge_base")),
+        picture_url: template["avatar_url"].as_str().map(str::to_string),
+    };
+    if let Some(response) = invalid_payload_response(&create_msg) {
+        return response;
+    }
+    let event = AuditEvent::user("actor.create")
+        .details(json!({
+            "name": create_msg.name,
+            "personality": create_msg.personality,
+            "template_id": agent_id,
+        }))
+        .origin(&origin);
+
+    let result = manager
+        .send(create_msg)
+        .await
+        .unwrap_or_else(|_| Err("Failed to create actor".to_string()));
+    match result {
+        Ok(actor_id) => {
+            record(&services, event.target("actor", &actor_id.to_string())).await;
+            HttpResponse::Ok().json(json!({
+                "message": "Actor created successfully",
+                "actor_id": actor_id,
+                "template_id": agent_id
+            }))
+        }
+        Err(err) => HttpResponse::BadRequest().json(err),
+    }
+}
+
+/// The caller's actors with their full profiles.
+pub async fn list_user_actors(
+    manager: web::Data<Addr<Manager>>,
+    user: AuthenticatedUser,
+) -> impl Responder {
+    match manager
+        .send(ListUserActors {
+            user_id: user.user_id,
+        })
+        .await
+    {
+        Ok(mut actors) => {
+            actors.sort_by(|a, b| a.name.cmp(&b.name));
+            HttpResponse::Ok().json(json!({ "actors": actors }))
+        }
+        Err(_) => HttpResponse::InternalServerError().json("Failed to list actors"),
+    }
+}
+
+pub async fn get_actor(
+    manager: web::Data<Addr<Manager>>,
+    user: AuthenticatedUser,
+    path: web::Path<String>,
+) -> impl Responder {
+    let result = manager
+        .send(GetUserActor {
+            user_id: user.user_id,
+            actor_id: path.into_inner(),
+        })
+        .await
+        .unwrap_or_else(|_| Err("Failed to fetch actor".to_string()));
+
+    match result {
+        Ok(profile) => HttpResponse::Ok().json(profile),
+        Err(err) => HttpResponse::NotFound().json(err),
+    }
+}
+
+pub async fn update_actor(
+    manager: web::Data<Addr<Manager>>,
+    services: web::Data<Services>,
+    user: AuthenticatedUser,
+    origin: RequestOrigin,
+    path: web::Path<String>,
+    payload: web::Json<ActorChanges>,
+) -> impl Responder {
+    let changes = payload.into_inner();
+    if let Some(response) = invalid_payload_response(&changes) {
+        return response;
+    }
+    let actor_id = path.into_inner();
+    // The current profile, so a personality change can be recorded with what it replaced.
+    let before = manager
+        .send(GetUserActor {
+            user_id: user.user_id.clone(),
+            actor_id: actor_id.clone(),
+        })
+        .await
+        .ok()
+        .and_then(Result::ok);
+    let changed = serde_json::to_value(&changes).unwrap_or_default();
+    let result = manager
+        .send(UpdateActor {
+            user_id: user.user_id,
+            actor_id: actor_id.clone(),
+            changes,
+        })
+        .await
+        .unwrap_or_else(|_| Err("Failed to update actor".to_string()));
+
+    match result {
+        Ok(profile) => {
+            let fields: Vec<&String> = changed
+                .as_object()
+                .map(|changed| {
+                    changed
+                        .iter()
+                        .filter(|(_, value)| !value.is_null())
+                        .map(|(field, _)| field)
+                        .collect()
+                })
+                .unwrap_or_default();
+            record(
+                &services,
+                AuditEvent::user("actor.update")
+                    .target("actor", &actor_id)
+                    .details(json!({ "fields": fields }))
+                    .origin(&origin),
+            )
+            .await;
+            if let Some(before) = before.filter(|b| b.personality != profile.personality) {
+                record(
+                    &services,
+                    AuditEvent::user("actor.personality_change")
+                        .target("actor", &actor_id)
+                        .details(json!({ "from": before.personality, "to": profile.personality }))
+                        .origin(&origin),
+                )
+                .await;
+            }
+            HttpResponse::Ok().json(profile)
+        }
+        Err(err) => HttpResponse::BadRequest().json(err),
+    }
+}
+
+pub async fn delete_actor(
+    manager: web::Data<Addr<Manager>>,
+    services: web::Data<Services>,
+    user: AuthenticatedUser,
+    origin: RequestOrigin,
+    path: web::Path<String>,
+) -> impl Responder {
+    let actor_id = path.into_inner();
+    let result = manager
+        .send(DeleteActor {
+            user_id: user.user_id,
+            actor_id: actor_id.clone(),
+        })
+        .await
+        .unwrap_or_else(|_| Err("Failed to delete actor".to_string()));
+
+    match result {
+        Ok(()) => {
+            record(
+                &services,
+                AuditEvent::user("actor.delete")
+                    .target("actor", &actor_id)
+                    .origin(&origin),
+            )
+            .await;
+            HttpResponse::Ok().json(json!({
+                "message": "Actor deleted successfully",
+                "actor_id": actor_id
+            }))
+        }
+        Err(err) => HttpResponse::BadRequest().json(err),
+    }
+}
+
 pub async fn list_actors(manager: web::Data<Addr<Manager>>) -> impl Responder {
     match manager.send(GetActorCount).await {
         Ok(count) => HttpResponse::Ok().json(format!("Active actors: {}", count)),
@@ -51,8 +369,19 @@ pub async fn list_actors(manager: web::Data<Addr<Manager>>) -> impl Responder {
 pub fn configure_actor_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/actors")
+            .route("", web::get().to(list_user_actors))
             .route("/create", web::post().to(create_actor))
+            .route("/templates", web::get().to(list_templates))
+            .route(
+                "/from-template/{agent_id}",
+                web::post().to(create_from_template),
+            )
             .route("/interact", web::post().to(interact_with_actor))
-            .route("/list", web::get().to(list_actors)),
+            .route("/huddle", web::post().to(team_huddle))
+            .route("/list", web::get().to(list_actors))
+            .route("/{actor_id}", web::get().to(get_actor))
+            .route("/{actor_id}", web::patch().to(update_actor))
+            .route("/{actor_id}", web::delete().to(delete_actor))
+            .route("/{actor_id}/history", web::get().to(actor_history)),
     );
 }
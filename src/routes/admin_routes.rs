@@ -1,26 +1,100 @@
 use crate::actors::manager::Manager;
-use crate::actors::message::{BroadcastNotification, GetActorCount, QueryActorState};
+use crate::actors::message::{BroadcastNotification, InspectActor, ListActors, QueryActorState};
+use crate::routes::auth::{request_origin, AuthenticatedUser};
+use crate::routes::validation::invalid_payload_response;
+use crate::services::audit::{record, AuditEvent, AuditQuery, RequestOrigin};
+use crate::services::database::Database;
+use crate::services::usage::{usage_report, UsageQuery};
+use crate::services::Services;
 use actix::Addr;
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::body::MessageBody;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::middleware::{from_fn, Next};
+use actix_web::{web, Error, FromRequest, HttpResponse, Responder};
+use serde::Deserialize;
+use serde_json::json;
 
-pub async fn list_all_actors(manager: web::Data<Addr<Manager>>) -> impl Responder {
-    let result = manager.send(GetActorCount).await;
+const DEFAULT_PAGE_SIZE: usize = 20;
+const MAX_PAGE_SIZE: usize = 100;
+
+#[derive(Deserialize)]
+pub struct ActorFilter {
+    pub user_id: Option<String>,
+    pub expertise: Option<String>,
+    pub personality: Option<String>,
+    pub page: Option<usize>,
+    pub per_page: Option<usize>,
+}
+
+pub async fn list_all_actors(
+    manager: web::Data<Addr<Manager>>,
+    query: web::Query<ActorFilter>,
+) -> impl Responder {
+    let filter = query.into_inner();
+    let result = manager
+        .send(ListActors {
+            user_id: filter.user_id,
+            expertise: filter.expertise,
+            personality: filter.personality,
+            page: filter.page.unwrap_or(1).max(1),
+            per_page: filter
+                .per_page
+                .unwrap_or(DEFAULT_PAGE_SIZE)
+                .clamp(1, MAX_PAGE_SIZE),
+        })
+        .await;
+    match result {
+        Ok(page) => HttpResponse::Ok().json(page),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to list actors"),
+    }
+}
+
+pub async fn inspect_actor(
+    manager: web::Data<Addr<Manager>>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let result = manager
+        .send(InspectActor {
+            actor_id: path.into_inner(),
+        })
+        .await
+        .unwrap_or_else(|_| Err("Failed to inspect actor".to_string()));
     match result {
-        Ok(count) => HttpResponse::Ok().json(format!("Total active actors: {}", count)),
-        Err(_) => HttpResponse::InternalServerError().json("Failed to fetch actor count"),
+        Ok(details) => HttpResponse::Ok().json(details),
+        Err(err) => HttpResponse::NotFound().json(err),
     }
 }
 
 pub async fn broadcast_message(
     manager: web::Data<Addr<Manager>>,
+    services: web::Data<Services>,
+    origin: RequestOrigin,
     payload: web::Json<BroadcastNotification>,
 ) -> impl Responder {
     let message = payload.into_inner();
+    if let Some(response) = invalid_payload_response(&message) {
+        return response;
+    }
+    let details = json!({ "title": message.title, "segment": message.segment });
 
-    let result = manager.send(message).await;
+    let result = manager
+        .send(message)
+        .await
+        .unwrap_or_else(|_| Err("Failed to send broadcast message".to_string()));
     match result {
-        Ok(_) => HttpResponse::Ok().json("Broadcast message sent successfully"),
-        Err(_) => HttpResponse::InternalServerError().json("Failed to send broadcast message"),
+        Ok(report) => {
+            let mut details = details;
+            details["report"] = json!(report);
+            record(
+                &services,
+                AuditEvent::admin("admin.broadcast")
+                    .details(details)
+                    .origin(&origin),
+            )
+            .await;
+            HttpResponse::Ok().json(report)
+        }
+        Err(err) => HttpResponse::InternalServerError().json(err),
     }
 }
 
@@ -37,11 +111,159 @@ pub async fn query_actor_state(
     }
 }
 
+/// The user's quota status, or the error response when it can't be read.
+async fn quota_status_response(
+    services: &Services,
+    db: &dyn Database,
+    user_id: &str,
+) -> HttpResponse {
+    match services.quota.status(db, user_id).await {
+        Ok(status) => HttpResponse::Ok().json(status),
+        Err(err) => HttpResponse::InternalServerError().json(err),
+    }
+}
+
+pub async fn get_user_quota(
+    services: web::Data<Services>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let Some(db) = services.db.clone() else {
+        return HttpResponse::ServiceUnavailable().json("Database is not configured");
+    };
+    quota_status_response(&services, db.as_ref(), &path.into_inner()).await
+}
+
+pub async fn reset_user_quota(
+    services: web::Data<Services>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let Some(db) = services.db.clone() else {
+        return HttpResponse::ServiceUnavailable().json("Database is not configured");
+    };
+    let user_id = path.into_inner();
+    if let Err(err) = services.quota.reset(db.as_ref(), &user_id).await {
+        return HttpResponse::InternalServerError().json(err);
+    }
+    quota_status_response(&services, db.as_ref(), &user_id).await
+}
+
+#[derive(Deserialize)]
+pub struct SetPlan {
+    pub plan: String,
+}
+
+pub async fn set_user_plan(
+    services: web::Data<Services>,
+    path: web::Path<String>,
+    payload: web::Json<SetPlan>,
+) -> impl Responder {
+    let Some(db) = services.db.clone() else {
+        return HttpResponse::ServiceUnavailable().json("Database is not configured");
+    };
+    if !services.quota.has_plan(&payload.plan) {
+        return HttpResponse::BadRequest().json(format!("Unknown plan: {}", payload.plan));
+    }
+    let user_id = path.into_inner();
+    match services
+        .quota
+        .set_plan(db.as_ref(), &user_id, &payload.plan)
+        .await
+    {
+        Ok(()) => quota_status_response(&services, db.as_ref(), &user_id).await,
+        Err(err) => HttpResponse::InternalServerError().json(err),
+    }
+}
+
+/// Audit log entries matching the query's filters, newest first.
+pub async fn list_audit_entries(
+    services: web::Data<Services>,
+    query: web::Query<AuditQuery>,
+) -> impl Responder {
+    let Some(db) = services.db.clone() else {
+        return HttpResponse::ServiceUnavailable().json("Database is not configured");
+    };
+    match db.list_audit_entries(&query).await {
+        Ok(entries) => HttpResponse::Ok().json(json!({ "entries": entries })),
+        Err(err) => HttpResponse::InternalServerError().json(err),
+    }
+}
+
+/// LLM usage and cost summed per user, actor, model or day, for every user.
+pub async fn get_usage_report(
+    services: web::Data<Services>,
+    query: web::Query<UsageQuery>,
+) -> impl Responder {
+    if let Err(err) = query.groups() {
+        return HttpResponse::BadRequest().json(err);
+    }
+    let Some(db) = services.db.clone() else {
+        return HttpResponse::ServiceUnavailable().json("Database is not configured");
+    };
+    match usage_report(db.as_ref(), &query).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(err) => HttpResponse::InternalServerError().json(err),
+    }
+}
+
+/// Lets only admins through: 401 without a valid access token, 403 for anyone else.
+pub async fn require_admin(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let refusal = match AuthenticatedUser::from_request(req.request(), &mut Payload::None).await {
+        Ok(user) if user.admin => None,
+        Ok(_) => Some(HttpResponse::Forbidden().json("Admin access required")),
+        Err(err) => Some(HttpResponse::Unauthorized().json(err.to_string())),
+    };
+    if let Some(response) = refusal {
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+    next.call(req).await.map(|res| res.map_into_left_body())
+}
+
+/// Records every request to an admin route in the audit log, once it has been answered.
+pub async fn audit_admin_request(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let origin = request_origin(req.request());
+    let services = req.app_data::<web::Data<Services>>().cloned();
+    let res = next.call(req).await?;
+
+    if let Some(services) = services {
+        let request = res.request();
+        let mut event = AuditEvent::admin("admin.request")
+            .details(json!({
+                "method": request.method().as_str(),
+                "route": request.match_pattern(),
+                "path": request.path(),
+                "query": request.query_string(),
+                "status": res.status().as_u16(),
+            }))
+            .origin(&origin);
+        for (param, target_type) in [("actor_id", "actor"), ("user_id", "user")] {
+            if let Some(id) = request.match_info().get(param) {
+                event = event.target(target_type, id);
+            }
+        }
+        record(&services, event).await;
+    }
+    Ok(res)
+}
+
 pub fn configure_admin_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/admin")
+            .wrap(from_fn(audit_admin_request))
+            .wrap(from_fn(require_admin))
+            .route("/audit", web::get().to(list_audit_entries))
+            .route("/usage", web::get().to(get_usage_report))
             .route("/actors", web::get().to(list_all_actors))
+            .route("/actors/{actor_id}", web::get().to(inspect_actor))
             .route("/broadcast", web::post().to(broadcast_message))
-            .route("/query", web::post().to(query_actor_state)),
+            .route("/query", web::post().to(query_actor_state))
+            .route("/quota/{user_id}", web::get().to(get_user_quota))
+            .route("/quota/{user_id}/reset", web::post().to(reset_user_quota))
+            .route("/quota/{user_id}/plan", web::put().to(set_user_plan)),
     );
 }
@@ -0,0 +1,78 @@
+use crate::services::audit::RequestOrigin;
+use crate::services::jwt;
+use actix_web::dev::Payload;
+use actix_web::error::ErrorUnauthorized;
+use actix_web::{Error, FromRequest, HttpRequest};
+use std::future::{ready, Ready};
+
+/// The caller, identified by the Supabase access token in the `Authorization` header.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub user_id: String,
+    pub token: String,
+    pub admin: bool, // `app_metadata.role` is "admin", which only the service role can set
+}
+
+impl AuthenticatedUser {
+    /// Verifies a Supabase JWT against `SUPABASE_JWT_SECRET`.
+    pub fn from_token(token: &str) -> Result<Self, String> {
+        let claims = jwt::verify(token)?;
+        let user_id = claims["sub"]
+            .as_str()
+            .ok_or_else(|| "Invalid access token: missing sub claim".to_string())?;
+
+        Ok(AuthenticatedUser {
+            user_id: user_id.to_string(),
+            token: token.to_string(),
+            admin: claims["app_metadata"]["role"] == "admin",
+        })
+    }
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let token = req
+            .headers()
+            .get("Authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+
+        ready(match token {
+            Some(token) => AuthenticatedUser::from_token(token.trim()).map_err(ErrorUnauthorized),
+            None => Err(ErrorUnauthorized("Missing bearer token")),
+        })
+    }
+}
+
+/// Reads who sent the request and from where. Never rejects: an invalid or missing token
+/// just leaves `user_id` unset.
+pub fn request_origin(req: &HttpRequest) -> RequestOrigin {
+    let user_id = req
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .and_then(|token| AuthenticatedUser::from_token(token.trim()).ok())
+        .map(|user| user.user_id);
+    RequestOrigin {
+        user_id,
+        ip: req.peer_addr().map(|addr| addr.ip().to_string()),
+        user_agent: req
+            .headers()
+            .get("User-Agent")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+    }
+}
+
+impl FromRequest for RequestOrigin {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(request_origin(req)))
+    }
+}
@@ -0,0 +1,100 @@
+use crate::actors::manager::Manager;
+use crate::actors::message::{ForwardToActor, ListUserActors};
+use crate::actors::validation::{Validate, Validator, MAX_QUERY_CHARS};
+use crate::routes::auth::AuthenticatedUser;
+use crate::routes::rate_limit::user_limit_response;
+use crate::routes::validation::validation_failed_response;
+use crate::services::structured::ResponseSchema;
+use crate::services::usage::{meter, store_calls};
+use crate::services::Services;
+use actix::Addr;
+use actix_web::{web, HttpResponse, Responder};
+use serde::Deserialize;
+use serde_json::json;
+
+#[derive(Deserialize)]
+pub struct ChatMessage {
+    pub message: String,
+    #[serde(default)]
+    pub response_schema: Option<ResponseSchema>,
+}
+
+/// Routes a message to whichever of the caller's actors is best suited to answer it.
+pub async fn chat(
+    manager: web::Data<Addr<Manager>>,
+    services: web::Data<Services>,
+    user: AuthenticatedUser,
+    payload: web::Json<ChatMessage>,
+) -> impl Responder {
+    let ChatMessage {
+        message,
+        response_schema,
+    } = payload.into_inner();
+    let mut v = Validator::new();
+    v.field("message", &message)
+        .required()
+        .max_chars(MAX_QUERY_CHARS);
+    if let Some(schema) = &response_schema {
+        v.nested("response_schema", schema.validate());
+    }
+    if let Err(errors) = v.finish() {
+        return validation_failed_response(errors);
+    }
+    if let Some(response) = user_limit_response(&services, &user.user_id).await {
+        return response;
+    }
+
+    let candidates = match manager
+        .send(ListUserActors {
+            user_id: user.user_id.clone(),
+        })
+        .await
+    {
+        Ok(candidates) => candidates,
+        Err(_) => return HttpResponse::InternalServerError().json("Failed to list actors"),
+    };
+
+    let decision = match services
+        .router
+        .route(
+            services.llm.as_ref(),
+            services.embeddings.as_ref(),
+            &message,
+            &candidates,
+        )
+        .await
+    {
+        Ok(decision) => decision,
+        Err(err) => return HttpResponse::BadRequest().json(err),
+    };
+    if let Some(completion) = &decision.completion {
+        let call = meter(&services, completion);
+        store_calls(&services, &user.user_id, None, None, "routing", &[call]).await;
+    }
+
+    let result = manager
+        .send(ForwardToActor {
+            user_id: user.user_id,
+            actor_id: decision.actor_id.to_string(),
+            query: message,
+            access_token: Some(user.token),
+            response_schema,
+        })
+        .await
+        .unwrap_or_else(|_| Err("Failed to interact with actor".to_string()));
+
+    match result {
+        Ok(reply) => HttpResponse::Ok().json(json!({
+            "actor_id": decision.actor_id,
+            "actor_name": decision.actor_name,
+            "confidence": decision.confidence,
+            "routing_method": decision.method,
+            "reply": reply
+        })),
+        Err(err) => HttpResponse::BadRequest().json(err),
+    }
+}
+
+pub fn configure_chat_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/chat", web::post().to(chat));
+}
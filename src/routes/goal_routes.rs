@@ -0,0 +1,146 @@
+use crate::actors::manager::Manager;
+use crate::actors::message::{AcceptedTasks, PlanGoal};
+use crate::actors::validation::{Validator, MAX_QUERY_CHARS};
+use crate::routes::auth::AuthenticatedUser;
+use crate::routes::rate_limit::{rate_limited_response, user_limit_response};
+use crate::routes::validation::{invalid_payload_response, validation_failed_response};
+use crate::services::database::Database;
+use crate::services::Services;
+use actix::Addr;
+use actix_web::{web, HttpResponse, Responder};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// What the caller wants from a plan, e.g. "I only have evenings free".
+#[derive(Deserialize, Default)]
+pub struct PlanRequest {
+    pub instructions: Option<String>,
+}
+
+/// Fetches the goal, answering 404 unless it belongs to the caller.
+async fn owned_goal(
+    db: &dyn Database,
+    user_id: &str,
+    goal_id: &str,
+) -> Result<Value, HttpResponse> {
+    match db.get_goal(goal_id).await {
+        Ok(Some(goal)) if goal["user_id"] == user_id => Ok(goal),
+        Ok(_) => Err(HttpResponse::NotFound().json(format!("No goal {}", goal_id))),
+        Err(err) => Err(HttpResponse::InternalServerError().json(err)),
+    }
+}
+
+/// Asks the goal's coach to propose tasks for it. Nothing is saved until the caller accepts
+/// some of them with `POST /goals/{goal_id}/plan/accept`.
+pub async fn plan_goal(
+    manager: web::Data<Addr<Manager>>,
+    services: web::Data<Services>,
+    user: AuthenticatedUser,
+    path: web::Path<String>,
+    payload: Option<web::Json<PlanRequest>>,
+) -> impl Responder {
+    let instructions = payload.and_then(|p| p.into_inner().instructions);
+    if let Some(instructions) = &instructions {
+        let mut v = Validator::new();
+        v.field("instructions", instructions)
+            .max_chars(MAX_QUERY_CHARS);
+        if let Err(errors) = v.finish() {
+            return validation_failed_response(errors);
+        }
+    }
+    if let Some(response) = user_limit_response(&services, &user.user_id).await {
+        return response;
+    }
+    let db = match services.user_db(&user.token) {
+        Ok(db) => db,
+        Err(err) => return HttpResponse::ServiceUnavailable().json(err),
+    };
+    let goal_id = path.into_inner();
+    let goal = match owned_goal(db.as_ref(), &user.user_id, &goal_id).await {
+        Ok(goal) => goal,
+        Err(response) => return response,
+    };
+    let tasks = match db.list_tasks(&goal_id).await {
+        Ok(tasks) => tasks,
+        Err(err) => return HttpResponse::InternalServerError().json(err),
+    };
+
+    let result = manager
+        .send(PlanGoal {
+            user_id: user.user_id,
+            goal,
+            tasks,
+            instructions,
+        })
+        .await
+        .unwrap_or_else(|_| Err("Failed to plan goal".to_string()));
+    match result {
+        Ok(plan) => HttpResponse::Ok().json(json!({
+            "goal_id": goal_id,
+            "actor_id": plan.actor_id,
+            "actor_name": plan.actor_name,
+            "proposals": plan.tasks,
+            "provider": plan.provider,
+            "model": plan.model,
+        })),
+        Err(err) => HttpResponse::BadGateway().json(err),
+    }
+}
+
+/// Inserts the proposals the caller kept, as they were proposed or edited. They're added
+/// together, so a failure leaves the goal as it was.
+pub async fn accept_plan(
+    services: web::Data<Services>,
+    user: AuthenticatedUser,
+    path: web::Path<String>,
+    payload: web::Json<AcceptedTasks>,
+) -> impl Responder {
+    let accepted = payload.into_inner();
+    if let Some(response) = invalid_payload_response(&accepted) {
+        return response;
+    }
+    if let Err(retry_after) = services.user_limiter.check(&user.user_id) {
+        return rate_limited_response(retry_after);
+    }
+    let db = match services.user_db(&user.token) {
+        Ok(db) => db,
+        Err(err) => return HttpResponse::ServiceUnavailable().json(err),
+    };
+    let goal_id = path.into_inner();
+    if let Err(response) = owned_goal(db.as_ref(), &user.user_id, &goal_id).await {
+        return response;
+    }
+
+    let rows = accepted
+        .tasks
+        .into_iter()
+        .map(|task| {
+            json!({
+                "goal_id": goal_id,
+                "title": task.title,
+                "description": task.description,
+                "priority": task.priority,
+                "duration": task.duration,
+                "xp_reward": task.xp_reward,
+                "ai_assignable": task.ai_assignable,
+                "status": "pending",
+            })
+        })
+        .collect();
+    match db.add_tasks(rows).await {
+        Ok(task_ids) => HttpResponse::Ok().json(json!({
+            "message": "Tasks added to goal",
+            "goal_id": goal_id,
+            "task_ids": task_ids
+        })),
+        Err(err) => HttpResponse::InternalServerError().json(err),
+    }
+}
+
+pub fn configure_goal_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/goals")
+            .route("/{goal_id}/plan", web::post().to(plan_goal))
+            .route("/{goal_id}/plan/accept", web::post().to(accept_plan)),
+    );
+}
@@ -0,0 +1,215 @@
+use crate::actors::manager::Manager;
+use crate::actors::message::GetUserActor;
+use crate::routes::auth::AuthenticatedUser;
+use crate::routes::rate_limit::rate_limited_response;
+use crate::services::knowledge::{self, extract_text, DocumentFormat, NewDocument};
+use crate::services::Services;
+use actix::Addr;
+use actix_web::http::header::CONTENT_TYPE;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// Largest document body accepted, in bytes.
+const MAX_DOCUMENT_BYTES: usize = 10 * 1024 * 1024;
+
+/// Upload options. `format` overrides the `Content-Type` header.
+#[derive(Deserialize)]
+pub struct DocumentQuery {
+    pub title: Option<String>,
+    pub format: Option<String>,
+}
+
+/// Confirms the actor belongs to the caller.
+async fn check_owner(
+    manager: &Addr<Manager>,
+    user_id: &str,
+    actor_id: &str,
+) -> Result<(), HttpResponse> {
+    manager
+        .send(GetUserActor {
+            user_id: user_id.to_string(),
+            actor_id: actor_id.to_string(),
+        })
+        .await
+        .unwrap_or_else(|_| Err("Failed to fetch actor".to_string()))
+        .map(|_| ())
+        .map_err(|err| HttpResponse::NotFound().json(err))
+}
+
+/// Converts an uploaded body to text and stores it, replacing `existing` when given.
+async fn store_document(
+    services: &Services,
+    req: &HttpRequest,
+    user: AuthenticatedUser,
+    actor_id: String,
+    query: DocumentQuery,
+    body: web::Bytes,
+    existing: Option<Value>,
+) -> HttpResponse {
+    if let Err(retry_after) = services.user_limiter.check(&user.user_id) {
+        return rate_limited_response(retry_after);
+    }
+    let db = match services.user_db(&user.token) {
+        Ok(db) => db,
+        Err(err) => return HttpResponse::ServiceUnavailable().json(err),
+    };
+
+    let format = match &query.format {
+        Some(name) => DocumentFormat::parse(name),
+        None => req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(DocumentFormat::from_content_type),
+    };
+    let Some(format) = format else {
+        return HttpResponse::UnsupportedMediaType()
+            .json("Documents must be plain text, Markdown, HTML or PDF");
+    };
+    let text = match extract_text(format, &body) {
+        Ok(text) => text,
+        Err(err) => return HttpResponse::UnprocessableEntity().json(err),
+    };
+
+    let title = query
+        .title
+        .filter(|title| !title.trim().is_empty())
+        .or_else(|| {
+            existing
+                .as_ref()
+                .and_then(|row| row["title"].as_str().map(str::to_string))
+        })
+        .unwrap_or_else(|| "Untitled document".to_string());
+    let document = NewDocument {
+        actor_id,
+        user_id: user.user_id,
+        title,
+        format,
+        size_bytes: body.len(),
+        text,
+    };
+
+    match knowledge::ingest_document(services, db.as_ref(), document, existing.as_ref()).await {
+        Ok(row) => HttpResponse::Ok().json(row),
+        Err(err) => HttpResponse::InternalServerError().json(err),
+    }
+}
+
+/// Adds a document to the actor's knowledge. The body is the raw document.
+pub async fn upload_document(
+    manager: web::Data<Addr<Manager>>,
+    services: web::Data<Services>,
+    user: AuthenticatedUser,
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<DocumentQuery>,
+    body: web::Bytes,
+) -> impl Responder {
+    let actor_id = path.into_inner();
+    if let Err(response) = check_owner(&manager, &user.user_id, &actor_id).await {
+        return response;
+    }
+    store_document(
+        &services,
+        &req,
+        user,
+        actor_id,
+        query.into_inner(),
+        body,
+        None,
+    )
+    .await
+}
+
+/// Replaces a document's contents, keeping its id and, unless a new one is given, its title.
+pub async fn replace_document(
+    manager: web::Data<Addr<Manager>>,
+    services: web::Data<Services>,
+    user: AuthenticatedUser,
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    query: web::Query<DocumentQuery>,
+    body: web::Bytes,
+) -> impl Responder {
+    let (actor_id, document_id) = path.into_inner();
+    if let Err(response) = check_owner(&manager, &user.user_id, &actor_id).await {
+        return response;
+    }
+    let db = match services.user_db(&user.token) {
+        Ok(db) => db,
+        Err(err) => return HttpResponse::ServiceUnavailable().json(err),
+    };
+    let existing = match db.get_knowledge_document(&actor_id, &document_id).await {
+        Ok(Some(row)) => row,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(format!("Document {} not found", document_id))
+        }
+        Err(err) => return HttpResponse::InternalServerError().json(err),
+    };
+    store_document(
+        &services,
+        &req,
+        user,
+        actor_id,
+        query.into_inner(),
+        body,
+        Some(existing),
+    )
+    .await
+}
+
+pub async fn list_documents(
+    manager: web::Data<Addr<Manager>>,
+    services: web::Data<Services>,
+    user: AuthenticatedUser,
+    path: web::Path<String>,
+) -> impl Responder {
+    let actor_id = path.into_inner();
+    if let Err(response) = check_owner(&manager, &user.user_id, &actor_id).await {
+        return response;
+    }
+    let db = match services.user_db(&user.token) {
+        Ok(db) => db,
+        Err(err) => return HttpResponse::ServiceUnavailable().json(err),
+    };
+    match db.list_knowledge_documents(&actor_id).await {
+        Ok(documents) => HttpResponse::Ok().json(json!({ "documents": documents })),
+        Err(err) => HttpResponse::InternalServerError().json(err),
+    }
+}
+
+pub async fn delete_document(
+    manager: web::Data<Addr<Manager>>,
+    services: web::Data<Services>,
+    user: AuthenticatedUser,
+    path: web::Path<(String, String)>,
+) -> impl Responder {
+    let (actor_id, document_id) = path.into_inner();
+    if let Err(response) = check_owner(&manager, &user.user_id, &actor_id).await {
+        return response;
+    }
+    let db = match services.user_db(&user.token) {
+        Ok(db) => db,
+        Err(err) => return HttpResponse::ServiceUnavailable().json(err),
+    };
+    match knowledge::delete_document(&services, db.as_ref(), &actor_id, &document_id).await {
+        Ok(()) => HttpResponse::Ok().json(json!({
+            "message": "Document deleted successfully",
+            "document_id": document_id
+        })),
+        Err(err) => HttpResponse::NotFound().json(err),
+    }
+}
+
+/// Registered ahead of the `/actors` scope, which would otherwise claim these paths.
+pub fn configure_knowledge_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/actors/{actor_id}/knowledge")
+            .app_data(web::PayloadConfig::new(MAX_DOCUMENT_BYTES))
+            .route("", web::get().to(list_documents))
+            .route("", web::post().to(upload_document))
+            .route("/{document_id}", web::put().to(replace_document))
+            .route("/{document_id}", web::delete().to(delete_document)),
+    );
+}
@@ -1,11 +1,24 @@
+pub mod account_routes;
 pub mod actor_routes;
 pub mod admin_routes;
+pub mod auth;
+pub mod chat_routes;
+pub mod goal_routes;
+pub mod knowledge_routes;
+pub mod notification_routes;
+pub mod rate_limit;
 pub mod task_routes;
+pub mod validation;
 
 use actix_web::web;
 
 pub fn configure_routes(cfg: &mut web::ServiceConfig) {
+    knowledge_routes::configure_knowledge_routes(cfg);
+    account_routes::configure_account_routes(cfg);
     actor_routes::configure_actor_routes(cfg);
     admin_routes::configure_admin_routes(cfg);
+    chat_routes::configure_chat_routes(cfg);
+    goal_routes::configure_goal_routes(cfg);
+    notification_routes::configure_notification_routes(cfg);
     task_routes::configure_task_routes(cfg);
 }
@@ -0,0 +1,132 @@
+use crate::routes::auth::AuthenticatedUser;
+use crate::routes::validation::invalid_payload_response;
+use crate::services::channels::NotificationPreferences;
+use crate::services::Services;
+use actix_web::{web, HttpResponse, Responder};
+use chrono::Utc;
+use futures::stream;
+use serde::Deserialize;
+use serde_json::json;
+
+#[derive(Deserialize)]
+pub struct InboxQuery {
+    #[serde(default)]
+    pub unread_only: bool,
+    pub limit: Option<usize>,
+}
+
+/// The caller's notification inbox, newest first.
+pub async fn list_notifications(
+    services: web::Data<Services>,
+    user: AuthenticatedUser,
+    query: web::Query<InboxQuery>,
+) -> impl Responder {
+    let db = match services.user_db(&user.token) {
+        Ok(db) => db,
+        Err(err) => return HttpResponse::ServiceUnavailable().json(err),
+    };
+    let limit = query.limit.unwrap_or(50).clamp(1, 200);
+    match db
+        .list_notifications(&user.user_id, query.unread_only, limit)
+        .await
+    {
+        Ok(notifications) => HttpResponse::Ok().json(json!({ "notifications": notifications })),
+        Err(err) => HttpResponse::InternalServerError().json(err),
+    }
+}
+
+pub async fn mark_notification_read(
+    services: web::Data<Services>,
+    user: AuthenticatedUser,
+    path: web::Path<String>,
+) -> impl Responder {
+    let db = match services.user_db(&user.token) {
+        Ok(db) => db,
+        Err(err) => return HttpResponse::ServiceUnavailable().json(err),
+    };
+    let notification_id = path.into_inner();
+    match db
+        .update_notification(
+            &user.user_id,
+            &notification_id,
+            json!({ "read_at": Utc::now().to_rfc3339() }),
+        )
+        .await
+    {
+        Ok(()) => HttpResponse::Ok().json("Notification marked as read"),
+        Err(err) => HttpResponse::NotFound().json(err),
+    }
+}
+
+pub async fn get_preferences(
+    services: web::Data<Services>,
+    user: AuthenticatedUser,
+) -> impl Responder {
+    let db = match services.user_db(&user.token) {
+        Ok(db) => db,
+        Err(err) => return HttpResponse::ServiceUnavailable().json(err),
+    };
+    match db.get_notification_preferences(&user.user_id).await {
+        Ok(row) => {
+            let preferences: NotificationPreferences = row
+                .and_then(|row| serde_json::from_value(row).ok())
+                .unwrap_or_default();
+            HttpResponse::Ok().json(preferences)
+        }
+        Err(err) => HttpResponse::InternalServerError().json(err),
+    }
+}
+
+pub async fn set_preferences(
+    services: web::Data<Services>,
+    user: AuthenticatedUser,
+    payload: web::Json<NotificationPreferences>,
+) -> impl Responder {
+    let db = match services.user_db(&user.token) {
+        Ok(db) => db,
+        Err(err) => return HttpResponse::ServiceUnavailable().json(err),
+    };
+    let preferences = payload.into_inner();
+    if let Some(response) = invalid_payload_response(&preferences) {
+        return response;
+    }
+    match db
+        .set_notification_preferences(&user.user_id, json!(preferences))
+        .await
+    {
+        Ok(()) => HttpResponse::Ok().json(preferences),
+        Err(err) => HttpResponse::InternalServerError().json(err),
+    }
+}
+
+/// Server-sent events stream of the caller's notifications as they are created.
+pub async fn stream_notifications(
+    services: web::Data<Services>,
+    user: AuthenticatedUser,
+) -> impl Responder {
+    let receiver = services.notifications.subscribe(&user.user_id);
+    let events = stream::unfold(receiver, |mut receiver| async move {
+        let notification = receiver.recv().await?;
+        let event = web::Bytes::from(format!("data: {}\n\n", notification));
+        Some((Ok::<_, actix_web::Error>(event), receiver))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(events)
+}
+
+pub fn configure_notification_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/notifications")
+            .route("", web::get().to(list_notifications))
+            .route("/stream", web::get().to(stream_notifications))
+            .route("/preferences", web::get().to(get_preferences))
+            .route("/preferences", web::put().to(set_preferences))
+            .route(
+                "/{notification_id}/read",
+                web::post().to(mark_notification_read),
+            ),
+    );
+}
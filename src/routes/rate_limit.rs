@@ -0,0 +1,75 @@
+use crate::services::quota::{QuotaDenial, QuotaStatus};
+use crate::services::Services;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpResponse};
+use serde_json::json;
+use std::time::Duration;
+
+pub fn rate_limited_response(retry_after: Duration) -> HttpResponse {
+    let retry_after_secs = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+    HttpResponse::TooManyRequests()
+        .insert_header(("Retry-After", retry_after_secs.to_string()))
+        .json(json!({
+            "error": "rate_limited",
+            "message": "Too many requests, please slow down",
+            "retry_after_secs": retry_after_secs
+        }))
+}
+
+pub fn quota_exceeded_response(status: Box<QuotaStatus>) -> HttpResponse {
+    HttpResponse::TooManyRequests().json(json!({
+        "error": "quota_exceeded",
+        "message": format!(
+            "Monthly LLM quota for the '{}' plan has been used up",
+            status.plan
+        ),
+        "quota": status
+    }))
+}
+
+pub fn quota_unavailable_response(plan: &str) -> HttpResponse {
+    HttpResponse::ServiceUnavailable().json(json!({
+        "error": "quota_unavailable",
+        "message": format!(
+            "Usage for the '{}' plan can't be checked right now, please try again shortly",
+            plan
+        )
+    }))
+}
+
+/// Checks the per-user rate limit and monthly quota before a request that calls the LLM,
+/// returning the error response to send when either is exhausted. Without a database there
+/// is no usage to count, so only the rate limit applies.
+pub async fn user_limit_response(services: &Services, user_id: &str) -> Option<HttpResponse> {
+    if let Err(retry_after) = services.user_limiter.check(user_id) {
+        return Some(rate_limited_response(retry_after));
+    }
+    match services.quota.check(services, user_id).await {
+        Ok(()) => None,
+        Err(QuotaDenial::Exceeded(status)) => Some(quota_exceeded_response(status)),
+        Err(QuotaDenial::Unverified { plan }) => Some(quota_unavailable_response(&plan)),
+    }
+}
+
+/// Middleware applying the per-IP token bucket to every route.
+pub async fn limit_by_ip(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let ip = req
+        .peer_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    if let Some(services) = req.app_data::<web::Data<Services>>() {
+        if let Err(retry_after) = services.ip_limiter.check(&ip) {
+            return Ok(req
+                .into_response(rate_limited_response(retry_after))
+                .map_into_right_body());
+        }
+    }
+
+    next.call(req).await.map(|res| res.map_into_left_body())
+}
@@ -1,31 +1,75 @@
 use crate::actors::manager::Manager;
 use crate::actors::message::{ActivateTask, TrackTaskProgress};
+use crate::routes::auth::AuthenticatedUser;
+use crate::routes::rate_limit::rate_limited_response;
+use crate::routes::validation::invalid_payload_response;
+use crate::services::audit::{record, AuditEvent, RequestOrigin};
+use crate::services::Services;
 use actix::Addr;
 use actix_web::{web, HttpResponse, Responder};
+use serde_json::json;
 
 pub async fn create_task(
     manager: web::Data<Addr<Manager>>,
+    services: web::Data<Services>,
+    user: AuthenticatedUser,
+    origin: RequestOrigin,
     payload: web::Json<ActivateTask>,
 ) -> impl Responder {
     let task_message = payload.into_inner();
+    if let Some(response) = invalid_payload_response(&task_message) {
+        return response;
+    }
+    if let Err(retry_after) = services.user_limiter.check(&user.user_id) {
+        return rate_limited_response(retry_after);
+    }
+    let event = AuditEvent::user("task.create")
+        .target("task", &task_message.task_id)
+        .details(json!({ "parameters": task_message.parameters }))
+        .origin(&origin);
 
-    let result = manager.send(task_message).await;
+    let result = manager
+        .send(task_message)
+        .await
+        .unwrap_or_else(|_| Err("Failed to create task".to_string()));
     match result {
-        Ok(_) => HttpResponse::Ok().json("Task created successfully"),
-        Err(_) => HttpResponse::InternalServerError().json("Failed to create task"),
+        Ok(()) => {
+            record(&services, event).await;
+            HttpResponse::Ok().json("Task created successfully")
+        }
+        Err(err) => HttpResponse::InternalServerError().json(err),
     }
 }
 
 pub async fn activate_task(
     manager: web::Data<Addr<Manager>>,
+    services: web::Data<Services>,
+    user: AuthenticatedUser,
+    origin: RequestOrigin,
     payload: web::Json<ActivateTask>,
 ) -> impl Responder {
     let task_message = payload.into_inner();
+    if let Some(response) = invalid_payload_response(&task_message) {
+        return response;
+    }
+    if let Err(retry_after) = services.user_limiter.check(&user.user_id) {
+        return rate_limited_response(retry_after);
+    }
+    let event = AuditEvent::user("task.activate")
+        .target("task", &task_message.task_id)
+        .details(json!({ "parameters": task_message.parameters }))
+        .origin(&origin);
 
-    let result = manager.send(task_message).await;
+    let result = manager
+        .send(task_message)
+        .await
+        .unwrap_or_else(|_| Err("Failed to activate task".to_string()));
     match result {
-        Ok(_) => HttpResponse::Ok().json("Task activated successfully"),
-        Err(_) => HttpResponse::InternalServerError().json("Failed to activate task"),
+        Ok(()) => {
+            record(&services, event).await;
+            HttpResponse::Ok().json("Task activated successfully")
+        }
+        Err(err) => HttpResponse::InternalServerError().json(err),
     }
 }
 
@@ -0,0 +1,16 @@
+use crate::actors::validation::{Validate, ValidationErrors};
+use actix_web::HttpResponse;
+use serde_json::json;
+
+pub fn validation_failed_response(errors: ValidationErrors) -> HttpResponse {
+    HttpResponse::UnprocessableEntity().json(json!({
+        "error": "validation_failed",
+        "message": "Some fields are invalid",
+        "fields": errors.errors
+    }))
+}
+
+/// Validates a payload, returning the 422 response to send when it breaks any rule.
+pub fn invalid_payload_response(payload: &impl Validate) -> Option<HttpResponse> {
+    payload.validate().err().map(validation_failed_response)
+}
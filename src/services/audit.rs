@@ -0,0 +1,139 @@
+use crate::services::Services;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Entries returned by an audit query when no limit is given, and the most allowed.
+const DEFAULT_QUERY_LIMIT: usize = 100;
+const MAX_QUERY_LIMIT: usize = 1000;
+
+/// Who sent a request and from where. `user_id` is set only when the request carried a
+/// valid access token.
+#[derive(Debug, Clone, Default)]
+pub struct RequestOrigin {
+    pub user_id: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// One entry for the `audit_log` table. Start with `user`, `admin` or `system` for the role
+/// the action was taken in.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEvent {
+    pub action: String,
+    pub role: &'static str,
+    pub performed_by: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub details: Value,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl AuditEvent {
+    fn new(role: &'static str, action: &str) -> Self {
+        AuditEvent {
+            action: action.to_string(),
+            role,
+            performed_by: None,
+            target_type: None,
+            target_id: None,
+            details: Value::Null,
+            ip: None,
+            user_agent: None,
+        }
+    }
+
+    pub fn user(action: &str) -> Self {
+        AuditEvent::new("user", action)
+    }
+
+    pub fn admin(action: &str) -> Self {
+        AuditEvent::new("admin", action)
+    }
+
+    pub fn system(action: &str) -> Self {
+        AuditEvent::new("system", action)
+    }
+
+    pub fn target(mut self, target_type: &str, target_id: &str) -> Self {
+        self.target_type = Some(target_type.to_string());
+        self.target_id = Some(target_id.to_string());
+        self
+    }
+
+    pub fn details(mut self, details: Value) -> Self {
+        self.details = details;
+        self
+    }
+
+    pub fn origin(mut self, origin: &RequestOrigin) -> Self {
+        if origin.user_id.is_some() {
+            self.performed_by = origin.user_id.clone();
+        }
+        self.ip = origin.ip.clone();
+        self.user_agent = origin.user_agent.clone();
+        self
+    }
+}
+
+/// Appends an event to the audit log. Failures are printed rather than returned, so an
+/// unavailable log never blocks the action being audited.
+pub async fn record(services: &Services, event: AuditEvent) {
+    let Some(db) = &services.db else {
+        println!(
+            "Warning: database not configured, audit event {} not recorded",
+            event.action
+        );
+        return;
+    };
+    let entry = match serde_json::to_value(&event) {
+        Ok(entry) => entry,
+        Err(e) => {
+            println!("Warning: failed to serialize audit event: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = db.add_audit_entry(entry).await {
+        println!(
+            "Warning: failed to record audit event {}: {}",
+            event.action, e
+        );
+    }
+}
+
+/// Filters for `GET /admin/audit`. Every filter that is set must match; `since` is
+/// inclusive and `until` exclusive.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuditQuery {
+    pub action: Option<String>,
+    pub role: Option<String>,
+    pub performed_by: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    /// The columns that must equal a given value.
+    pub fn equals(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("action", &self.action),
+            ("role", &self.role),
+            ("performed_by", &self.performed_by),
+            ("target_type", &self.target_type),
+            ("target_id", &self.target_id),
+        ]
+        .into_iter()
+        .filter_map(|(column, value)| value.as_deref().map(|value| (column, value)))
+        .collect()
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(DEFAULT_QUERY_LIMIT)
+            .clamp(1, MAX_QUERY_LIMIT)
+    }
+}
@@ -0,0 +1,513 @@
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+
+/// A browser push subscription, as returned by `PushManager.subscribe()`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PushSubscription {
+    pub endpoint: String,
+    #[serde(default)]
+    pub keys: Value,
+}
+
+/// Where and how a user wants to be reached outside the app.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NotificationPreferences {
+    #[serde(default)]
+    pub channels: Vec<String>, // Enabled channel names, e.g. ["email", "push"]
+    pub email: Option<String>,
+    pub push_subscription: Option<PushSubscription>,
+    pub webhook_url: Option<String>,
+}
+
+impl NotificationPreferences {
+    pub fn is_enabled(&self, channel: &str) -> bool {
+        self.channels.iter().any(|name| name == channel)
+    }
+}
+
+/// An outbound delivery channel for notifications.
+#[async_trait]
+pub trait NotificationChannel: Send + Sync {
+    /// The name users enable the channel by in their preferences.
+    fn name(&self) -> &'static str;
+
+    async fn deliver(
+        &self,
+        preferences: &NotificationPreferences,
+        notification: &Value,
+    ) -> Result<(), String>;
+}
+
+/// Builds every channel whose configuration is present in the environment.
+pub fn channels_from_env() -> Vec<Arc<dyn NotificationChannel>> {
+    let mut channels: Vec<Arc<dyn NotificationChannel>> = Vec::new();
+    match SmtpChannel::from_env() {
+        Ok(channel) => channels.push(Arc::new(channel)),
+        Err(e) => println!("Warning: email notifications disabled: {}", e),
+    }
+    match WebPushChannel::from_env() {
+        Ok(channel) => channels.push(Arc::new(channel)),
+        Err(e) => println!("Warning: push notifications disabled: {}", e),
+    }
+    channels.push(Arc::new(WebhookChannel::from_env()));
+    channels
+}
+
+/// Hosts of the push services browsers subscribe with, matched with their subdomains.
+const PUSH_SERVICE_HOSTS: &[&str] = &[
+    "fcm.googleapis.com",
+    "updates.push.services.mozilla.com",
+    "notify.windows.com",
+    "push.apple.com",
+];
+
+/// Checks a URL a user gave us before the server sends anything to it: it must be https and
+/// must not name a loopback, private, link-local or unspecified address. Host names are
+/// checked again when `outbound_client` resolves them.
+pub fn check_destination(url: &str) -> Result<Url, String> {
+    let url = Url::parse(url).map_err(|e| format!("Invalid URL: {}", e))?;
+    if url.scheme() != "https" {
+        return Err("URL must use https".to_string());
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| "URL has no host".to_string())?;
+    let literal = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>();
+    if host.trim_end_matches('.').eq_ignore_ascii_case("localhost")
+        || literal.is_ok_and(|ip| !is_public(ip))
+    {
+        return Err(format!("{} is not a public address", host));
+    }
+    Ok(url)
+}
+
+/// Whether `url` points at one of the known push services.
+pub fn is_push_service(url: &Url) -> bool {
+    url.host_str().is_some_and(|host| {
+        PUSH_SERVICE_HOSTS
+            .iter()
+            .any(|known| host == *known || host.ends_with(&format!(".{}", known)))
+    })
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                // Carrier-grade NAT, 100.64.0.0/10.
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(mapped) => is_public(IpAddr::V4(mapped)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || (first & 0xfe00) == 0xfc00 // Unique local, fc00::/7
+                    || (first & 0xffc0) == 0xfe80) // Link-local, fe80::/10
+            }
+        },
+    }
+}
+
+/// Resolves host names to their public addresses only, so a name can't lead a request to an
+/// internal service, however it resolves at the time.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// A client for URLs users gave us: it only connects to public addresses and doesn't follow
+/// redirects, which could point anywhere.
+fn outbound_client() -> Client {
+    Client::builder()
+        .dns_resolver(Arc::new(PublicResolver))
+        .redirect(Policy::none())
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("static client configuration is valid")
+}
+
+fn notification_text(notification: &Value) -> (String, String) {
+    (
+        notification["title"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        notification["body"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+    )
+}
+
+/// Plain SMTP delivery. Point it at a local relay for TLS; in development the Inbucket server
+/// from `supabase/config.toml` accepts mail on `SMTP_PORT=54325`.
+pub struct SmtpChannel {
+    host: String,
+    port: u16,
+    credentials: Option<(String, String)>,
+    from: String,
+    timeout: Duration,
+}
+
+impl SmtpChannel {
+    pub fn from_env() -> Result<Self, String> {
+        let host = env::var("SMTP_HOST").map_err(|_| "SMTP_HOST environment variable not set")?;
+        let port = env::var("SMTP_PORT")
+            .ok()
+            .and_then(|port| port.parse().ok())
+            .unwrap_or(25);
+        let credentials = match (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+            (Ok(username), Ok(password)) => Some((username, password)),
+            _ => None,
+        };
+        let from = env::var("SMTP_FROM")
+            .unwrap_or_else(|_| "Procuvita <no-reply@procuvita.app>".to_string());
+
+        Ok(SmtpChannel {
+            host,
+            port,
+            credentials,
+            from,
+            timeout: Duration::from_secs(10),
+        })
+    }
+
+    /// The bare address from a mailbox such as `Name <user@example.com>`.
+    fn address(mailbox: &str) -> &str {
+        match (mailbox.find('<'), mailbox.rfind('>')) {
+            (Some(start), Some(end)) if start < end => &mailbox[start + 1..end],
+            _ => mailbox.trim(),
+        }
+    }
+
+    async fn send_mail(&self, to: &str, subject: &str, body: &str) -> Result<(), String> {
+        if [to, subject]
+            .iter()
+            .any(|value| value.contains(['\r', '\n']))
+        {
+            return Err("Email headers must not contain line breaks".to_string());
+        }
+
+        let stream = TcpStream::connect((self.host.as_str(), self.port))
+            .await
+            .map_err(|e| format!("SMTP connection failed: {}", e))?;
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+
+        read_reply(&mut reader, 220).await?;
+        command(&mut writer, &mut reader, "EHLO procuvita", 250).await?;
+        if let Some((username, password)) = &self.credentials {
+            let token = STANDARD.encode(format!("\0{}\0{}", username, password));
+            command(
+                &mut writer,
+                &mut reader,
+                &format!("AUTH PLAIN {}", token),
+                235,
+            )
+            .await?;
+        }
+        let from = format!("MAIL FROM:<{}>", Self::address(&self.from));
+        command(&mut writer, &mut reader, &from, 250).await?;
+        let rcpt = format!("RCPT TO:<{}>", Self::address(to));
+        command(&mut writer, &mut reader, &rcpt, 250).await?;
+        command(&mut writer, &mut reader, "DATA", 354).await?;
+
+        // Lines starting with a dot are escaped by doubling it (RFC 5321 section 4.5.2).
+        let body: Vec<String> = body
+            .lines()
+            .map(|line| {
+                if line.starts_with('.') {
+                    format!(".{}", line)
+                } else {
+                    line.to_string()
+                }
+            })
+            .collect();
+        let message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\n\
+            Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n{}\r\n.",
+            self.from,
+            to,
+            subject,
+            Utc::now().to_rfc2822(),
+            body.join("\r\n")
+        );
+        command(&mut writer, &mut reader, &message, 250).await?;
+        command(&mut writer, &mut reader, "QUIT", 221).await?;
+        Ok(())
+    }
+}
+
+async fn command(
+    writer: &mut OwnedWriteHalf,
+    reader: &mut BufReader<OwnedReadHalf>,
+    line: &str,
+    expected: u16,
+) -> Result<(), String> {
+    writer
+        .write_all(format!("{}\r\n", line).as_bytes())
+        .await
+        .map_err(|e| format!("SMTP write failed: {}", e))?;
+    read_reply(reader, expected).await
+}
+
+/// Reads a possibly multi-line SMTP reply and checks its status code.
+async fn read_reply(reader: &mut BufReader<OwnedReadHalf>, expected: u16) -> Result<(), String> {
+    loop {
+        let mut line = String::new();
+        let read = reader
+            .read_line(&mut line)
+            .await
+            .map_err(|e| format!("SMTP read failed: {}", e))?;
+        if read == 0 {
+            return Err("SMTP server closed the connection".to_string());
+        }
+        // "250-..." continues the reply, "250 ..." ends it.
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+        let code: u16 = line
+            .get(..3)
+            .and_then(|code| code.parse().ok())
+            .unwrap_or(0);
+        return if code == expected {
+            Ok(())
+        } else {
+            Err(format!("SMTP server replied: {}", line.trim()))
+        };
+    }
+}
+
+#[async_trait]
+impl NotificationChannel for SmtpChannel {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    async fn deliver(
+        &self,
+        preferences: &NotificationPreferences,
+        notification: &Value,
+    ) -> Result<(), String> {
+        let to = preferences
+            .email
+            .as_deref()
+            .ok_or_else(|| "No email address set".to_string())?;
+        let (title, body) = notification_text(notification);
+        tokio::time::timeout(self.timeout, self.send_mail(to, &title, &body))
+            .await
+            .map_err(|_| "SMTP delivery timed out".to_string())?
+    }
+}
+
+/// Web push with VAPID authentication. Pushes carry no payload; the service worker fetches
+/// `GET /notifications` when woken up.
+pub struct WebPushChannel {
+    client: Client,
+    private_key: EncodingKey,
+    public_key: String,
+    subject: String,
+}
+
+impl WebPushChannel {
+    pub fn from_env() -> Result<Self, String> {
+        // PKCS#8, e.g. from `openssl ecparam -name prime256v1 -genkey | openssl pkcs8 -topk8 -nocrypt`
+        let private_key = env::var("VAPID_PRIVATE_KEY")
+            .map_err(|_| "VAPID_PRIVATE_KEY environment variable not set")?;
+        let public_key = env::var("VAPID_PUBLIC_KEY")
+            .map_err(|_| "VAPID_PUBLIC_KEY environment variable not set")?;
+        let subject = env::var("VAPID_SUBJECT")
+            .unwrap_or_else(|_| "mailto:support@procuvita.app".to_string());
+
+        let channel = WebPushChannel {
+            client: outbound_client(),
+            private_key: EncodingKey::from_ec_der(&pkcs8_der(&private_key)?),
+            public_key,
+            subject,
+        };
+        // Fail at startup rather than on the first push if the key can't sign.
+        let probe = Url::parse("https://push.example.com").expect("valid URL");
+        channel.vapid_token(&probe)?;
+        Ok(channel)
+    }
+
+    fn vapid_token(&self, endpoint: &Url) -> Result<String, String> {
+        let claims = json!({
+            "aud": endpoint.origin().ascii_serialization(),
+            "exp": Utc::now().timestamp() + 12 * 60 * 60,
+            "sub": self.subject,
+        });
+        encode(&Header::new(Algorithm::ES256), &claims, &self.private_key)
+            .map_err(|e| format!("Failed to sign VAPID token: {}", e))
+    }
+}
+
+/// Decodes a PKCS#8 EC private key given either as PEM or as its base64 body.
+fn pkcs8_der(key: &str) -> Result<Vec<u8>, String> {
+    let body: String = key
+        .replace("\\n", "\n")
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .map(str::trim)
+        .collect();
+    STANDARD
+        .decode(body)
+        .map_err(|e| format!("Invalid VAPID_PRIVATE_KEY: {}", e))
+}
+
+#[async_trait]
+impl NotificationChannel for WebPushChannel {
+    fn name(&self) -> &'static str {
+        "push"
+    }
+
+    async fn deliver(
+        &self,
+        preferences: &NotificationPreferences,
+        _: &Value,
+    ) -> Result<(), String> {
+        let subscription = preferences
+            .push_subscription
+            .as_ref()
+            .ok_or_else(|| "No push subscription set".to_string())?;
+        let endpoint = check_destination(&subscription.endpoint)
+            .map_err(|e| format!("Invalid push endpoint: {}", e))?;
+        if !is_push_service(&endpoint) {
+            return Err(format!(
+                "Push endpoint {} is not on a known push service",
+                endpoint.host_str().unwrap_or_default()
+            ));
+        }
+        let token = self.vapid_token(&endpoint)?;
+
+        let response = self
+            .client
+            .post(endpoint)
+            .header("TTL", "86400")
+            .header("Urgency", "normal")
+            .header(
+                "Authorization",
+                format!("vapid t={}, k={}", token, self.public_key),
+            )
+            .header("Content-Length", "0")
+            .send()
+            .await
+            .map_err(|e| format!("Push request failed: {}", e))?;
+
+        match response.status().as_u16() {
+            200..=299 => Ok(()),
+            404 | 410 => Err("Push subscription has expired".to_string()),
+            status => Err(format!("Push service returned {}", status)),
+        }
+    }
+}
+
+/// POSTs notifications as JSON to a URL of the user's choosing. When `WEBHOOK_SECRET` is set,
+/// the body is signed with HMAC-SHA256 in the `X-Procuvita-Signature` header.
+pub struct WebhookChannel {
+    client: Client,
+    secret: Option<String>,
+}
+
+impl WebhookChannel {
+    pub fn from_env() -> Self {
+        WebhookChannel {
+            client: outbound_client(),
+            secret: env::var("WEBHOOK_SECRET").ok(),
+        }
+    }
+
+    fn signature(secret: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(body);
+        let digest: String = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        format!("sha256={}", digest)
+    }
+}
+
+#[async_trait]
+impl NotificationChannel for WebhookChannel {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn deliver(
+        &self,
+        preferences: &NotificationPreferences,
+        notification: &Value,
+    ) -> Result<(), String> {
+        let url = preferences
+            .webhook_url
+            .as_deref()
+            .ok_or_else(|| "No webhook URL set".to_string())?;
+        let url = check_destination(url).map_err(|e| format!("Invalid webhook URL: {}", e))?;
+        let body = json!({ "event": "notification", "notification": notification }).to_string();
+
+        let mut request = self
+            .client
+            .post(url)
+            .header("Content-Type", "application/json");
+        if let Some(secret) = &self.secret {
+            request = request.header(
+                "X-Procuvita-Signature",
+                Self::signature(secret, body.as_bytes()),
+            );
+        }
+
+        let response = request
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| format!("Webhook request failed: {}", e))?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("Webhook returned {}", response.status()))
+        }
+    }
+}
@@ -0,0 +1,406 @@
+use serde_json::Value;
+use std::env;
+use tiktoken_rs::o200k_base_singleton;
+
+/// Context limits used when `LLM_CONTEXT_WINDOWS` is not set.
+const DEFAULT_LIMITS: &str = "gpt-4o=128000:16384,gpt-4o-mini=128000:16384,\
+    gpt-4-turbo=128000:4096,gpt-4=8192:8192,gpt-3.5-turbo=16385:4096";
+/// Tokens every chat message costs besides its content, and those priming the reply.
+pub const MESSAGE_OVERHEAD: usize = 4;
+pub const REPLY_PRIMING: usize = 3;
+/// One in this many prompt tokens is held back for models whose tokenizers count more
+/// tokens than o200k_base.
+const PROMPT_MARGIN: usize = 20;
+/// Replies are never given less room than this, even when the prompt fills the window.
+const MIN_REPLY_TOKENS: usize = 64;
+/// Most tokens spent on the summary of conversation turns that were dropped.
+const SUMMARY_TOKENS: usize = 200;
+/// Most tokens each dropped turn contributes to that summary.
+const SUMMARY_TURN_TOKENS: usize = 24;
+/// Appended to text that was cut short.
+const ELLIPSIS: &str = " […]";
+
+/// How many tokens `text` takes in o200k_base, the vocabulary of the gpt-4o family. Other
+/// models' tokenizers split text a little differently, which `PROMPT_MARGIN` absorbs.
+pub fn count_tokens(text: &str) -> usize {
+    o200k_base_singleton().encode_ordinary(text).len()
+}
+
+/// The byte offset after each token of `text` with the tokens counted up to there. Tokens that
+/// end inside a character are merged into the next one.
+fn token_boundaries(text: &str) -> Vec<(usize, usize)> {
+    let bpe = o200k_base_singleton();
+    let mut boundaries = Vec::new();
+    let mut pending = Vec::new(); // Tokens that don't decode to whole characters on their own
+    let mut end = 0;
+    for (i, token) in bpe.encode_ordinary(text).into_iter().enumerate() {
+        pending.push(token);
+        if let Ok(decoded) = bpe.decode(pending.clone()) {
+            end += decoded.len();
+            pending.clear();
+            boundaries.push((end, i + 1));
+        }
+    }
+    boundaries
+}
+
+/// Cuts `text` down to about `max_tokens`, preferring to end at a sentence or line break, and
+/// marks the cut.
+pub fn truncate(text: &str, max_tokens: usize) -> String {
+    if count_tokens(text) <= max_tokens {
+        return text.to_string();
+    }
+    let room = max_tokens.saturating_sub(count_tokens(ELLIPSIS));
+    let end = token_boundaries(text)
+        .into_iter()
+        .take_while(|(_, tokens)| *tokens <= room)
+        .last()
+        .map_or(0, |(end, _)| end);
+    if end == 0 {
+        return String::new();
+    }
+    let kept = &text[..end];
+    let kept = match kept.rfind(['.', '!', '?', '\n']) {
+        Some(i) if i >= end / 2 => &kept[..=i],
+        _ => kept,
+    };
+    format!("{}{}", kept.trim_end(), ELLIPSIS)
+}
+
+/// Tokens a list of chat messages takes, including tool calls and per-message overhead.
+pub fn message_tokens(messages: &[Value]) -> usize {
+    let content: usize = messages
+        .iter()
+        .map(|message| {
+            let text = match &message["content"] {
+                Value::String(text) => count_tokens(text),
+                Value::Null => 0,
+                other => count_tokens(&other.to_string()),
+            };
+            let calls = match &message["tool_calls"] {
+                Value::Null => 0,
+                calls => count_tokens(&calls.to_string()),
+            };
+            MESSAGE_OVERHEAD + text + calls
+        })
+        .sum();
+    content + REPLY_PRIMING
+}
+
+/// How much a model reads and writes, in tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModelLimits {
+    pub context_window: usize, // Prompt and reply together
+    pub max_output: usize,
+}
+
+/// Model limits from `LLM_CONTEXT_WINDOWS`, formatted as `model=context_window:max_output`
+/// pairs separated by commas. Models without an entry use `LLM_CONTEXT_WINDOW` and
+/// `LLM_MAX_OUTPUT_TOKENS`. `LLM_MAX_PROMPT_TOKENS` bounds prompts even when the window is
+/// larger, since every prompt token is paid for.
+#[derive(Debug, Clone)]
+pub struct ContextTable {
+    limits: Vec<(String, ModelLimits)>,
+    default: ModelLimits,
+    max_prompt: usize,
+}
+
+fn env_usize(name: &str, default: usize) -> usize {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+impl ContextTable {
+    pub fn from_env() -> Self {
+        let default = ModelLimits {
+            context_window: env_usize("LLM_CONTEXT_WINDOW", 8192),
+            max_output: env_usize("LLM_MAX_OUTPUT_TOKENS", 4096),
+        };
+        let spec = env::var("LLM_CONTEXT_WINDOWS").unwrap_or_else(|_| DEFAULT_LIMITS.to_string());
+        ContextTable::parse(&spec, default, env_usize("LLM_MAX_PROMPT_TOKENS", 16_000))
+    }
+
+    pub fn parse(spec: &str, default: ModelLimits, max_prompt: usize) -> Self {
+        let limits = spec
+            .split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .filter_map(|entry| {
+                let parsed = entry.split_once('=').and_then(|(model, limits)| {
+                    let (context_window, max_output) = limits.split_once(':')?;
+                    Some((
+                        model.trim().to_string(),
+                        ModelLimits {
+                            context_window: context_window.trim().parse().ok()?,
+                            max_output: max_output.trim().parse().ok()?,
+                        },
+                    ))
+                });
+                if parsed.is_none() {
+                    println!("Warning: ignoring invalid context limit '{}'", entry);
+                }
+                parsed
+            })
+            .collect();
+        ContextTable {
+            limits,
+            default,
+            max_prompt,
+        }
+    }
+
+    /// The entry for `model` or else the longest entry it starts with, as for prices.
+    pub fn limits(&self, model: &str) -> ModelLimits {
+        self.limits
+            .iter()
+            .filter(|(name, _)| model.starts_with(name.as_str()))
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, limits)| *limits)
+            .unwrap_or(self.default)
+    }
+
+    /// The budget for a request that any of `models` may serve, keeping `reply_tokens` free
+    /// for the reply. The smallest limits win, since a fallback can land on any of them.
+    pub fn budget(&self, models: &[String], reply_tokens: usize) -> PromptBudget {
+        let limits = models
+            .iter()
+            .map(|model| self.limits(model))
+            .reduce(|a, b| ModelLimits {
+                context_window: a.context_window.min(b.context_window),
+                max_output: a.max_output.min(b.max_output),
+            })
+            .unwrap_or(self.default);
+        let prompt = limits
+            .context_window
+            .saturating_sub(reply_tokens.min(limits.max_output))
+            .min(self.max_prompt);
+        PromptBudget {
+            prompt: prompt - prompt / PROMPT_MARGIN,
+            limits,
+        }
+    }
+}
+
+/// The tokens a prompt may take, and the limits the reply length is derived from.
+#[derive(Debug, Clone, Copy)]
+pub struct PromptBudget {
+    pub prompt: usize,
+    pub limits: ModelLimits,
+}
+
+impl PromptBudget {
+    /// The `max_tokens` for a prompt of `prompt_tokens`: whatever the window has left, up to
+    /// the model's output limit.
+    pub fn max_tokens(&self, prompt_tokens: usize) -> usize {
+        self.limits
+            .context_window
+            .saturating_sub(prompt_tokens + prompt_tokens / PROMPT_MARGIN)
+            .min(self.limits.max_output)
+            .max(MIN_REPLY_TOKENS)
+    }
+}
+
+/// How a section gives up tokens when the prompt is over budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trim {
+    /// Drops parts from the end, then cuts the last one short.
+    End,
+    /// Drops the oldest parts and replaces them with a short summary of what they said.
+    Oldest,
+}
+
+/// Sections that are trimmed only once every other section is empty.
+pub const REQUIRED: u8 = u8::MAX;
+
+struct Section {
+    name: &'static str,
+    priority: u8,
+    trim: Trim,
+    parts: Vec<String>,
+    header: usize,
+    part_overhead: usize,
+    dropped: usize,
+    summary: Option<String>,
+}
+
+impl Section {
+    fn tokens(&self) -> usize {
+        let framing = if self.parts.is_empty() {
+            0
+        } else {
+            self.header + self.parts.len() * self.part_overhead
+        };
+        self.parts
+            .iter()
+            .map(|part| count_tokens(part))
+            .sum::<usize>()
+            + framing
+            + self.summary.as_deref().map_or(0, count_tokens)
+    }
+
+    /// Gives up at least `over` tokens if the section has them.
+    fn shrink(&mut self, over: usize) {
+        let target = self.tokens().saturating_sub(over);
+        match self.trim {
+            Trim::End => {
+                while self.tokens() > target {
+                    let Some(last) = self.parts.pop() else {
+                        break;
+                    };
+                    let rest = self.tokens();
+                    let framing = self.part_overhead
+                        + if self.parts.is_empty() {
+                            self.header
+                        } else {
+                            0
+                        };
+                    if rest + framing < target {
+                        let cut = truncate(&last, target - rest - framing);
+                        if !cut.is_empty() {
+                            self.parts.push(cut);
+                        }
+                    }
+                }
+            }
+            Trim::Oldest => {
+                let mut dropped = Vec::new();
+                while self.tokens() > target && !self.parts.is_empty() {
+                    dropped.push(self.parts.remove(0));
+                    self.summary = Some(summarize(&dropped));
+                }
+                self.dropped += dropped.len();
+                if self.tokens() > target {
+                    self.summary = None;
+                }
+            }
+        }
+    }
+}
+
+/// A condensed account of dropped conversation turns: the start of each, newest kept first
+/// when they don't all fit.
+fn summarize(turns: &[String]) -> String {
+    let mut lines = Vec::new();
+    let mut tokens = 0;
+    for turn in turns.iter().rev() {
+        let line = truncate(turn.lines().next().unwrap_or_default(), SUMMARY_TURN_TOKENS);
+        tokens += count_tokens(&line);
+        if tokens > SUMMARY_TOKENS {
+            break;
+        }
+        lines.push(line);
+    }
+    lines.reverse();
+    format!("Earlier in this conversation: {}", lines.join("; "))
+}
+
+/// Fits the parts of a prompt into a token budget. Sections are trimmed lowest priority first,
+/// each only as far as needed, so higher-priority sections keep everything they can.
+#[derive(Default)]
+pub struct PromptBuilder {
+    fixed: usize,
+    sections: Vec<Section>,
+}
+
+impl PromptBuilder {
+    pub fn new() -> Self {
+        PromptBuilder::default()
+    }
+
+    /// Counts tokens that are always sent, such as instructions and tool definitions.
+    pub fn fixed(mut self, tokens: usize) -> Self {
+        self.fixed += tokens;
+        self
+    }
+
+    pub fn section(
+        mut self,
+        name: &'static str,
+        priority: u8,
+        trim: Trim,
+        parts: Vec<String>,
+    ) -> Self {
+        self.sections.push(Section {
+            name,
+            priority,
+            trim,
+            parts,
+            header: 0,
+            part_overhead: 0,
+            dropped: 0,
+            summary: None,
+        });
+        self
+    }
+
+    /// Charges the section added last for the text that frames it: `header` tokens while any
+    /// part is kept, and `per_part` more for each kept part.
+    pub fn framing(mut self, header: usize, per_part: usize) -> Self {
+        if let Some(section) = self.sections.last_mut() {
+            section.header = header;
+            section.part_overhead = per_part;
+        }
+        self
+    }
+
+    pub fn fit(mut self, budget: usize) -> FittedPrompt {
+        let mut total = self.fixed + self.sections.iter().map(Section::tokens).sum::<usize>();
+        let mut order: Vec<usize> = (0..self.sections.len()).collect();
+        order.sort_by_key(|&i| self.sections[i].priority);
+        for i in order {
+            if total <= budget {
+                break;
+            }
+            let section = &mut self.sections[i];
+            let before = section.tokens();
+            section.shrink(total - budget);
+            let after = section.tokens();
+            println!(
+                "Prompt over budget by {} tokens, trimmed {} from {} to {}",
+                total - budget,
+                section.name,
+                before,
+                after
+            );
+            total = total - before + after;
+        }
+        FittedPrompt {
+            sections: self.sections,
+            tokens: total,
+        }
+    }
+}
+
+/// The sections of a prompt after fitting.
+pub struct FittedPrompt {
+    sections: Vec<Section>,
+    pub tokens: usize,
+}
+
+impl FittedPrompt {
+    fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|section| section.name == name)
+    }
+
+    /// The parts of a section that were kept, some possibly cut short.
+    pub fn parts(&self, name: &str) -> &[String] {
+        self.section(name).map_or(&[], |section| &section.parts)
+    }
+
+    /// The kept parts of a section joined with `separator`.
+    pub fn text(&self, name: &str, separator: &str) -> String {
+        self.parts(name).join(separator)
+    }
+
+    /// How many leading parts of an `Oldest` section were dropped.
+    pub fn dropped(&self, name: &str) -> usize {
+        self.section(name).map_or(0, |section| section.dropped)
+    }
+
+    /// The summary standing in for the dropped parts of an `Oldest` section.
+    pub fn summary(&self, name: &str) -> Option<&str> {
+        self.section(name)
+            .and_then(|section| section.summary.as_deref())
+    }
+}
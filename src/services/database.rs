@@ -0,0 +1,150 @@
+use crate::services::audit::AuditQuery;
+use crate::services::usage::{UsageGroup, UsageQuery, UsageTotals};
+use async_trait::async_trait;
+use serde_json::Value;
+use std::sync::Arc;
+
+/// Data access used by actors and their tools. Rows are returned as JSON objects shaped like
+/// the tables in `supabase/migrations`.
+#[async_trait]
+pub trait Database: Send + Sync {
+    /// Fails when the tables or columns the code relies on are missing.
+    async fn check_schema(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// A handle whose queries run as the user holding `access_token`, so row-level security
+    /// applies to them. Fails when the backend has no notion of per-user access.
+    fn for_user(&self, _access_token: &str) -> Result<Arc<dyn Database>, String> {
+        Err("This database backend can't act as a user".to_string())
+    }
+
+    async fn get_user(&self, user_id: &str) -> Result<Option<Value>, String>;
+
+    async fn list_users(&self) -> Result<Vec<Value>, String>;
+
+    /// Sets the user's `last_active_at` to now.
+    async fn touch_user(&self, user_id: &str) -> Result<(), String>;
+
+    async fn list_goals(&self, user_id: &str) -> Result<Vec<Value>, String>;
+
+    async fn get_goal(&self, goal_id: &str) -> Result<Option<Value>, String>;
+
+    async fn list_tasks(&self, goal_id: &str) -> Result<Vec<Value>, String>;
+
+    async fn get_task(&self, task_id: &str) -> Result<Option<Value>, String>;
+
+    /// Inserts a task and returns its id. `task` must include `goal_id` and `title`.
+    async fn add_task(&self, task: Value) -> Result<String, String>;
+
+    /// Inserts several tasks, all of them or none, and returns their ids in order.
+    async fn add_tasks(&self, tasks: Vec<Value>) -> Result<Vec<String>, String>;
+
+    /// Marks a task completed and credits its `xp_reward` to the goal and the user.
+    /// Returns the XP awarded and the user's new level. Backends with transactions apply all
+    /// three writes atomically.
+    async fn complete_task(&self, user_id: &str, task_id: &str) -> Result<(i64, i64), String>;
+
+    /// The seeded `ai_agents` rows that have no owner and serve as templates.
+    async fn list_actor_templates(&self) -> Result<Vec<Value>, String>;
+
+    async fn get_actor_template(&self, agent_id: &str) -> Result<Option<Value>, String>;
+
+    /// Creates or replaces an actor's row in `ai_agents`.
+    async fn save_actor(&self, actor: Value) -> Result<(), String>;
+
+    /// Deletes an actor along with its interactions and saved state.
+    async fn delete_actor(&self, actor_id: &str) -> Result<(), String>;
+
+    /// The actors the user owns in `ai_agents`, whether or not they are running.
+    async fn list_user_actors(&self, user_id: &str) -> Result<Vec<Value>, String>;
+
+    /// Inserts a row in `interactions` and returns its id.
+    async fn add_interaction(&self, interaction: Value) -> Result<String, String>;
+
+    /// The actor's rows in `interactions`, oldest first.
+    async fn list_interactions(&self, actor_id: &str) -> Result<Vec<Value>, String>;
+
+    /// The actor's rows in `historical_interactions`, oldest first.
+    async fn list_historical_interactions(&self, actor_id: &str) -> Result<Vec<Value>, String>;
+
+    /// Creates or replaces a row in `knowledge_documents`.
+    async fn save_knowledge_document(&self, document: Value) -> Result<(), String>;
+
+    /// The documents uploaded to an actor, oldest first.
+    async fn list_knowledge_documents(&self, actor_id: &str) -> Result<Vec<Value>, String>;
+
+    async fn get_knowledge_document(
+        &self,
+        actor_id: &str,
+        document_id: &str,
+    ) -> Result<Option<Value>, String>;
+
+    async fn delete_knowledge_document(
+        &self,
+        actor_id: &str,
+        document_id: &str,
+    ) -> Result<(), String>;
+
+    /// Inserts a notification and returns its id.
+    async fn add_notification(&self, notification: Value) -> Result<String, String>;
+
+    /// The user's notifications, newest first.
+    async fn list_notifications(
+        &self,
+        user_id: &str,
+        unread_only: bool,
+        limit: usize,
+    ) -> Result<Vec<Value>, String>;
+
+    async fn get_notification_preferences(&self, user_id: &str) -> Result<Option<Value>, String>;
+
+    /// Creates or replaces the user's notification preferences.
+    async fn set_notification_preferences(
+        &self,
+        user_id: &str,
+        preferences: Value,
+    ) -> Result<(), String>;
+
+    /// Applies `changes` to one of the user's notifications.
+    async fn update_notification(
+        &self,
+        user_id: &str,
+        notification_id: &str,
+        changes: Value,
+    ) -> Result<(), String>;
+
+    /// Erases the user's profile and everything they own: goals and tasks, actors with their
+    /// interactions and state, knowledge documents and notifications.
+    async fn delete_user(&self, user_id: &str) -> Result<(), String>;
+
+    /// The user's row in `user_quotas`, if they were ever given a plan or reset.
+    async fn get_user_quota(&self, user_id: &str) -> Result<Option<Value>, String>;
+
+    /// Creates or replaces the user's row in `user_quotas`.
+    async fn set_user_quota(&self, user_id: &str, quota: Value) -> Result<(), String>;
+
+    /// Appends a row to `account_erasures`, which outlives the erased user.
+    async fn record_erasure(&self, record: Value) -> Result<(), String>;
+
+    /// Appends a row to `audit_log`.
+    async fn add_audit_entry(&self, entry: Value) -> Result<(), String>;
+
+    /// The `audit_log` rows matching `query`, newest first.
+    async fn list_audit_entries(&self, query: &AuditQuery) -> Result<Vec<Value>, String>;
+
+    /// Appends rows to `llm_usage`.
+    async fn add_llm_usage(&self, rows: Vec<Value>) -> Result<(), String>;
+
+    /// The `llm_usage` rows matching `query`, summed by `groups` and ordered by them.
+    async fn usage_report(
+        &self,
+        query: &UsageQuery,
+        groups: &[UsageGroup],
+    ) -> Result<Vec<UsageTotals>, String>;
+}
+
+/// Levels start at 1 and go up every 100 XP.
+pub fn level_for_xp(xp: i64) -> i64 {
+    1 + xp.max(0) / 100
+}
@@ -0,0 +1,185 @@
+use crate::actors::message::ActorProfile;
+use crate::services::llm::{ChatCompletion, EmbeddingBackend, LlmBackend};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// Softmax temperature applied to cosine similarities; lower values make the top match stand
+/// out more.
+const SIMILARITY_TEMPERATURE: f32 = 0.05;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteDecision {
+    pub actor_id: Uuid,
+    pub actor_name: String,
+    pub confidence: f32,
+    pub method: &'static str, // "only_actor", "embedding" or "llm"
+    #[serde(skip)]
+    pub completion: Option<ChatCompletion>, // The routing call, when the LLM decided
+}
+
+/// Picks which of a user's actors should answer a message.
+pub struct IntentRouter {
+    profile_embeddings: Mutex<HashMap<String, Vec<f32>>>,
+}
+
+fn describe(profile: &ActorProfile) -> String {
+    format!(
+        "{}: {} coach helping with {}",
+        profile.name,
+        profile.expertise,
+        profile.goals.join("; ")
+    )
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
+
+impl IntentRouter {
+    pub fn new() -> Self {
+        IntentRouter {
+            profile_embeddings: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn route(
+        &self,
+        llm: &dyn LlmBackend,
+        embeddings: &dyn EmbeddingBackend,
+        message: &str,
+        candidates: &[ActorProfile],
+    ) -> Result<RouteDecision, String> {
+        match candidates {
+            [] => Err("User has no actors to talk to".to_string()),
+            [only] => Ok(RouteDecision {
+                actor_id: only.id,
+                actor_name: only.name.clone(),
+                confidence: 1.0,
+                method: "only_actor",
+                completion: None,
+            }),
+            _ => match self
+                .route_by_embedding(embeddings, message, candidates)
+                .await
+            {
+                Ok(decision) => Ok(decision),
+                Err(e) => {
+                    println!("Embedding routing failed ({}), asking the LLM instead", e);
+                    self.route_by_llm(llm, message, candidates).await
+                }
+            },
+        }
+    }
+
+    async fn profile_embedding(
+        &self,
+        embeddings: &dyn EmbeddingBackend,
+        profile: &ActorProfile,
+    ) -> Result<Vec<f32>, String> {
+        let description = describe(profile);
+        if let Some(embedding) = self.profile_embeddings.lock().unwrap().get(&description) {
+            return Ok(embedding.clone());
+        }
+        let embedding = embeddings.embed(&description).await?;
+        self.profile_embeddings
+            .lock()
+            .unwrap()
+            .insert(description, embedding.clone());
+        Ok(embedding)
+    }
+
+    async fn route_by_embedding(
+        &self,
+        embeddings: &dyn EmbeddingBackend,
+        message: &str,
+        candidates: &[ActorProfile],
+    ) -> Result<RouteDecision, String> {
+        let query = embeddings.embed(message).await?;
+        let mut scores = Vec::with_capacity(candidates.len());
+        for profile in candidates {
+            let embedding = self.profile_embedding(embeddings, profile).await?;
+            scores.push(cosine_similarity(&query, &embedding));
+        }
+
+        let max = scores.iter().cloned().fold(f32::MIN, f32::max);
+        let weights: Vec<f32> = scores
+            .iter()
+            .map(|score| ((score - max) / SIMILARITY_TEMPERATURE).exp())
+            .collect();
+        let total: f32 = weights.iter().sum();
+        let (best, weight) = weights
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .ok_or_else(|| "No candidates".to_string())?;
+
+        Ok(RouteDecision {
+            actor_id: candidates[best].id,
+            actor_name: candidates[best].name.clone(),
+            confidence: weight / total,
+            method: "embedding",
+            completion: None,
+        })
+    }
+
+    async fn route_by_llm(
+        &self,
+        llm: &dyn LlmBackend,
+        message: &str,
+        candidates: &[ActorProfile],
+    ) -> Result<RouteDecision, String> {
+        let options: Vec<Value> = candidates
+            .iter()
+            .map(|profile| json!({ "actor_id": profile.id, "description": describe(profile) }))
+            .collect();
+
+        let completion = llm
+            .chat(json!({
+                "messages": [{
+                    "role": "system",
+                    "content": "You route a user's message to the coach best suited to answer it. \
+                        Reply with a JSON object {\"actor_id\": string, \"confidence\": number between 0 and 1}."
+                },
+                {
+                    "role": "user",
+                    "content": format!("Coaches: {}\n\nMessage: {}", json!(options), message)
+                }],
+                "response_format": { "type": "json_object" },
+                "max_tokens": 60,
+                "temperature": 0
+            }))
+            .await?;
+
+        let content = completion.content.clone().unwrap_or_default();
+        let choice: Value = serde_json::from_str(&content)
+            .map_err(|e| format!("Router returned invalid JSON: {}", e))?;
+        let chosen = candidates
+            .iter()
+            .find(|profile| choice["actor_id"].as_str() == Some(profile.id.to_string().as_str()))
+            .ok_or_else(|| format!("Router chose an unknown actor: {}", content))?;
+
+        Ok(RouteDecision {
+            actor_id: chosen.id,
+            actor_name: chosen.name.clone(),
+            confidence: choice["confidence"].as_f64().unwrap_or(0.5).clamp(0.0, 1.0) as f32,
+            method: "llm",
+            completion: Some(completion),
+        })
+    }
+}
+
+impl Default for IntentRouter {
+    fn default() -> Self {
+        IntentRouter::new()
+    }
+}
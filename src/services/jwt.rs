@@ -0,0 +1,19 @@
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use serde_json::Value;
+use std::env;
+
+/// Verifies a Supabase access token against `SUPABASE_JWT_SECRET` and returns its claims.
+pub fn verify(token: &str) -> Result<Value, String> {
+    let secret = env::var("SUPABASE_JWT_SECRET")
+        .map_err(|_| "SUPABASE_JWT_SECRET environment variable not set".to_string())?;
+
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_audience(&["authenticated"]);
+    let data = decode::<Value>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &validation,
+    )
+    .map_err(|e| format!("Invalid access token: {}", e))?;
+    Ok(data.claims)
+}
@@ -0,0 +1,590 @@
+use crate::services::database::Database;
+use crate::services::vector_store::knowledge_namespace;
+use crate::services::Services;
+use chrono::Utc;
+use flate2::read::ZlibDecoder;
+use futures::{stream, StreamExt, TryStreamExt};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::Read;
+use std::sync::LazyLock;
+use uuid::Uuid;
+
+/// Target chunk length in characters; chunks break on word boundaries.
+const CHUNK_CHARS: usize = 1000;
+/// Characters repeated at the start of the next chunk so passages aren't cut mid-thought.
+const CHUNK_OVERLAP: usize = 200;
+/// Upper bound on chunks per document, which caps embedding calls per upload.
+const MAX_CHUNKS: usize = 500;
+/// Embedding requests in flight at once while ingesting a document.
+const EMBED_CONCURRENCY: usize = 4;
+/// Most bytes one compressed PDF stream may inflate to, and all of a PDF's streams together.
+/// Compression ratios run to the thousands, so a small upload can otherwise exhaust memory.
+const MAX_INFLATED_BYTES: usize = 8 * 1024 * 1024;
+const MAX_TOTAL_INFLATED_BYTES: usize = 32 * 1024 * 1024;
+
+static MD_IMAGE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"!\[([^\]]*)\]\([^)]*\)").unwrap());
+static MD_LINK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[([^\]]*)\]\([^)]*\)").unwrap());
+static MD_LINE_MARKER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?m)^[ \t]*(#{1,6}[ \t]+|>[ \t]?|[-*+][ \t]+|\d+\.[ \t]+)").unwrap()
+});
+static MD_FENCE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?m)^[ \t]*(```|~~~).*$").unwrap());
+static MD_EMPHASIS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\*\*|__|\*|`").unwrap());
+static HTML_HIDDEN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?is)<script\b.*?</script\s*>|<style\b.*?</style\s*>|<head\b.*?</head\s*>|<!--.*?-->",
+    )
+    .unwrap()
+});
+static HTML_BREAK: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)<(br|/p|/div|/h[1-6]|/li|/tr|/blockquote|/pre)\b[^>]*>").unwrap()
+});
+static HTML_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>").unwrap());
+static HTML_ENTITY: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"&(#[0-9]+|#[xX][0-9a-fA-F]+|[a-zA-Z]+);").unwrap());
+
+/// Formats accepted for knowledge documents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DocumentFormat {
+    Text,
+    Markdown,
+    Html,
+    Pdf,
+}
+
+impl DocumentFormat {
+    /// Parses a format name such as `markdown` or `md`.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "text" | "txt" | "plain" => Some(DocumentFormat::Text),
+            "markdown" | "md" => Some(DocumentFormat::Markdown),
+            "html" | "htm" => Some(DocumentFormat::Html),
+            "pdf" => Some(DocumentFormat::Pdf),
+            _ => None,
+        }
+    }
+
+    /// Maps a `Content-Type` header value, ignoring parameters like `charset`.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+        match mime.to_lowercase().as_str() {
+            "text/plain" => Some(DocumentFormat::Text),
+            "text/markdown" | "text/x-markdown" => Some(DocumentFormat::Markdown),
+            "text/html" | "application/xhtml+xml" => Some(DocumentFormat::Html),
+            "application/pdf" => Some(DocumentFormat::Pdf),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DocumentFormat::Text => "text",
+            DocumentFormat::Markdown => "markdown",
+            DocumentFormat::Html => "html",
+            DocumentFormat::Pdf => "pdf",
+        }
+    }
+}
+
+/// Turns an uploaded document into plain text.
+pub fn extract_text(format: DocumentFormat, content: &[u8]) -> Result<String, String> {
+    let text = match format {
+        DocumentFormat::Pdf => pdf_to_text(content)?,
+        _ => {
+            let source = std::str::from_utf8(content)
+                .map_err(|_| "Document is not valid UTF-8".to_string())?;
+            match format {
+                DocumentFormat::Markdown => markdown_to_text(source),
+                DocumentFormat::Html => html_to_text(source),
+                _ => source.to_string(),
+            }
+        }
+    };
+
+    if text.trim().is_empty() {
+        return Err(format!("No text found in {} document", format.as_str()));
+    }
+    Ok(text)
+}
+
+fn markdown_to_text(source: &str) -> String {
+    let text = MD_FENCE.replace_all(source, "");
+    let text = MD_IMAGE.replace_all(&text, "$1");
+    let text = MD_LINK.replace_all(&text, "$1");
+    let text = MD_LINE_MARKER.replace_all(&text, "");
+    MD_EMPHASIS.replace_all(&text, "").into_owned()
+}
+
+fn html_to_text(source: &str) -> String {
+    let text = HTML_HIDDEN.replace_all(source, " ");
+    let text = HTML_BREAK.replace_all(&text, "\n");
+    let text = HTML_TAG.replace_all(&text, " ");
+    let text = HTML_ENTITY.replace_all(&text, |caps: &regex::Captures| {
+        decode_entity(&caps[1]).unwrap_or_else(|| caps[0].to_string())
+    });
+    text.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn decode_entity(entity: &str) -> Option<String> {
+    let decoded = match entity {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => ' ',
+        _ => {
+            let code = if let Some(hex) = entity.strip_prefix("#x").or(entity.strip_prefix("#X")) {
+                u32::from_str_radix(hex, 16).ok()?
+            } else {
+                entity.strip_prefix('#')?.parse().ok()?
+            };
+            char::from_u32(code)?
+        }
+    };
+    Some(decoded.to_string())
+}
+
+/// Pulls the text shown by a PDF's content streams. Handles uncompressed and FlateDecode
+/// streams with simple (single-byte) fonts, which covers most text exports; scanned pages
+/// and CID-keyed fonts yield nothing. Fails when the streams inflate past the size limits.
+fn pdf_to_text(content: &[u8]) -> Result<String, String> {
+    let mut text = String::new();
+    let mut rest = content;
+    let mut inflated_total = 0;
+
+    while let Some(start) = find_bytes(rest, b"stream") {
+        let dictionary = &rest[..start];
+        let mut body = &rest[start + b"stream".len()..];
+        if body.starts_with(b"\r\n") {
+            body = &body[2..];
+        } else if body.starts_with(b"\n") || body.starts_with(b"\r") {
+            body = &body[1..];
+        }
+        let Some(end) = find_bytes(body, b"endstream") else {
+            break;
+        };
+        let data = &body[..end];
+        rest = &body[end + b"endstream".len()..];
+
+        // Images, fonts and streams in filters we can't decode carry no page text.
+        let is_flate = find_bytes(dictionary, b"/FlateDecode").is_some();
+        if find_bytes(dictionary, b"/Image").is_some()
+            || find_bytes(dictionary, b"/Length1").is_some()
+            || (find_bytes(dictionary, b"/Filter").is_some() && !is_flate)
+        {
+            continue;
+        }
+        let decoded = if is_flate {
+            let limit = MAX_INFLATED_BYTES.min(MAX_TOTAL_INFLATED_BYTES - inflated_total);
+            let mut inflated = Vec::new();
+            if ZlibDecoder::new(data)
+                .take(limit as u64 + 1)
+                .read_to_end(&mut inflated)
+                .is_err()
+            {
+                continue;
+            }
+            if inflated.len() > limit {
+                return Err(format!(
+                    "PDF content is too large once decompressed: at most {} MB per stream and {} MB in total",
+                    MAX_INFLATED_BYTES / (1024 * 1024),
+                    MAX_TOTAL_INFLATED_BYTES / (1024 * 1024)
+                ));
+            }
+            inflated_total += inflated.len();
+            inflated
+        } else {
+            data.to_vec()
+        };
+
+        let page_text = content_stream_text(&decoded);
+        if !page_text.trim().is_empty() {
+            text.push_str(page_text.trim());
+            text.push('\n');
+        }
+    }
+    Ok(text)
+}
+
+/// Collects the strings passed to the text-showing operators (`Tj`, `TJ`, `'` and `"`),
+/// starting a new line on line-moving operators.
+fn content_stream_text(stream: &[u8]) -> String {
+    let mut out = String::new();
+    let mut operands: Vec<String> = Vec::new();
+    let mut in_array = false;
+    let mut i = 0;
+
+    while i < stream.len() {
+        match stream[i] {
+            b'(' => {
+                let (string, next) = literal_string(stream, i + 1);
+                operands.push(string);
+                i = next;
+            }
+            b'<' if stream.get(i + 1) == Some(&b'<') => i += 2,
+            b'<' => {
+                let end = stream[i..]
+                    .iter()
+                    .position(|&b| b == b'>')
+                    .map_or(stream.len(), |pos| i + pos);
+                operands.push(hex_string(&stream[i + 1..end]));
+                i = end + 1;
+            }
+            b'[' => {
+                in_array = true;
+                i += 1;
+            }
+            b']' => {
+                in_array = false;
+                i += 1;
+            }
+            b'%' => {
+                while i < stream.len() && stream[i] != b'\n' && stream[i] != b'\r' {
+                    i += 1;
+                }
+            }
+            b'/' => {
+                i += 1;
+                while i < stream.len() && is_regular(stream[i]) {
+                    i += 1;
+                }
+            }
+            b'-' | b'+' | b'.' | b'0'..=b'9' => {
+                let start = i;
+                i += 1;
+                while i < stream.len() && matches!(stream[i], b'.' | b'0'..=b'9') {
+                    i += 1;
+                }
+                // Large negative kerning inside a TJ array stands in for a space.
+                let adjustment: f32 = String::from_utf8_lossy(&stream[start..i])
+                    .parse()
+                    .unwrap_or_default();
+                if in_array && adjustment < -200.0 {
+                    operands.push(" ".to_string());
+                }
+            }
+            b if is_regular(b) => {
+                let start = i;
+                while i < stream.len() && is_regular(stream[i]) {
+                    i += 1;
+                }
+                match &stream[start..i] {
+                    b"Tj" | b"TJ" => out.push_str(&operands.concat()),
+                    b"'" | b"\"" => {
+                        out.push('\n');
+                        out.push_str(&operands.concat());
+                    }
+                    b"T*" | b"Td" | b"TD" | b"ET" if !out.is_empty() && !out.ends_with('\n') => {
+                        out.push('\n')
+                    }
+                    _ => {}
+                }
+                operands.clear();
+            }
+            _ => i += 1,
+        }
+    }
+
+    out.chars()
+        .filter(|c| !c.is_control() || *c == '\n')
+        .collect()
+}
+
+fn is_regular(byte: u8) -> bool {
+    !byte.is_ascii_whitespace() && !b"()<>[]{}/%".contains(&byte)
+}
+
+/// Reads a `( ... )` string starting just after the opening parenthesis. Returns the decoded
+/// text and the index after the closing parenthesis.
+fn literal_string(stream: &[u8], mut i: usize) -> (String, usize) {
+    let mut bytes = Vec::new();
+    let mut depth = 0;
+    while i < stream.len() {
+        let byte = stream[i];
+        i += 1;
+        match byte {
+            b'\\' => {
+                let Some(&escaped) = stream.get(i) else { break };
+                i += 1;
+                match escaped {
+                    b'n' => bytes.push(b'\n'),
+                    b'r' => bytes.push(b'\r'),
+                    b't' => bytes.push(b'\t'),
+                    b'b' | b'f' => {}
+                    b'\r' | b'\n' => {
+                        // Line continuation
+                        if escaped == b'\r' && stream.get(i) == Some(&b'\n') {
+                            i += 1;
+                        }
+                    }
+                    b'0'..=b'7' => {
+                        let mut code = u32::from(escaped - b'0');
+                        for _ in 0..2 {
+                            match stream.get(i) {
+                                Some(&digit @ b'0'..=b'7') => {
+                                    code = code * 8 + u32::from(digit - b'0');
+                                    i += 1;
+                                }
+                                _ => break,
+                            }
+                        }
+                        bytes.push(code as u8);
+                    }
+                    other => bytes.push(other),
+                }
+            }
+            b'(' => {
+                depth += 1;
+                bytes.push(byte);
+            }
+            b')' if depth == 0 => break,
+            b')' => {
+                depth -= 1;
+                bytes.push(byte);
+            }
+            _ => bytes.push(byte),
+        }
+    }
+    // Simple fonts use single-byte codes; Latin-1 is the closest general-purpose mapping.
+    (bytes.iter().map(|&b| b as char).collect(), i)
+}
+
+fn hex_string(hex: &[u8]) -> String {
+    let digits: Vec<u8> = hex
+        .iter()
+        .filter_map(|&b| (b as char).to_digit(16).map(|d| d as u8))
+        .collect();
+    digits
+        .chunks(2)
+        .map(|pair| (pair[0] << 4 | pair.get(1).copied().unwrap_or(0)) as char)
+        .collect()
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Splits text into overlapping chunks of about `CHUNK_CHARS` characters.
+pub fn chunk_text(text: &str) -> Vec<String> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+
+    while start < words.len() {
+        let mut end = start;
+        let mut len = 0;
+        while end < words.len() && (end == start || len + words[end].len() < CHUNK_CHARS) {
+            len += words[end].len() + 1;
+            end += 1;
+        }
+        chunks.push(words[start..end].join(" "));
+        if end == words.len() {
+            break;
+        }
+
+        let mut next = end;
+        let mut overlap = 0;
+        while next > start + 1 && overlap + words[next - 1].len() < CHUNK_OVERLAP {
+            next -= 1;
+            overlap += words[next].len() + 1;
+        }
+        start = next;
+    }
+    chunks
+}
+
+/// A document to add to an actor's knowledge, already converted to text.
+pub struct NewDocument {
+    pub actor_id: String,
+    pub user_id: String,
+    pub title: String,
+    pub format: DocumentFormat,
+    pub size_bytes: usize,
+    pub text: String,
+}
+
+/// Chunks, embeds and stores a document, returning its `knowledge_documents` row, which is
+/// written through `db`. Passing the row of an existing document replaces it, keeping its id.
+pub async fn ingest_document(
+    services: &Services,
+    db: &dyn Database,
+    document: NewDocument,
+    existing: Option<&Value>,
+) -> Result<Value, String> {
+    let store = services
+        .vectors
+        .clone()
+        .ok_or_else(|| "Vector store is not configured".to_string())?;
+
+    let chunks = chunk_text(&document.text);
+    if chunks.len() > MAX_CHUNKS {
+        return Err(format!(
+            "Document is too long: {} chunks, at most {} allowed",
+            chunks.len(),
+            MAX_CHUNKS
+        ));
+    }
+
+    // Embed everything before touching the store so a failure leaves the old version intact.
+    let embeddings: Vec<Vec<f32>> = stream::iter(&chunks)
+        .map(|chunk| services.embeddings.embed(chunk))
+        .buffered(EMBED_CONCURRENCY)
+        .try_collect()
+        .await?;
+
+    // A new version goes in under new vector ids, and the old ones are only deleted once it
+    // is complete, so a failure partway leaves the document searchable as it was.
+    let namespace = knowledge_namespace(&document.actor_id);
+    let (document_id, old_ids) = match existing {
+        Some(row) => {
+            let id = row["id"]
+                .as_str()
+                .filter(|id| !id.is_empty())
+                .ok_or_else(|| "Document to replace has no id".to_string())?
+                .to_string();
+            let old_ids: Vec<String> = store
+                .list(&namespace, &format!("doc-{}-", id))
+                .await?
+                .into_iter()
+                .map(|vector| vector.id)
+                .collect();
+            (id, old_ids)
+        }
+        None => (Uuid::new_v4().to_string(), Vec::new()),
+    };
+
+    let version = Uuid::new_v4().simple().to_string();
+    let mut new_ids = Vec::with_capacity(chunks.len());
+    for (index, (chunk, embedding)) in chunks.iter().zip(embeddings).enumerate() {
+        let id = format!("doc-{}-{}-{}", document_id, version, index);
+        let upserted = store
+            .upsert(
+                &namespace,
+                &id,
+                embedding,
+                json!({
+                    "kind": "knowledge",
+                    "actor_id": document.actor_id,
+                    "user_id": document.user_id,
+                    "document_id": document_id,
+                    "title": document.title,
+                    "chunk_index": index,
+                    "text": chunk,
+                }),
+            )
+            .await;
+        new_ids.push(id);
+        if let Err(e) = upserted {
+            if let Err(cleanup) = store.delete_ids(&namespace, &new_ids).await {
+                println!(
+                    "Warning: failed to remove partial upload of document {}: {}",
+                    document_id, cleanup
+                );
+            }
+            return Err(e);
+        }
+    }
+
+    let now = Utc::now().to_rfc3339();
+    let row = json!({
+        "id": document_id,
+        "actor_id": document.actor_id,
+        "user_id": document.user_id,
+        "title": document.title,
+        "format": document.format,
+        "size_bytes": document.size_bytes,
+        "chunk_count": chunks.len(),
+        "created_at": existing.map_or(json!(now), |row| row["created_at"].clone()),
+        "updated_at": now,
+    });
+    if let Err(e) = db.save_knowledge_document(row.clone()).await {
+        if let Err(cleanup) = store.delete_ids(&namespace, &new_ids).await {
+            println!(
+                "Warning: failed to remove unsaved version of document {}: {}",
+                document_id, cleanup
+            );
+        }
+        return Err(e);
+    }
+    store.delete_ids(&namespace, &old_ids).await?;
+    Ok(row)
+}
+
+/// Removes a document and its chunks from an actor's knowledge.
+pub async fn delete_document(
+    services: &Services,
+    db: &dyn Database,
+    actor_id: &str,
+    document_id: &str,
+) -> Result<(), String> {
+    db.delete_knowledge_document(actor_id, document_id).await?;
+    if let Some(store) = &services.vectors {
+        store
+            .delete(
+                &knowledge_namespace(actor_id),
+                json!({ "document_id": document_id }),
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+/// A passage retrieved from one of the actor's documents.
+#[derive(Debug, Clone)]
+pub struct KnowledgeChunk {
+    pub document_id: String,
+    pub title: String,
+    pub chunk_index: usize,
+    pub text: String,
+}
+
+/// The actor's document chunks closest to `query`. Retrieval problems are logged and treated
+/// as having no matching knowledge, so replies still go out.
+pub async fn retrieve(
+    services: &Services,
+    actor_id: &str,
+    query: &str,
+    top_k: usize,
+) -> Vec<KnowledgeChunk> {
+    let Some(store) = services.vectors.clone() else {
+        return Vec::new();
+    };
+
+    let result = match services.embeddings.embed(query).await {
+        Ok(embedding) => {
+            store
+                .query(&knowledge_namespace(actor_id), embedding, top_k, json!({}))
+                .await
+        }
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(matches) => matches
+            .into_iter()
+            .map(|m| KnowledgeChunk {
+                document_id: m.metadata["document_id"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                title: m.metadata["title"].as_str().unwrap_or_default().to_string(),
+                chunk_index: m.metadata["chunk_index"].as_u64().unwrap_or_default() as usize,
+                text: m.metadata["text"].as_str().unwrap_or_default().to_string(),
+            })
+            .collect(),
+        Err(e) => {
+            println!("Warning: failed to retrieve knowledge: {}", e);
+            Vec::new()
+        }
+    }
+}
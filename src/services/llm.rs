@@ -0,0 +1,468 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Token counts as reported in the `usage` field of an LLM provider response.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TokenUsage {
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    #[serde(default)]
+    pub completion_tokens: u64,
+    #[serde(default)]
+    pub total_tokens: u64,
+}
+
+impl TokenUsage {
+    pub fn add(&mut self, other: &TokenUsage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+}
+
+/// Which `response_format` a provider accepts for structured replies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StructuredOutput {
+    /// `json_schema`: the provider constrains the reply to the schema.
+    JsonSchema,
+    /// `json_object` only: the schema is described in the prompt instead.
+    JsonObject,
+}
+
+/// One entry of the fallback chain: an OpenAI-compatible chat completions endpoint and model.
+#[derive(Debug, Clone)]
+pub struct LlmProvider {
+    pub name: String,
+    pub base_url: String,
+    pub api_key: Option<String>,
+    pub model: String,
+    pub structured_output: StructuredOutput,
+}
+
+impl LlmProvider {
+    /// Builds a provider from a `name:model` entry. The endpoint and key are read from
+    /// `{NAME}_BASE_URL` and `{NAME}_API_KEY`; `{NAME}_STRUCTURED_OUTPUT=json_object` marks
+    /// providers without JSON schema support.
+    fn from_spec(spec: &str) -> Option<Self> {
+        let (name, model) = spec.trim().split_once(':')?;
+        let prefix = name.to_uppercase().replace('-', "_");
+        let base_url = env::var(format!("{}_BASE_URL", prefix)).unwrap_or_else(|_| match name {
+            "openai" => "https://api.openai.com/v1".to_string(),
+            _ => String::new(),
+        });
+        if base_url.is_empty() {
+            println!(
+                "Warning: no {}_BASE_URL set, skipping provider {}",
+                prefix, name
+            );
+            return None;
+        }
+
+        Some(LlmProvider {
+            name: name.to_string(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: env::var(format!("{}_API_KEY", prefix)).ok(),
+            model: model.to_string(),
+            structured_output: match env::var(format!("{}_STRUCTURED_OUTPUT", prefix)).as_deref() {
+                Ok("json_object") => StructuredOutput::JsonObject,
+                _ => StructuredOutput::JsonSchema,
+            },
+        })
+    }
+
+    /// Rewrites a `json_schema` response format into what the provider accepts.
+    fn adapt_response_format(&self, body: &mut Value) {
+        if self.structured_output != StructuredOutput::JsonObject
+            || body["response_format"]["type"] != "json_schema"
+        {
+            return;
+        }
+        let schema = body["response_format"]["json_schema"]["schema"].take();
+        body["response_format"] = json!({ "type": "json_object" });
+        if let Some(messages) = body["messages"].as_array_mut() {
+            messages.insert(
+                0,
+                json!({
+                    "role": "system",
+                    "content": format!(
+                        "Reply with only a JSON object that follows this JSON schema: {}",
+                        schema
+                    )
+                }),
+            );
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LlmSettings {
+    pub request_timeout: Duration,
+    pub max_retries: u32,
+    pub backoff_base: Duration,
+    pub backoff_max: Duration,
+    pub breaker_threshold: u32,
+    pub breaker_cooldown: Duration,
+}
+
+fn env_u64(name: &str, default: u64) -> u64 {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+impl LlmSettings {
+    pub fn from_env() -> Self {
+        LlmSettings {
+            request_timeout: Duration::from_secs(env_u64("LLM_TIMEOUT_SECS", 30)),
+            max_retries: env_u64("LLM_MAX_RETRIES", 3) as u32,
+            backoff_base: Duration::from_millis(env_u64("LLM_BACKOFF_BASE_MS", 500)),
+            backoff_max: Duration::from_millis(env_u64("LLM_BACKOFF_MAX_MS", 8000)),
+            breaker_threshold: env_u64("LLM_BREAKER_THRESHOLD", 5) as u32,
+            breaker_cooldown: Duration::from_secs(env_u64("LLM_BREAKER_COOLDOWN_SECS", 30)),
+        }
+    }
+}
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+}
+
+/// Opens after `threshold` consecutive failures and lets a single trial request through
+/// once `cooldown` has elapsed (half-open). A success closes it again.
+struct CircuitBreaker {
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    fn new() -> Self {
+        CircuitBreaker {
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    fn allows_request(&self, cooldown: Duration) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state.opened_at {
+            None => true,
+            Some(opened_at) if opened_at.elapsed() >= cooldown => {
+                // Half-open: re-arm the timer so only this request gets through.
+                state.opened_at = Some(Instant::now());
+                true
+            }
+            Some(_) => false,
+        }
+    }
+
+    fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures = 0;
+        state.opened_at = None;
+    }
+
+    fn record_failure(&self, threshold: u32) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures += 1;
+        if state.consecutive_failures >= threshold {
+            state.opened_at = Some(Instant::now());
+        }
+    }
+}
+
+/// Something that can produce chat completions: the real provider chain or a test double.
+#[async_trait]
+pub trait LlmBackend: Send + Sync {
+    /// Sends a chat completions request body (without `model`).
+    async fn chat(&self, body: Value) -> Result<ChatCompletion, String>;
+
+    /// The models a request may be served by, in fallback order. Empty when unknown.
+    fn models(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+#[async_trait]
+pub trait EmbeddingBackend: Send + Sync {
+    async fn embed(&self, input: &str) -> Result<Vec<f32>, String>;
+}
+
+/// A successful chat completion along with the provider and model that produced it.
+#[derive(Debug, Clone)]
+pub struct ChatCompletion {
+    pub message: Value,
+    pub content: Option<String>,
+    pub usage: TokenUsage,
+    pub provider: String,
+    pub model: String,
+}
+
+enum AttemptError {
+    /// Worth retrying on the same provider, optionally after the server-requested delay.
+    Retryable(String, Option<Duration>),
+    /// The provider is unusable for this request; move on to the next one.
+    Provider(String),
+    /// The request itself is invalid and would fail on every provider.
+    Fatal(String),
+}
+
+/// Chat completions client with timeouts, retries, per-provider circuit breakers and an
+/// ordered fallback chain configured through `LLM_PROVIDERS` (e.g.
+/// `openai:gpt-4o-2024-11-20,openai:gpt-4o-mini`).
+pub struct LlmClient {
+    http: Client,
+    settings: LlmSettings,
+    providers: Vec<(LlmProvider, CircuitBreaker)>,
+    embedder: Option<(LlmProvider, CircuitBreaker)>,
+}
+
+impl LlmClient {
+    pub fn new(
+        http: Client,
+        settings: LlmSettings,
+        providers: Vec<LlmProvider>,
+        embedder: Option<LlmProvider>,
+    ) -> Self {
+        LlmClient {
+            http,
+            settings,
+            providers: providers
+                .into_iter()
+                .map(|provider| (provider, CircuitBreaker::new()))
+                .collect(),
+            embedder: embedder.map(|provider| (provider, CircuitBreaker::new())),
+        }
+    }
+
+    pub fn from_env(http: Client) -> Self {
+        let chain = env::var("LLM_PROVIDERS")
+            .unwrap_or_else(|_| "openai:gpt-4o-2024-11-20,openai:gpt-4o-mini".to_string());
+        let providers = chain
+            .split(',')
+            .filter_map(LlmProvider::from_spec)
+            .collect();
+        let embedder = LlmProvider::from_spec(
+            &env::var("EMBEDDING_MODEL")
+                .unwrap_or_else(|_| "openai:text-embedding-3-small".to_string()),
+        );
+        LlmClient::new(http, LlmSettings::from_env(), providers, embedder)
+    }
+
+    /// Exponential backoff for the given retry, capped at `backoff_max`.
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.settings
+            .backoff_base
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.settings.backoff_max)
+    }
+
+    async fn post_with_retries(
+        &self,
+        provider: &LlmProvider,
+        path: &str,
+        body: &Value,
+    ) -> Result<Value, AttemptError> {
+        let mut attempt = 0;
+        loop {
+            match self.send_once(provider, path, body).await {
+                Err(AttemptError::Retryable(err, retry_after))
+                    if attempt < self.settings.max_retries =>
+                {
+                    // A server that asks for more time than the backoff allows is given it
+                    // elsewhere: the provider counts as failed and the chain moves on.
+                    let delay = match retry_after {
+                        Some(delay) if delay > self.settings.backoff_max => {
+                            return Err(AttemptError::Provider(format!(
+                                "{} (asked to retry after {:?})",
+                                err, delay
+                            )));
+                        }
+                        Some(delay) => delay,
+                        None => self.backoff(attempt),
+                    };
+                    println!(
+                        "LLM request to {} failed ({}), retrying in {:?}",
+                        provider.name, err, delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn send_once(
+        &self,
+        provider: &LlmProvider,
+        path: &str,
+        body: &Value,
+    ) -> Result<Value, AttemptError> {
+        let mut body = body.clone();
+        body["model"] = Value::String(provider.model.clone());
+        provider.adapt_response_format(&mut body);
+
+        let mut request = self
+            .http
+            .post(format!("{}{}", provider.base_url, path))
+            .timeout(self.settings.request_timeout)
+            .json(&body);
+        if let Some(api_key) = &provider.api_key {
+            request = request.bearer_auth(api_key);
+        }
+
+        let response = request.send().await.map_err(|e| {
+            if e.is_timeout() || e.is_connect() || e.is_request() {
+                AttemptError::Retryable(format!("Request failed: {}", e), None)
+            } else {
+                AttemptError::Provider(format!("Request failed: {}", e))
+            }
+        })?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let retry_after = parse_retry_after(response.headers());
+            let text = response.text().await.unwrap_or_default();
+            let err = format!("{} {}", status, text);
+            return Err(match status {
+                StatusCode::TOO_MANY_REQUESTS
+                | StatusCode::REQUEST_TIMEOUT
+                | StatusCode::INTERNAL_SERVER_ERROR
+                | StatusCode::BAD_GATEWAY
+                | StatusCode::SERVICE_UNAVAILABLE
+                | StatusCode::GATEWAY_TIMEOUT => AttemptError::Retryable(err, retry_after),
+                StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => {
+                    AttemptError::Fatal(err)
+                }
+                _ => AttemptError::Provider(err),
+            });
+        }
+
+        response
+            .json()
+            .await
+            .map_err(|e| AttemptError::Retryable(format!("Failed to parse response: {}", e), None))
+    }
+}
+
+#[async_trait]
+impl LlmBackend for LlmClient {
+    /// Sends a chat completions request body (without `model`) through the fallback chain.
+    async fn chat(&self, body: Value) -> Result<ChatCompletion, String> {
+        let mut errors = Vec::new();
+
+        for (provider, breaker) in &self.providers {
+            if !breaker.allows_request(self.settings.breaker_cooldown) {
+                errors.push(format!(
+                    "{}/{}: circuit open",
+                    provider.name, provider.model
+                ));
+                continue;
+            }
+
+            let result = self
+                .post_with_retries(provider, "/chat/completions", &body)
+                .await
+                .and_then(|response_json| {
+                    parse_completion(provider, response_json)
+                        .ok_or_else(|| AttemptError::Provider("No message in response".to_string()))
+                });
+            match result {
+                Ok(completion) => {
+                    breaker.record_success();
+                    return Ok(completion);
+                }
+                Err(AttemptError::Fatal(err)) => return Err(err),
+                Err(AttemptError::Retryable(err, _)) | Err(AttemptError::Provider(err)) => {
+                    println!(
+                        "LLM provider {}/{} failed: {}",
+                        provider.name, provider.model, err
+                    );
+                    breaker.record_failure(self.settings.breaker_threshold);
+                    errors.push(format!("{}/{}: {}", provider.name, provider.model, err));
+                }
+            }
+        }
+
+        Err(format!("All LLM providers failed: {}", errors.join("; ")))
+    }
+
+    fn models(&self) -> Vec<String> {
+        self.providers
+            .iter()
+            .map(|(provider, _)| provider.model.clone())
+            .collect()
+    }
+}
+
+#[async_trait]
+impl EmbeddingBackend for LlmClient {
+    /// Embeds `input` with the model configured through `EMBEDDING_MODEL` (e.g.
+    /// `openai:text-embedding-3-small`).
+    async fn embed(&self, input: &str) -> Result<Vec<f32>, String> {
+        let (provider, breaker) = self
+            .embedder
+            .as_ref()
+            .ok_or_else(|| "No embedding provider configured".to_string())?;
+        if !breaker.allows_request(self.settings.breaker_cooldown) {
+            return Err(format!(
+                "{}/{}: circuit open",
+                provider.name, provider.model
+            ));
+        }
+
+        let body = json!({ "input": input });
+        let response_json = match self.post_with_retries(provider, "/embeddings", &body).await {
+            Ok(response_json) => {
+                breaker.record_success();
+                response_json
+            }
+            Err(AttemptError::Fatal(err)) => return Err(err),
+            Err(AttemptError::Retryable(err, _)) | Err(AttemptError::Provider(err)) => {
+                breaker.record_failure(self.settings.breaker_threshold);
+                return Err(format!("{}/{}: {}", provider.name, provider.model, err));
+            }
+        };
+
+        serde_json::from_value(response_json["data"][0]["embedding"].clone())
+            .map_err(|e| format!("Failed to parse embedding: {}", e))
+    }
+}
+
+fn parse_completion(provider: &LlmProvider, response_json: Value) -> Option<ChatCompletion> {
+    let message = response_json["choices"][0]["message"].clone();
+    if message.is_null() {
+        return None;
+    }
+
+    Some(ChatCompletion {
+        content: message["content"].as_str().map(String::from),
+        message,
+        usage: serde_json::from_value(response_json["usage"].clone()).unwrap_or_default(),
+        provider: provider.name.clone(),
+        model: response_json["model"]
+            .as_str()
+            .unwrap_or(&provider.model)
+            .to_string(),
+    })
+}
+
+/// Reads a `Retry-After` header given either in seconds or as an HTTP date.
+fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<f64>() {
+        return Duration::try_from_secs_f64(seconds.max(0.0)).ok();
+    }
+    let at = DateTime::parse_from_rfc2822(value)
+        .ok()?
+        .with_timezone(&Utc);
+    (at - Utc::now()).to_std().ok()
+}
@@ -1,10 +1,174 @@
+pub mod audit;
+pub mod channels;
+pub mod context;
+pub mod database;
+pub mod intent;
+pub mod jwt;
+pub mod knowledge;
+pub mod llm;
+pub mod models;
+pub mod moderation;
+pub mod notifications;
 pub mod pinecone;
+pub mod planning;
+pub mod postgres;
+pub mod privacy;
+pub mod quota;
+pub mod rate_limit;
+pub mod realtime;
+pub mod repository;
+pub mod structured;
 pub mod supabase;
+pub mod usage;
+pub mod vector_store;
 
+use crate::actors::tools::ToolRegistry;
+use channels::{channels_from_env, NotificationChannel};
+use context::ContextTable;
+use database::Database;
+use intent::IntentRouter;
+use llm::{EmbeddingBackend, LlmBackend, LlmClient};
+use moderation::{ModerationClassifier, Moderator, OpenAiModeration};
+use notifications::NotificationHub;
+use pinecone::PineconeStore;
+use postgres::PostgresService;
+use quota::QuotaTracker;
+use rate_limit::RateLimiter;
+use reqwest::Client;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use supabase::SupabaseService;
 use supabase_rs::SupabaseClient;
+use usage::PriceTable;
+use vector_store::VectorStore;
+
+/// The external systems the app talks to. Tests swap these for in-memory doubles.
+#[derive(Clone)]
+pub struct Backends {
+    pub llm: Arc<dyn LlmBackend>,
+    pub embeddings: Arc<dyn EmbeddingBackend>,
+    pub vectors: Option<Arc<dyn VectorStore>>,
+    pub db: Option<Arc<dyn Database>>,
+    pub channels: Vec<Arc<dyn NotificationChannel>>,
+    pub moderation: Option<Arc<dyn ModerationClassifier>>, // Runs alongside the local rules
+}
+
+/// Shared services handed to the HTTP layer and to every actor.
+#[derive(Clone)]
+pub struct Services {
+    pub http: Client,
+    pub llm: Arc<dyn LlmBackend>,
+    pub embeddings: Arc<dyn EmbeddingBackend>,
+    pub user_limiter: Arc<RateLimiter>,
+    pub ip_limiter: Arc<RateLimiter>,
+    pub quota: Arc<QuotaTracker>,
+    pub prices: Arc<PriceTable>,
+    pub context: Arc<ContextTable>,
+    pub db: Option<Arc<dyn Database>>, // Service role, for system jobs; see `user_db`
+    pub vectors: Option<Arc<dyn VectorStore>>,
+    pub tools: Arc<ToolRegistry>,
+    pub router: Arc<IntentRouter>,
+    pub notifications: Arc<NotificationHub>,
+    pub channels: Vec<Arc<dyn NotificationChannel>>,
+    pub moderator: Arc<Moderator>,
+}
+
+impl Services {
+    pub fn new(http: Client, backends: Backends) -> Self {
+        Services {
+            http,
+            llm: backends.llm,
+            embeddings: backends.embeddings,
+            user_limiter: Arc::new(RateLimiter::from_env("RATE_LIMIT_USER", 10, 20)),
+            ip_limiter: Arc::new(RateLimiter::from_env("RATE_LIMIT_IP", 30, 60)),
+            quota: Arc::new(QuotaTracker::from_env()),
+            prices: Arc::new(PriceTable::from_env()),
+            context: Arc::new(ContextTable::from_env()),
+            db: backends.db,
+            vectors: backends.vectors,
+            tools: Arc::new(ToolRegistry::default()),
+            router: Arc::new(IntentRouter::new()),
+            notifications: Arc::new(NotificationHub::new()),
+            channels: backends.channels,
+            moderator: Arc::new(Moderator::new(backends.moderation)),
+        }
+    }
+
+    /// The database as the user holding `access_token` sees it, so row-level security
+    /// protects requests made on their behalf. Fails rather than falling back to the
+    /// service role.
+    pub fn user_db(&self, access_token: &str) -> Result<Arc<dyn Database>, String> {
+        self.db
+            .as_ref()
+            .ok_or_else(|| "Database is not configured".to_string())?
+            .for_user(access_token)
+    }
+
+    pub fn from_env() -> Self {
+        let connect_timeout = env::var("HTTP_CONNECT_TIMEOUT_SECS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(5);
+        let http = Client::builder()
+            .connect_timeout(Duration::from_secs(connect_timeout))
+            .build()
+            .expect("Failed to build HTTP client");
+
+        let llm = Arc::new(LlmClient::from_env(http.clone()));
+        let backends = Backends {
+            llm: llm.clone(),
+            embeddings: llm,
+            vectors: PineconeStore::from_env(http.clone())
+                .map_err(|e| println!("Warning: vector store disabled: {}", e))
+                .ok()
+                .map(|store| Arc::new(store) as Arc<dyn VectorStore>),
+            db: database_from_env(http.clone()),
+            channels: channels_from_env(),
+            moderation: moderation_from_env(http.clone()),
+        };
+        Services::new(http, backends)
+    }
+}
+
+/// Direct Postgres when `DATABASE_URL` is set, otherwise Supabase's REST API.
+fn database_from_env(http: Client) -> Option<Arc<dyn Database>> {
+    if env::var("DATABASE_URL").is_ok() {
+        return PostgresService::new()
+            .map_err(|e| println!("Warning: Postgres disabled: {}", e))
+            .ok()
+            .map(|service| Arc::new(service) as Arc<dyn Database>);
+    }
+    SupabaseService::from_env(http)
+        .map_err(|e| println!("Warning: Supabase disabled: {}", e))
+        .ok()
+        .map(|service| Arc::new(service) as Arc<dyn Database>)
+}
+
+/// The provider classifier named by `MODERATION_PROVIDER`; unset means local rules only.
+fn moderation_from_env(http: Client) -> Option<Arc<dyn ModerationClassifier>> {
+    match env::var("MODERATION_PROVIDER").ok()?.as_str() {
+        "openai" => OpenAiModeration::from_env(http)
+            .map_err(|e| println!("Warning: OpenAI moderation disabled: {}", e))
+            .ok()
+            .map(|classifier| Arc::new(classifier) as Arc<dyn ModerationClassifier>),
+        "rules" | "" => None,
+        other => {
+            println!(
+                "Warning: unknown MODERATION_PROVIDER {}, using local rules",
+                other
+            );
+            None
+        }
+    }
+}
 
 pub fn init_supabase() -> Result<SupabaseClient, String> {
-    supabase::SupabaseService::new().map(|service| service.get_client().clone())
+    let supabase_url = env::var("SUPABASE_URL")
+        .map_err(|_| "SUPABASE_URL environment variable not set".to_string())?;
+    let supabase_key = env::var("SUPABASE_KEY")
+        .map_err(|_| "SUPABASE_KEY environment variable not set".to_string())?;
+    SupabaseClient::new(supabase_url, supabase_key).map_err(|e| e.to_string())
 }
 
 pub async fn init_pinecone() -> Result<(), String> {
@@ -0,0 +1,434 @@
+//! Rows of the tables defined in `supabase/migrations`, one struct per table. Optional fields
+//! are left out when serializing so inserts fall back to the column defaults.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+/// A table the repository can read and write. `COLUMNS` lists every column the code relies
+/// on; the startup schema check selects exactly these.
+pub trait Table {
+    const NAME: &'static str;
+    const COLUMNS: &'static [&'static str];
+    /// The primary key, used to match rows on upsert.
+    const KEY: &'static str = "id";
+}
+
+/// Every table the code uses with the columns it expects, for schema checks.
+pub const TABLES: &[(&str, &[&str])] = &[
+    (User::NAME, User::COLUMNS),
+    (AiAgent::NAME, AiAgent::COLUMNS),
+    (Goal::NAME, Goal::COLUMNS),
+    (Task::NAME, Task::COLUMNS),
+    (Interaction::NAME, Interaction::COLUMNS),
+    (ActorStateRow::NAME, ActorStateRow::COLUMNS),
+    (HistoricalInteraction::NAME, HistoricalInteraction::COLUMNS),
+    (Notification::NAME, Notification::COLUMNS),
+    (
+        NotificationPreferencesRow::NAME,
+        NotificationPreferencesRow::COLUMNS,
+    ),
+    (KnowledgeDocument::NAME, KnowledgeDocument::COLUMNS),
+    (AccountErasure::NAME, AccountErasure::COLUMNS),
+    (AuditEntry::NAME, AuditEntry::COLUMNS),
+    (LlmUsage::NAME, LlmUsage::COLUMNS),
+    (UserQuota::NAME, UserQuota::COLUMNS),
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(default = "default_level")]
+    pub level: i32,
+    #[serde(default)]
+    pub total_xp: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_active_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl Table for User {
+    const NAME: &'static str = "users";
+    const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "display_name",
+        "level",
+        "total_xp",
+        "last_active_at",
+        "created_at",
+    ];
+}
+
+/// A coach. Seeded templates have no `user_id`; personal actors belong to one user.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AiAgent {
+    pub id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<Uuid>,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub personality: Option<String>, // stern, empathetic or balanced
+    pub specialty: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avatar_url: Option<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub goals: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub knowledge_base: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl Table for AiAgent {
+    const NAME: &'static str = "ai_agents";
+    const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "user_id",
+        "name",
+        "personality",
+        "specialty",
+        "avatar_url",
+        "goals",
+        "knowledge_base",
+        "created_at",
+    ];
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Goal {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub category: String,
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub xp: i32,
+    #[serde(default = "default_level")]
+    pub level: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>, // active or paused
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub agent_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl Table for Goal {
+    const NAME: &'static str = "goals";
+    const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "user_id",
+        "category",
+        "title",
+        "description",
+        "xp",
+        "level",
+        "status",
+        "agent_id",
+        "created_at",
+    ];
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Task {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Uuid>, // Generated by the database on insert
+    pub goal_id: Uuid,
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub xp_reward: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<i32>, // Minutes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<String>, // high, medium or low
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>, // pending, in_progress, completed or delegated_to_ai
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ai_assignable: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+impl Table for Task {
+    const NAME: &'static str = "tasks";
+    const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "goal_id",
+        "title",
+        "description",
+        "xp_reward",
+        "duration",
+        "priority",
+        "status",
+        "ai_assignable",
+        "created_at",
+        "completed_at",
+    ];
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Uuid>,
+    pub actor_id: Uuid,
+    pub interaction_data: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl Table for Interaction {
+    const NAME: &'static str = "interactions";
+    const COLUMNS: &'static [&'static str] = &["id", "actor_id", "interaction_data", "created_at"];
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActorStateRow {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Uuid>,
+    pub actor_id: Uuid,
+    pub state_data: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl Table for ActorStateRow {
+    const NAME: &'static str = "actor_states";
+    const COLUMNS: &'static [&'static str] =
+        &["id", "actor_id", "state_data", "created_at", "updated_at"];
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoricalInteraction {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Uuid>,
+    pub actor_id: Uuid,
+    pub data: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl Table for HistoricalInteraction {
+    const NAME: &'static str = "historical_interactions";
+    const COLUMNS: &'static [&'static str] = &["id", "actor_id", "data", "created_at"];
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Notification {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Uuid>,
+    pub user_id: Uuid,
+    pub title: String,
+    pub body: String,
+    pub kind: String, // broadcast, reminder or actor
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actor_id: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub data: Value,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub channel_deliveries: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delivered_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl Table for Notification {
+    const NAME: &'static str = "notifications";
+    const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "user_id",
+        "title",
+        "body",
+        "kind",
+        "actor_id",
+        "data",
+        "channel_deliveries",
+        "delivered_at",
+        "read_at",
+        "created_at",
+    ];
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationPreferencesRow {
+    pub user_id: Uuid,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub channels: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub push_subscription: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webhook_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl Table for NotificationPreferencesRow {
+    const NAME: &'static str = "notification_preferences";
+    const KEY: &'static str = "user_id";
+    const COLUMNS: &'static [&'static str] = &[
+        "user_id",
+        "channels",
+        "email",
+        "push_subscription",
+        "webhook_url",
+        "updated_at",
+    ];
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnowledgeDocument {
+    pub id: Uuid,
+    pub actor_id: Uuid,
+    pub user_id: Uuid,
+    pub title: String,
+    pub format: String, // text, markdown, html or pdf
+    pub size_bytes: i64,
+    pub chunk_count: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl Table for KnowledgeDocument {
+    const NAME: &'static str = "knowledge_documents";
+    const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "actor_id",
+        "user_id",
+        "title",
+        "format",
+        "size_bytes",
+        "chunk_count",
+        "created_at",
+        "updated_at",
+    ];
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountErasure {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Uuid>,
+    pub user_id: Uuid,
+    pub summary: Value, // What was erased, e.g. counts of actors, goals and tasks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub erased_at: Option<DateTime<Utc>>,
+}
+
+impl Table for AccountErasure {
+    const NAME: &'static str = "account_erasures";
+    const COLUMNS: &'static [&'static str] = &["id", "user_id", "summary", "erased_at"];
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Uuid>,
+    pub action: String, // e.g. actor.create, admin.request, user.export
+    pub role: String,   // user, admin or system
+    pub performed_by: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub details: Value,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl Table for AuditEntry {
+    const NAME: &'static str = "audit_log";
+    const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "action",
+        "role",
+        "performed_by",
+        "target_type",
+        "target_id",
+        "details",
+        "ip",
+        "user_agent",
+        "created_at",
+    ];
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LlmUsage {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Uuid>,
+    pub user_id: Uuid,
+    pub actor_id: Option<Uuid>,
+    pub interaction_id: Option<Uuid>,
+    pub purpose: String, // interaction, background, routing, planning or huddle
+    pub provider: String,
+    pub model: String,
+    pub prompt_tokens: i32,
+    pub completion_tokens: i32,
+    pub total_tokens: i32,
+    pub cost_usd: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl Table for LlmUsage {
+    const NAME: &'static str = "llm_usage";
+    const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "user_id",
+        "actor_id",
+        "interaction_id",
+        "purpose",
+        "provider",
+        "model",
+        "prompt_tokens",
+        "completion_tokens",
+        "total_tokens",
+        "cost_usd",
+        "created_at",
+    ];
+}
+
+fn default_level() -> i32 {
+    1
+}
+
+/// Reads a JSON `null` as the type's default, for array columns that may hold NULL.
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserQuota {
+    pub user_id: Uuid,
+    pub plan: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reset_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl Table for UserQuota {
+    const NAME: &'static str = "user_quotas";
+    const KEY: &'static str = "user_id";
+    const COLUMNS: &'static [&'static str] = &["user_id", "plan", "reset_at", "updated_at"];
+}
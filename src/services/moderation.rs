@@ -0,0 +1,334 @@
+use async_trait::async_trait;
+use futures::future::join_all;
+use regex::Regex;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::env;
+use std::sync::{Arc, LazyLock};
+
+/// Shortest system prompt sentence treated as leaked when it shows up verbatim in a reply.
+const MIN_LEAKED_SENTENCE_CHARS: usize = 40;
+
+pub const SELF_HARM_RESPONSE: &str = "I'm really sorry you're feeling this way, and I'm glad you told me. \
+You don't have to go through this alone. If you might act on these thoughts or are in danger, please \
+call your local emergency number now. In the US you can call or text 988 to reach the Suicide & Crisis \
+Lifeline, and you can find a helpline in your country at https://findahelpline.com. Talking to someone \
+you trust can help too. I'm here to keep talking whenever you want.";
+
+pub const MEDICAL_EMERGENCY_RESPONSE: &str = "This sounds like it could be a medical emergency. Please \
+call your local emergency number (911 in the US, 112 in Europe) or get to the nearest emergency room \
+right away. I'm a coach, not a medical professional, so I can't help with urgent care, but I'll be \
+here when you're safe.";
+
+pub const PROMPT_INJECTION_RESPONSE: &str = "I can't share or change how I've been set up, \
+but I'm happy to keep helping with your goals. What would you like to work on?";
+
+pub const BLOCKED_RESPONSE: &str = "I'm not able to help with that. Let's get back to your goals. \
+What would you like to work on next?";
+
+// Hurting or cutting oneself only counts with intent around it, so "I hurt myself deadlifting"
+// or "cut myself some slack" is not a crisis.
+static SELF_HARM: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)\b(kill(ing)? myself|suicid(e|al)|end(ing)? my life|end it all|want(ed)? to die|(don'?t|do not) want to (live|be alive|wake up)|self[- ]?harm(ing)?|better off dead|(want(ed)?|going|plan(ning)?|urges?|tempted|trying) to (hurt|harm) myself|thinking (about|of) (hurting|harming|cutting) myself|(hurt|harm|cut)(ing)? myself on purpose)\b|\b(want(ed)?|going|plan(ning)?|urges?|tempted|trying) to cut myself\s*([.!?,]|$)",
+    )
+    .unwrap()
+});
+static MEDICAL_EMERGENCY: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)\b(chest pains?|heart attack|(can'?t|cannot|can not|struggling to) breathe|trouble breathing|having a stroke|seizure|unconscious|won'?t wake up|bleeding (heavily|badly|a lot)|severe bleeding|overdos(e|ed|ing)|anaphyla\w*|took too many pills)\b",
+    )
+    .unwrap()
+});
+// Requests to reveal rules or instructions must target the assistant's own setup, so "what are
+// the rules of intermittent fasting" passes.
+static PROMPT_INJECTION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)\b(ignore|disregard|forget|override)\b[^.!?\n]{0,40}\b(previous|prior|above|earlier|all|your|the)\b[^.!?\n]{0,20}\b(instructions?|prompts?|rules|directions|guidelines)\b|\b(reveal|show|print|output|repeat|recite|tell me|what (is|are|were))\b[^.!?\n]{0,30}\b(system prompt|your (initial |original |hidden )?(instructions|prompt|rules)|(your|the) (initial|original|hidden|system) (instructions|prompt|rules))\b|\byou are now\b|\bdeveloper mode\b|\bjailbreak",
+    )
+    .unwrap()
+});
+static SENTENCE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[^.!?\n]+[.!?]*").unwrap());
+
+/// What a classifier found in a piece of text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationCategory {
+    SelfHarm,
+    MedicalEmergency,
+    PromptInjection,
+    Violence,
+    Sexual,
+    Hate,
+}
+
+/// A category flagged on the way in (`input`) or out (`output`) of the model.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModerationFlag {
+    pub category: ModerationCategory,
+    pub stage: String,
+}
+
+/// Labels text with moderation categories.
+#[async_trait]
+pub trait ModerationClassifier: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn classify(&self, text: &str) -> Result<Vec<ModerationCategory>, String>;
+}
+
+/// Keyword rules for crisis language and prompt-injection phrasing. Always on, since it needs
+/// no network and catches the cases where a safe reply matters most.
+pub struct RuleClassifier;
+
+#[async_trait]
+impl ModerationClassifier for RuleClassifier {
+    fn name(&self) -> &'static str {
+        "rules"
+    }
+
+    async fn classify(&self, text: &str) -> Result<Vec<ModerationCategory>, String> {
+        let mut categories = Vec::new();
+        if SELF_HARM.is_match(text) {
+            categories.push(ModerationCategory::SelfHarm);
+        }
+        if MEDICAL_EMERGENCY.is_match(text) {
+            categories.push(ModerationCategory::MedicalEmergency);
+        }
+        if PROMPT_INJECTION.is_match(text) {
+            categories.push(ModerationCategory::PromptInjection);
+        }
+        Ok(categories)
+    }
+}
+
+/// OpenAI's moderation endpoint, enabled with `MODERATION_PROVIDER=openai`. Uses
+/// `OPENAI_BASE_URL` and `OPENAI_API_KEY` like the chat provider.
+pub struct OpenAiModeration {
+    http: Client,
+    base_url: String,
+    api_key: String,
+    model: String,
+}
+
+impl OpenAiModeration {
+    pub fn from_env(http: Client) -> Result<Self, String> {
+        let api_key = env::var("OPENAI_API_KEY")
+            .map_err(|_| "OPENAI_API_KEY environment variable not set".to_string())?;
+        let base_url =
+            env::var("OPENAI_BASE_URL").unwrap_or_else(|_| "https://api.openai.com/v1".to_string());
+        Ok(OpenAiModeration {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+            model: env::var("MODERATION_MODEL")
+                .unwrap_or_else(|_| "omni-moderation-latest".to_string()),
+        })
+    }
+}
+
+#[async_trait]
+impl ModerationClassifier for OpenAiModeration {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    async fn classify(&self, text: &str) -> Result<Vec<ModerationCategory>, String> {
+        let response = self
+            .http
+            .post(format!("{}/moderations", self.base_url))
+            .bearer_auth(&self.api_key)
+            .json(&json!({ "model": self.model, "input": text }))
+            .send()
+            .await
+            .map_err(|e| format!("Moderation request failed: {}", e))?;
+        if !response.status().is_success() {
+            return Err(format!(
+                "Moderation request failed: {}",
+                response.text().await.unwrap_or_default()
+            ));
+        }
+        let body: Value = response
+            .json()
+            .await
+            .map_err(|e| format!("Failed to parse moderation response: {}", e))?;
+
+        let mut categories = Vec::new();
+        let flagged = body["results"][0]["categories"]
+            .as_object()
+            .cloned()
+            .unwrap_or_default();
+        for (name, value) in flagged {
+            if value.as_bool() != Some(true) {
+                continue;
+            }
+            let category = match name.split(['/', '-']).next().unwrap_or_default() {
+                "self" => ModerationCategory::SelfHarm,
+                "violence" | "illicit" => ModerationCategory::Violence,
+                "sexual" => ModerationCategory::Sexual,
+                "hate" | "harassment" => ModerationCategory::Hate,
+                _ => continue,
+            };
+            if !categories.contains(&category) {
+                categories.push(category);
+            }
+        }
+        Ok(categories)
+    }
+}
+
+/// The result of screening a user query before it reaches the model.
+pub struct InputScreen {
+    pub query: String, // The query with prompt-injection sentences removed
+    pub flags: Vec<ModerationFlag>,
+    pub safe_response: Option<String>, // Set when the model must not be called
+}
+
+/// The result of screening a model reply before it reaches the user.
+pub struct OutputScreen {
+    pub response: String,
+    pub flags: Vec<ModerationFlag>,
+}
+
+/// Runs every configured classifier around a model call.
+pub struct Moderator {
+    classifiers: Vec<Arc<dyn ModerationClassifier>>,
+}
+
+impl Moderator {
+    /// Local rules plus, when given, a provider classifier.
+    pub fn new(provider: Option<Arc<dyn ModerationClassifier>>) -> Self {
+        let mut classifiers: Vec<Arc<dyn ModerationClassifier>> = vec![Arc::new(RuleClassifier)];
+        classifiers.extend(provider);
+        Moderator { classifiers }
+    }
+
+    /// Categories found by any classifier. A failing classifier is logged and skipped so an
+    /// outage at the provider doesn't take coaching down; the local rules still apply.
+    async fn classify(&self, text: &str) -> Vec<ModerationCategory> {
+        let results = join_all(
+            self.classifiers
+                .iter()
+                .map(|classifier| classifier.classify(text)),
+        )
+        .await;
+
+        let mut categories = Vec::new();
+        for (classifier, result) in self.classifiers.iter().zip(results) {
+            match result {
+                Ok(found) => categories.extend(found),
+                Err(e) => println!("Warning: {} moderation failed: {}", classifier.name(), e),
+            }
+        }
+        categories.sort_by_key(|category| *category as u8);
+        categories.dedup();
+        categories
+    }
+
+    pub async fn screen_input(&self, query: &str) -> InputScreen {
+        let categories = self.classify(query).await;
+        let flags = flags(&categories, "input");
+
+        let safe_response = if categories.contains(&ModerationCategory::SelfHarm) {
+            Some(SELF_HARM_RESPONSE)
+        } else if categories.contains(&ModerationCategory::MedicalEmergency) {
+            Some(MEDICAL_EMERGENCY_RESPONSE)
+        } else if categories.iter().any(|category| {
+            matches!(
+                category,
+                ModerationCategory::Violence
+                    | ModerationCategory::Sexual
+                    | ModerationCategory::Hate
+            )
+        }) {
+            Some(BLOCKED_RESPONSE)
+        } else {
+            None
+        };
+
+        let cleaned = strip_injection(query);
+        let safe_response = safe_response.or_else(|| {
+            // Nothing left to answer once the injection attempt is removed.
+            cleaned
+                .trim()
+                .is_empty()
+                .then_some(PROMPT_INJECTION_RESPONSE)
+        });
+
+        InputScreen {
+            query: cleaned,
+            flags,
+            safe_response: safe_response.map(str::to_string),
+        }
+    }
+
+    /// Replaces harmful replies and redacts any part of `system_prompt` the model repeated.
+    pub async fn screen_output(&self, response: &str, system_prompt: &str) -> OutputScreen {
+        let mut categories: Vec<ModerationCategory> = self
+            .classify(response)
+            .await
+            .into_iter()
+            .filter(|category| {
+                // Replies that point to crisis resources or emergency care read like the input
+                // categories, so only harmful content is blocked on the way out.
+                matches!(
+                    category,
+                    ModerationCategory::Violence
+                        | ModerationCategory::Sexual
+                        | ModerationCategory::Hate
+                )
+            })
+            .collect();
+
+        let mut response = response.to_string();
+        if !categories.is_empty() {
+            response = BLOCKED_RESPONSE.to_string();
+        } else if let Some(redacted) = redact_leaks(&response, system_prompt) {
+            categories.push(ModerationCategory::PromptInjection);
+            response = redacted;
+        }
+
+        OutputScreen {
+            response,
+            flags: flags(&categories, "output"),
+        }
+    }
+}
+
+fn flags(categories: &[ModerationCategory], stage: &str) -> Vec<ModerationFlag> {
+    categories
+        .iter()
+        .map(|category| ModerationFlag {
+            category: *category,
+            stage: stage.to_string(),
+        })
+        .collect()
+}
+
+/// Removes sentences that try to override or extract the system prompt. Used on user queries
+/// and on knowledge text before either is placed in a prompt.
+pub fn strip_injection(text: &str) -> String {
+    if !PROMPT_INJECTION.is_match(text) {
+        return text.to_string();
+    }
+    SENTENCE
+        .find_iter(text)
+        .map(|sentence| sentence.as_str().trim())
+        .filter(|sentence| !PROMPT_INJECTION.is_match(sentence))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Returns `response` with every long system prompt sentence it repeats replaced by
+/// `[redacted]`, or `None` when nothing leaked.
+fn redact_leaks(response: &str, system_prompt: &str) -> Option<String> {
+    let mut redacted = response.to_string();
+    for sentence in SENTENCE.find_iter(system_prompt) {
+        let sentence = sentence.as_str().trim();
+        if sentence.chars().count() >= MIN_LEAKED_SENTENCE_CHARS && redacted.contains(sentence) {
+            redacted = redacted.replace(sentence, "[redacted]");
+        }
+    }
+    (redacted != response).then_some(redacted)
+}
@@ -0,0 +1,243 @@
+use crate::services::channels::NotificationPreferences;
+use crate::services::database::Database;
+use crate::services::Services;
+use chrono::{DateTime, Duration, Utc};
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+/// Selects users by level, goal category or inactivity. Every criterion that is set must match.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Segment {
+    pub min_level: Option<i64>,
+    pub max_level: Option<i64>,
+    pub goal_category: Option<String>,
+    pub inactive_days: Option<i64>, // Users with no interaction in at least this many days
+}
+
+impl Segment {
+    fn matches(&self, user: &Value, goals: &[Value], now: DateTime<Utc>) -> bool {
+        let level = user["level"].as_i64().unwrap_or(1);
+        if self.min_level.is_some_and(|min| level < min)
+            || self.max_level.is_some_and(|max| level > max)
+        {
+            return false;
+        }
+        if let Some(category) = &self.goal_category {
+            let category = category.to_lowercase();
+            if !goals.iter().any(|goal| {
+                goal["category"].as_str().map(str::to_lowercase) == Some(category.clone())
+            }) {
+                return false;
+            }
+        }
+        if let Some(days) = self.inactive_days {
+            let last_active = user["last_active_at"]
+                .as_str()
+                .and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+                .map(|at| at.with_timezone(&Utc));
+            if last_active.is_some_and(|at| at > now - Duration::days(days)) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Returns the ids of the users matching `segment`, out of `user_ids` or, when that is empty,
+/// out of every user.
+pub async fn resolve_recipients(
+    db: &dyn Database,
+    user_ids: &[String],
+    segment: Option<&Segment>,
+) -> Result<Vec<String>, String> {
+    let Some(segment) = segment else {
+        if !user_ids.is_empty() {
+            return Ok(user_ids.to_vec());
+        }
+        return Ok(db
+            .list_users()
+            .await?
+            .iter()
+            .filter_map(|user| user["id"].as_str().map(str::to_string))
+            .collect());
+    };
+
+    let users = db.list_users().await?;
+    let now = Utc::now();
+    let mut recipients = Vec::new();
+    for user in users {
+        let Some(user_id) = user["id"].as_str() else {
+            continue;
+        };
+        if !user_ids.is_empty() && !user_ids.iter().any(|id| id == user_id) {
+            continue;
+        }
+        let goals = match segment.goal_category {
+            Some(_) => db.list_goals(user_id).await?,
+            None => Vec::new(),
+        };
+        if segment.matches(&user, &goals, now) {
+            recipients.push(user_id.to_string());
+        }
+    }
+    Ok(recipients)
+}
+
+/// A notification to store in a user's inbox and push to their connected clients.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewNotification {
+    pub user_id: String,
+    pub title: String,
+    pub body: String,
+    pub kind: String, // "broadcast", "reminder" or "actor"
+    pub actor_id: Option<String>,
+    pub data: Value,
+}
+
+/// Live connections of users waiting for notifications.
+pub struct NotificationHub {
+    subscribers: Mutex<HashMap<String, Vec<UnboundedSender<Value>>>>,
+}
+
+impl NotificationHub {
+    pub fn new() -> Self {
+        NotificationHub {
+            subscribers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn subscribe(&self, user_id: &str) -> UnboundedReceiver<Value> {
+        let (sender, receiver) = unbounded_channel();
+        self.subscribers
+            .lock()
+            .unwrap()
+            .entry(user_id.to_string())
+            .or_default()
+            .push(sender);
+        receiver
+    }
+
+    /// Sends the notification to every open connection of the user. Returns whether at least
+    /// one connection received it.
+    pub fn publish(&self, user_id: &str, notification: &Value) -> bool {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let Some(senders) = subscribers.get_mut(user_id) else {
+            return false;
+        };
+        senders.retain(|sender| sender.send(notification.clone()).is_ok());
+        let delivered = !senders.is_empty();
+        if !delivered {
+            subscribers.remove(user_id);
+        }
+        delivered
+    }
+}
+
+impl Default for NotificationHub {
+    fn default() -> Self {
+        NotificationHub::new()
+    }
+}
+
+/// Stores a notification in the user's inbox and delivers it to their connected clients.
+/// Returns the stored row.
+pub async fn send_notification(
+    services: &Services,
+    notification: NewNotification,
+) -> Result<Value, String> {
+    let db = services
+        .db
+        .clone()
+        .ok_or_else(|| "Database is not configured".to_string())?;
+
+    let mut row = json!({
+        "user_id": notification.user_id,
+        "title": notification.title,
+        "body": notification.body,
+        "kind": notification.kind,
+        "actor_id": notification.actor_id,
+        "data": notification.data,
+        "created_at": Utc::now().to_rfc3339(),
+    });
+    let id = db.add_notification(row.clone()).await?;
+    row["id"] = json!(id);
+
+    if services.notifications.publish(&notification.user_id, &row) {
+        let delivered_at = Utc::now().to_rfc3339();
+        db.update_notification(
+            &notification.user_id,
+            &id,
+            json!({ "delivered_at": delivered_at }),
+        )
+        .await?;
+        row["delivered_at"] = json!(delivered_at);
+    }
+
+    let deliveries = deliver_to_channels(services, db.as_ref(), &notification.user_id, &row).await;
+    if !deliveries.is_empty() {
+        let deliveries = json!(deliveries);
+        db.update_notification(
+            &notification.user_id,
+            &id,
+            json!({ "channel_deliveries": deliveries }),
+        )
+        .await?;
+        row["channel_deliveries"] = deliveries;
+    }
+    Ok(row)
+}
+
+/// Sends the notification through every outbound channel the user has enabled. Returns the
+/// outcome per channel: "sent" or the error.
+async fn deliver_to_channels(
+    services: &Services,
+    db: &dyn Database,
+    user_id: &str,
+    notification: &Value,
+) -> HashMap<&'static str, String> {
+    let preferences: NotificationPreferences = match db.get_notification_preferences(user_id).await
+    {
+        Ok(Some(row)) => serde_json::from_value(row).unwrap_or_default(),
+        Ok(None) => return HashMap::new(),
+        Err(e) => {
+            println!("Warning: failed to load notification preferences: {}", e);
+            return HashMap::new();
+        }
+    };
+
+    let channels: Vec<_> = services
+        .channels
+        .iter()
+        .filter(|channel| preferences.is_enabled(channel.name()))
+        .collect();
+    let results = join_all(
+        channels
+            .iter()
+            .map(|channel| channel.deliver(&preferences, notification)),
+    )
+    .await;
+
+    channels
+        .into_iter()
+        .zip(results)
+        .map(|(channel, result)| {
+            let outcome = match result {
+                Ok(()) => "sent".to_string(),
+                Err(e) => {
+                    println!(
+                        "Warning: {} delivery to {} failed: {}",
+                        channel.name(),
+                        user_id,
+                        e
+                    );
+                    e
+                }
+            };
+            (channel.name(), outcome)
+        })
+        .collect()
+}
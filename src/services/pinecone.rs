@@ -1,5 +1,7 @@
+use crate::services::vector_store::{VectorMatch, VectorStore};
+use async_trait::async_trait;
 use reqwest::Client;
-use serde_json::Value;
+use serde_json::{json, Value};
 
 pub async fn init_pinecone() -> Result<(), String> {
     let pinecone_api_key = std::env::var("PINECONE_API_KEY")
@@ -35,7 +37,7 @@ pub async fn test_pinecone_connection() -> Result<(), String> {
 
     let client = Client::new();
     let response = client
-        .get(&format!("{}/describe_index_stats", pinecone_index_url))
+        .get(format!("{}/describe_index_stats", pinecone_index_url))
         .bearer_auth(&pinecone_api_key)
         .send()
         .await
@@ -55,3 +57,220 @@ pub async fn test_pinecone_connection() -> Result<(), String> {
         ))
     }
 }
+
+/// How many vectors one `vectors/fetch` request asks for, keeping the URL short.
+const FETCH_BATCH: usize = 50;
+
+/// Most ids Pinecone accepts in one delete request.
+const DELETE_BATCH: usize = 1000;
+
+/// Data-plane client for the Pinecone index at `PINECONE_INDEX_URL`.
+pub struct PineconeStore {
+    client: Client,
+    api_key: String,
+    index_url: String,
+}
+
+impl PineconeStore {
+    pub fn from_env(client: Client) -> Result<Self, String> {
+        let api_key = std::env::var("PINECONE_API_KEY")
+            .map_err(|_| "PINECONE_API_KEY environment variable not set".to_string())?;
+        let index_url = std::env::var("PINECONE_INDEX_URL")
+            .map_err(|_| "PINECONE_INDEX_URL environment variable not set".to_string())?;
+
+        Ok(PineconeStore {
+            client,
+            api_key,
+            index_url: index_url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    async fn get(&self, path: &str, params: &[(&str, String)]) -> Result<Value, String> {
+        let response = self
+            .client
+            .get(format!("{}/{}", self.index_url, path))
+            .header("Api-Key", &self.api_key)
+            .query(params)
+            .send()
+            .await
+            .map_err(|e| format!("Request failed: {}", e))?;
+
+        if !response.status().is_success() {
+            return Err(format!(
+                "Pinecone {} failed: {}",
+                path,
+                response.text().await.unwrap_or_default()
+            ));
+        }
+        response
+            .json()
+            .await
+            .map_err(|e| format!("Failed to parse response: {}", e))
+    }
+
+    async fn post_delete(&self, body: Value) -> Result<(), String> {
+        let response = self
+            .client
+            .post(format!("{}/vectors/delete", self.index_url))
+            .header("Api-Key", &self.api_key)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| format!("Request failed: {}", e))?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!(
+                "Failed to delete vectors: {}",
+                response.text().await.unwrap_or_default()
+            ))
+        }
+    }
+}
+
+#[async_trait]
+impl VectorStore for PineconeStore {
+    async fn upsert(
+        &self,
+        namespace: &str,
+        id: &str,
+        values: Vec<f32>,
+        metadata: Value,
+    ) -> Result<(), String> {
+        let body = json!({
+            "vectors": [{ "id": id, "values": values, "metadata": metadata }],
+            "namespace": namespace
+        });
+
+        let response = self
+            .client
+            .post(format!("{}/vectors/upsert", self.index_url))
+            .header("Api-Key", &self.api_key)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| format!("Request failed: {}", e))?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!(
+                "Failed to upsert vector: {}",
+                response.text().await.unwrap_or_default()
+            ))
+        }
+    }
+
+    async fn query(
+        &self,
+        namespace: &str,
+        values: Vec<f32>,
+        top_k: usize,
+        filter: Value,
+    ) -> Result<Vec<VectorMatch>, String> {
+        let body = json!({
+            "namespace": namespace,
+            "vector": values,
+            "topK": top_k,
+            "filter": filter,
+            "includeMetadata": true
+        });
+
+        let response = self
+            .client
+            .post(format!("{}/query", self.index_url))
+            .header("Api-Key", &self.api_key)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| format!("Request failed: {}", e))?;
+
+        if !response.status().is_success() {
+            return Err(format!(
+                "Vector query failed: {}",
+                response.text().await.unwrap_or_default()
+            ));
+        }
+
+        let response_json: Value = response
+            .json()
+            .await
+            .map_err(|e| format!("Failed to parse response: {}", e))?;
+
+        Ok(response_json["matches"]
+            .as_array()
+            .map(|matches| {
+                matches
+                    .iter()
+                    .map(|m| VectorMatch {
+                        id: m["id"].as_str().unwrap_or_default().to_string(),
+                        score: m["score"].as_f64().unwrap_or_default() as f32,
+                        metadata: m["metadata"].clone(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    /// Pages through the matching ids with `vectors/list`, then fetches their metadata in
+    /// batches.
+    async fn list(&self, namespace: &str, prefix: &str) -> Result<Vec<VectorMatch>, String> {
+        let mut ids: Vec<String> = Vec::new();
+        let mut next_page: Option<String> = None;
+        loop {
+            let mut params = vec![
+                ("namespace", namespace.to_string()),
+                ("prefix", prefix.to_string()),
+            ];
+            if let Some(token) = next_page.take() {
+                params.push(("paginationToken", token));
+            }
+            let page = self.get("vectors/list", &params).await?;
+            ids.extend(
+                page["vectors"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|v| v["id"].as_str().map(str::to_string)),
+            );
+            match page["pagination"]["next"].as_str() {
+                Some(token) => next_page = Some(token.to_string()),
+                None => break,
+            }
+        }
+
+        let mut vectors = Vec::with_capacity(ids.len());
+        for batch in ids.chunks(FETCH_BATCH) {
+            let mut params = vec![("namespace", namespace.to_string())];
+            params.extend(batch.iter().map(|id| ("ids", id.clone())));
+            let fetched = self.get("vectors/fetch", &params).await?;
+            vectors.extend(fetched["vectors"].as_object().into_iter().flatten().map(
+                |(id, vector)| VectorMatch {
+                    id: id.clone(),
+                    score: 0.0,
+                    metadata: vector["metadata"].clone(),
+                },
+            ));
+        }
+        Ok(vectors)
+    }
+
+    async fn delete(&self, namespace: &str, filter: Value) -> Result<(), String> {
+        // Pinecone rejects an empty filter; clearing a namespace needs deleteAll instead.
+        let body = if filter.as_object().is_some_and(|f| !f.is_empty()) {
+            json!({ "namespace": namespace, "filter": filter })
+        } else {
+            json!({ "namespace": namespace, "deleteAll": true })
+        };
+        self.post_delete(body).await
+    }
+
+    async fn delete_ids(&self, namespace: &str, ids: &[String]) -> Result<(), String> {
+        for batch in ids.chunks(DELETE_BATCH) {
+            self.post_delete(json!({ "namespace": namespace, "ids": batch }))
+                .await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+use crate::actors::manager::Manager;
+use crate::actors::message::{
+    ActorProfile, FetchHistoricalInteractions, ForgetUser, ListUserActors,
+};
+use crate::services::database::Database;
+use crate::services::usage::{usage_report, UsageQuery};
+use crate::services::vector_store::{chat_id_prefix, knowledge_namespace, CHAT_NAMESPACE};
+use crate::services::Services;
+use actix::Addr;
+use chrono::Utc;
+use serde_json::{json, Value};
+
+/// Most notifications included in an export.
+const EXPORT_NOTIFICATIONS: usize = 10_000;
+
+/// The user's running actors and their `ai_agents` rows, which also cover stopped actors.
+async fn user_actors(
+    db: &dyn Database,
+    manager: &Addr<Manager>,
+    user_id: &str,
+) -> Result<(Vec<ActorProfile>, Vec<Value>), String> {
+    let running = manager
+        .send(ListUserActors {
+            user_id: user_id.to_string(),
+        })
+        .await
+        .map_err(|_| "Failed to list actors".to_string())?;
+    Ok((running, db.list_user_actors(user_id).await?))
+}
+
+/// Ids of the actors in either list, each listed once.
+fn actor_ids(running: &[ActorProfile], stored: &[Value]) -> Vec<String> {
+    let mut ids: Vec<String> = running.iter().map(|actor| actor.id.to_string()).collect();
+    for id in stored.iter().filter_map(|actor| actor["id"].as_str()) {
+        if !ids.iter().any(|existing| existing == id) {
+            ids.push(id.to_string());
+        }
+    }
+    ids
+}
+
+/// Everything stored about the user as one JSON document: profile, actors with their
+/// conversations and documents, goals with their tasks, XP history, chat memories kept in
+/// the vector store and notifications.
+pub async fn export_user(
+    services: &Services,
+    db: &dyn Database,
+    manager: &Addr<Manager>,
+    user_id: &str,
+) -> Result<Value, String> {
+    let (running, stored) = user_actors(db, manager, user_id).await?;
+    let mut actors = Vec::new();
+    for actor_id in actor_ids(&running, &stored) {
+        let profile = stored
+            .iter()
+            .find(|actor| actor["id"] == actor_id.as_str())
+            .cloned()
+            .or_else(|| {
+                running
+                    .iter()
+                    .find(|actor| actor.id.to_string() == actor_id)
+                    .and_then(|actor| serde_json::to_value(actor).ok())
+            })
+            .unwrap_or_default();
+        // Only running actors keep a history in memory; stopped ones report none.
+        let history = manager
+            .send(FetchHistoricalInteractions {
+                user_id: user_id.to_string(),
+                actor_id: actor_id.clone(),
+            })
+            .await
+            .ok()
+            .and_then(Result::ok)
+            .unwrap_or_default();
+        actors.push(json!({
+            "profile": profile,
+            "recent_history": history,
+            "interactions": db.list_interactions(&actor_id).await?,
+            "historical_interactions": db.list_historical_interactions(&actor_id).await?,
+            "knowledge_documents": db.list_knowledge_documents(&actor_id).await?,
+        }));
+    }
+
+    let mut goals = Vec::new();
+    let mut xp_history = Vec::new();
+    for mut goal in db.list_goals(user_id).await? {
+        let goal_id = goal["id"].as_str().unwrap_or_default().to_string();
+        let tasks = db.list_tasks(&goal_id).await?;
+        xp_history.extend(
+            tasks
+                .iter()
+                .filter(|task| task["status"] == "completed")
+                .map(|task| {
+                    json!({
+                        "task_id": task["id"],
+                        "goal_id": goal_id,
+                        "title": task["title"],
+                        "xp": task["xp_reward"],
+                        "completed_at": task["completed_at"],
+                    })
+                }),
+        );
+        goal["tasks"] = json!(tasks);
+        goals.push(goal);
+    }
+    xp_history.sort_by(|a, b| {
+        let at = |entry: &Value| {
+            entry["completed_at"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string()
+        };
+        at(a).cmp(&at(b))
+    });
+
+    let conversations: Vec<Value> = match &services.vectors {
+        Some(vectors) => vectors
+            .list(CHAT_NAMESPACE, &chat_id_prefix(user_id))
+            .await?
+            .into_iter()
+            .map(|vector| vector.metadata)
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let usage = UsageQuery {
+        group_by: Some("actor,model,day".to_string()),
+        user_id: Some(user_id.to_string()),
+        ..Default::default()
+    };
+
+    Ok(json!({
+        "user_id": user_id,
+        "exported_at": Utc::now().to_rfc3339(),
+        "profile": db.get_user(user_id).await?,
+        "actors": actors,
+        "goals": goals,
+        "xp_history": xp_history,
+        "conversations": conversations,
+        "notifications": db.list_notifications(user_id, false, EXPORT_NOTIFICATIONS).await?,
+        "notification_preferences": db.get_notification_preferences(user_id).await?,
+        "llm_usage": usage_report(db, &usage).await?,
+    }))
+}
+
+/// Erases the user's data from the database and the vector store, stops their actors and
+/// records the erasure in `account_erasures`. Returns what was erased. Every step is safe to
+/// repeat, so a failed erasure can be retried.
+pub async fn erase_user(
+    services: &Services,
+    manager: &Addr<Manager>,
+    user_id: &str,
+) -> Result<Value, String> {
+    let db = services
+        .db
+        .clone()
+        .ok_or_else(|| "Database is not configured".to_string())?;
+    let (running, stored) = user_actors(db.as_ref(), manager, user_id).await?;
+    let actor_ids = actor_ids(&running, &stored);
+    let goals = db.list_goals(user_id).await?;
+    let mut tasks = 0;
+    for goal in &goals {
+        tasks += db
+            .list_tasks(goal["id"].as_str().unwrap_or_default())
+            .await?
+            .len();
+    }
+
+    if let Some(vectors) = &services.vectors {
+        vectors
+            .delete(CHAT_NAMESPACE, json!({ "user_id": user_id }))
+            .await?;
+        for actor_id in &actor_ids {
+            vectors
+                .delete(&knowledge_namespace(actor_id), json!({}))
+                .await?;
+        }
+    }
+    db.delete_user(user_id).await?;
+    let stopped = manager
+        .send(ForgetUser {
+            user_id: user_id.to_string(),
+        })
+        .await
+        .map_err(|_| "Failed to stop actors".to_string())?;
+
+    let summary = json!({
+        "actors": actor_ids.len(),
+        "running_actors_stopped": stopped,
+        "goals": goals.len(),
+        "tasks": tasks,
+        "vectors_erased": services.vectors.is_some(),
+    });
+    if let Err(e) = db
+        .record_erasure(json!({ "user_id": user_id, "summary": summary }))
+        .await
+    {
+        println!(
+            "Warning: failed to record erasure of user {}: {}",
+            user_id, e
+        );
+    }
+    Ok(summary)
+}
@@ -0,0 +1,263 @@
+use crate::services::audit::{record, AuditEvent};
+use crate::services::database::Database;
+use crate::services::usage::UsageQuery;
+use crate::services::Services;
+use chrono::{DateTime, Datelike, TimeZone, Utc};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::env;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlanLimits {
+    pub monthly_tokens: u64,
+    pub monthly_cost_usd: f64,
+    pub hard_cap: bool, // Turn requests away when usage can't be read rather than let them through
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MonthlyUsage {
+    pub period: String, // e.g. "2025-01"
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+    pub cost_usd: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QuotaStatus {
+    pub user_id: String,
+    pub plan: String,
+    pub limits: PlanLimits,
+    pub usage: MonthlyUsage,
+    pub exceeded: bool,
+}
+
+/// Why `QuotaTracker::check` turned a request away.
+#[derive(Debug)]
+pub enum QuotaDenial {
+    Exceeded(Box<QuotaStatus>),
+    /// Usage couldn't be read and the plan has a hard cap.
+    Unverified {
+        plan: String,
+    },
+}
+
+/// Checks per-user LLM consumption for the current calendar month against the user's plan.
+/// Usage is summed from `llm_usage` and plan assignments are kept in `user_quotas`, so every
+/// instance sees the same numbers and they survive restarts.
+pub struct QuotaTracker {
+    plans: HashMap<String, PlanLimits>,
+    default_plan: String,
+}
+
+fn current_period() -> String {
+    let now = Utc::now();
+    format!("{:04}-{:02}", now.year(), now.month())
+}
+
+/// What the user has used since `since`, counted from `llm_usage`.
+async fn usage_since(
+    db: &dyn Database,
+    user_id: &str,
+    since: DateTime<Utc>,
+) -> Result<MonthlyUsage, String> {
+    let query = UsageQuery {
+        user_id: Some(user_id.to_string()),
+        since: Some(since),
+        ..Default::default()
+    };
+    let mut usage = MonthlyUsage {
+        period: current_period(),
+        ..Default::default()
+    };
+    for totals in db.usage_report(&query, &[]).await? {
+        usage.requests += totals.calls;
+        usage.prompt_tokens += totals.prompt_tokens;
+        usage.completion_tokens += totals.completion_tokens;
+        usage.total_tokens += totals.total_tokens;
+        usage.cost_usd += totals.cost_usd;
+    }
+    Ok(usage)
+}
+
+/// Midnight UTC on the first day of the current month.
+fn period_start() -> DateTime<Utc> {
+    let now = Utc::now();
+    Utc.with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0)
+        .single()
+        .unwrap_or(now)
+}
+
+/// Parses `QUOTA_PLANS`, formatted as `plan=tokens:cost_usd` pairs separated by commas.
+/// Append `:hard` to a pair to give that plan a hard cap.
+fn parse_plans(spec: &str) -> HashMap<String, PlanLimits> {
+    spec.split(',')
+        .filter_map(|entry| {
+            let (name, limits) = entry.trim().split_once('=')?;
+            let mut parts = limits.split(':').map(str::trim);
+            let tokens = parts.next()?.parse().ok()?;
+            let cost = parts.next()?.parse().ok()?;
+            let hard_cap = match parts.next() {
+                None => false,
+                Some("hard") => true,
+                Some(_) => return None,
+            };
+            Some((
+                name.trim().to_string(),
+                PlanLimits {
+                    monthly_tokens: tokens,
+                    monthly_cost_usd: cost,
+                    hard_cap,
+                },
+            ))
+        })
+        .collect()
+}
+
+impl QuotaTracker {
+    pub fn from_env() -> Self {
+        let spec = env::var("QUOTA_PLANS")
+            .unwrap_or_else(|_| "free=100000:1.0:hard,pro=2000000:20.0,team=10000000:100.0".into());
+        let plans = parse_plans(&spec);
+        let default_plan = env::var("QUOTA_DEFAULT_PLAN").unwrap_or_else(|_| "free".into());
+
+        QuotaTracker {
+            plans,
+            default_plan,
+        }
+    }
+
+    pub fn has_plan(&self, plan: &str) -> bool {
+        self.plans.contains_key(plan)
+    }
+
+    fn limits_for(&self, plan: &str) -> PlanLimits {
+        self.plans.get(plan).cloned().unwrap_or(PlanLimits {
+            monthly_tokens: 0,
+            monthly_cost_usd: 0.0,
+            hard_cap: true,
+        })
+    }
+
+    pub async fn status(&self, db: &dyn Database, user_id: &str) -> Result<QuotaStatus, String> {
+        let (plan, since) = self.plan_of(db, user_id).await?;
+        let usage = usage_since(db, user_id, since).await?;
+        Ok(self.status_for(user_id, plan, usage))
+    }
+
+    /// The user's plan and when this month's count for them starts.
+    async fn plan_of(
+        &self,
+        db: &dyn Database,
+        user_id: &str,
+    ) -> Result<(String, DateTime<Utc>), String> {
+        let quota = db.get_user_quota(user_id).await?;
+        let plan = quota
+            .as_ref()
+            .and_then(|quota| quota["plan"].as_str())
+            .unwrap_or(&self.default_plan)
+            .to_string();
+        // A reset only forgives what was used before it, within the current month.
+        let reset_at = quota
+            .as_ref()
+            .and_then(|quota| quota["reset_at"].as_str())
+            .and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+            .map(|at| at.with_timezone(&Utc));
+        let since = reset_at.map_or(period_start(), |at| at.max(period_start()));
+        Ok((plan, since))
+    }
+
+    fn status_for(&self, user_id: &str, plan: String, usage: MonthlyUsage) -> QuotaStatus {
+        let limits = self.limits_for(&plan);
+        let exceeded = usage.total_tokens >= limits.monthly_tokens
+            || usage.cost_usd >= limits.monthly_cost_usd;
+        QuotaStatus {
+            user_id: user_id.to_string(),
+            plan,
+            limits,
+            usage,
+            exceeded,
+        }
+    }
+
+    /// Fails once the user has used up their plan's monthly tokens or budget. When usage
+    /// can't be read the failure is audited and the request let through, so a database
+    /// hiccup doesn't lock users out, unless the plan has a hard cap. Without a database
+    /// there is no usage to count and every request passes.
+    pub async fn check(&self, services: &Services, user_id: &str) -> Result<(), QuotaDenial> {
+        let Some(db) = &services.db else {
+            return Ok(());
+        };
+        let (plan, since) = match self.plan_of(db.as_ref(), user_id).await {
+            Ok(found) => found,
+            Err(e) => {
+                return self
+                    .unchecked(services, user_id, &self.default_plan, e)
+                    .await
+            }
+        };
+        match usage_since(db.as_ref(), user_id, since).await {
+            Ok(usage) => {
+                let status = self.status_for(user_id, plan, usage);
+                if status.exceeded {
+                    Err(QuotaDenial::Exceeded(Box::new(status)))
+                } else {
+                    Ok(())
+                }
+            }
+            Err(e) => self.unchecked(services, user_id, &plan, e).await,
+        }
+    }
+
+    /// Settles a check whose usage couldn't be read; a plan that can't be looked up is
+    /// treated as the default plan.
+    async fn unchecked(
+        &self,
+        services: &Services,
+        user_id: &str,
+        plan: &str,
+        error: String,
+    ) -> Result<(), QuotaDenial> {
+        let allowed = !self.limits_for(plan).hard_cap;
+        let event = AuditEvent::system("quota.check_failed")
+            .target("user", user_id)
+            .details(json!({ "plan": plan, "error": error, "allowed": allowed }));
+        record(services, event).await;
+        if allowed {
+            Ok(())
+        } else {
+            Err(QuotaDenial::Unverified {
+                plan: plan.to_string(),
+            })
+        }
+    }
+
+    pub async fn set_plan(
+        &self,
+        db: &dyn Database,
+        user_id: &str,
+        plan: &str,
+    ) -> Result<(), String> {
+        if !self.has_plan(plan) {
+            return Err(format!("Unknown plan: {}", plan));
+        }
+        let mut quota = db
+            .get_user_quota(user_id)
+            .await?
+            .unwrap_or_else(|| json!({}));
+        quota["plan"] = json!(plan);
+        db.set_user_quota(user_id, quota).await
+    }
+
+    /// Starts the user's count for this month over from now. Their `llm_usage` rows are kept.
+    pub async fn reset(&self, db: &dyn Database, user_id: &str) -> Result<(), String> {
+        let mut quota = db
+            .get_user_quota(user_id)
+            .await?
+            .unwrap_or_else(|| json!({ "plan": self.default_plan }));
+        quota["reset_at"] = json!(Utc::now().to_rfc3339());
+        db.set_user_quota(user_id, quota).await
+    }
+}
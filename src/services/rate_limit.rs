@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const MAX_TRACKED_KEYS: usize = 10_000;
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Token bucket rate limiter keyed by an arbitrary string (user id, IP address, ...).
+pub struct RateLimiter {
+    capacity: f64,
+    refill_per_sec: f64,
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(capacity: u32, per_minute: u32) -> Self {
+        RateLimiter {
+            capacity: capacity.max(1) as f64,
+            refill_per_sec: per_minute.max(1) as f64 / 60.0,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Reads `{prefix}_BURST` and `{prefix}_PER_MINUTE`, falling back to the given defaults.
+    pub fn from_env(prefix: &str, default_burst: u32, default_per_minute: u32) -> Self {
+        let read = |name: &str, default: u32| {
+            env::var(format!("{}_{}", prefix, name))
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        };
+        RateLimiter::new(
+            read("BURST", default_burst),
+            read("PER_MINUTE", default_per_minute),
+        )
+    }
+
+    /// Takes one token from the bucket for `key`. When the bucket is empty, returns how long
+    /// the caller has to wait before the next token becomes available.
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_KEYS {
+            // Buckets that have been idle long enough to refill completely carry no state.
+            let full_after = Duration::from_secs_f64(self.capacity / self.refill_per_sec);
+            buckets.retain(|_, bucket| now.duration_since(bucket.last_refill) < full_after);
+        }
+        let bucket = buckets.entry(key.to_string()).or_insert(TokenBucket {
+            tokens: self.capacity,
+            last_refill: now,
+        });
+
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let missing = 1.0 - bucket.tokens;
+            Err(Duration::from_secs_f64(missing / self.refill_per_sec))
+        }
+    }
+}
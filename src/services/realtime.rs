@@ -0,0 +1,200 @@
+use crate::actors::manager::Manager;
+use crate::actors::message::RowChanged;
+use actix::Addr;
+use futures::{future, stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::env;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_postgres::{AsyncMessage, NoTls};
+
+/// The channel the `notify_row_change` trigger publishes task and goal changes on.
+pub const CHANNEL: &str = "row_changes";
+/// Longest wait between reconnection attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ChangeKind {
+    Insert,
+    Update,
+    Delete,
+}
+
+/// A change to a `tasks` or `goals` row, in the shape the trigger publishes. For tasks, the
+/// user, agent and goal fields come from the task's goal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RowChange {
+    pub table: String,
+    #[serde(rename = "type")]
+    pub kind: ChangeKind,
+    pub user_id: Option<String>,
+    pub agent_id: Option<String>, // The goal's assigned coach
+    pub goal_category: Option<String>,
+    pub goal_title: Option<String>,
+    #[serde(default)]
+    pub record: Value, // Id, status and title after the change, null for deletes
+    #[serde(default)]
+    pub old_record: Value, // Id, status and title before the change, null for inserts
+}
+
+/// A change a coach reacts to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoachEvent {
+    TaskCompleted {
+        task_id: String,
+        task: String,
+        goal: String,
+    },
+    GoalPaused {
+        goal_id: String,
+        goal: String,
+    },
+    GoalResumed {
+        goal_id: String,
+        goal: String,
+    },
+}
+
+impl RowChange {
+    /// The event this change represents, or `None` for changes coaches don't react to.
+    pub fn event(&self) -> Option<CoachEvent> {
+        if self.kind != ChangeKind::Update {
+            return None;
+        }
+        let field = |key: &str| self.record[key].as_str().unwrap_or_default().to_string();
+        let (before, after) = (&self.old_record["status"], &self.record["status"]);
+        match (self.table.as_str(), before.as_str(), after.as_str()) {
+            ("tasks", Some(before), Some("completed")) if before != "completed" => {
+                Some(CoachEvent::TaskCompleted {
+                    task_id: field("id"),
+                    task: field("title"),
+                    goal: self.goal_title.clone().unwrap_or_default(),
+                })
+            }
+            ("goals", Some("active"), Some("paused")) => Some(CoachEvent::GoalPaused {
+                goal_id: field("id"),
+                goal: field("title"),
+            }),
+            ("goals", Some("paused"), Some("active")) => Some(CoachEvent::GoalResumed {
+                goal_id: field("id"),
+                goal: field("title"),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl CoachEvent {
+    /// Identifies the changed row as "table:id", so a coach can skip changes it made itself.
+    pub fn key(&self) -> String {
+        match self {
+            CoachEvent::TaskCompleted { task_id, .. } => format!("tasks:{}", task_id),
+            CoachEvent::GoalPaused { goal_id, .. } | CoachEvent::GoalResumed { goal_id, .. } => {
+                format!("goals:{}", goal_id)
+            }
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CoachEvent::TaskCompleted { .. } => "task_completed",
+            CoachEvent::GoalPaused { .. } => "goal_paused",
+            CoachEvent::GoalResumed { .. } => "goal_resumed",
+        }
+    }
+
+    /// Describes what happened to the coach, who answers with a message for the user.
+    pub fn prompt(&self) -> String {
+        match self {
+            CoachEvent::TaskCompleted { task, goal, .. } => format!(
+                "The user just completed the task \"{}\" for their goal \"{}\". \
+                Congratulate them briefly and suggest what to focus on next.",
+                task, goal
+            ),
+            CoachEvent::GoalPaused { goal, .. } => format!(
+                "The user just paused their goal \"{}\". Acknowledge it without judgement \
+                and help them re-plan: ask what got in the way and offer a smaller next step.",
+                goal
+            ),
+            CoachEvent::GoalResumed { goal, .. } => format!(
+                "The user just resumed their paused goal \"{}\". Welcome them back and \
+                suggest one task to restart with.",
+                goal
+            ),
+        }
+    }
+
+    /// Extra fields stored with the notification the coach sends.
+    pub fn data(&self) -> Value {
+        json!({ "event": self.name(), "row": self.key() })
+    }
+}
+
+/// Listens for task and goal changes with Postgres LISTEN/NOTIFY and passes them to the
+/// manager as `RowChanged` messages, whichever client made the change.
+pub struct RealtimeListener {
+    url: String,
+}
+
+impl RealtimeListener {
+    /// Connects with `REALTIME_DATABASE_URL`, falling back to `DATABASE_URL`, so the listener
+    /// also works when queries go through the Supabase REST API.
+    pub fn from_env() -> Result<Self, String> {
+        let url = env::var("REALTIME_DATABASE_URL")
+            .or_else(|_| env::var("DATABASE_URL"))
+            .map_err(|_| "REALTIME_DATABASE_URL or DATABASE_URL not set".to_string())?;
+        Ok(RealtimeListener { url })
+    }
+
+    /// Listens for as long as the manager runs, reconnecting with exponential backoff after
+    /// the connection drops. Changes made while disconnected are missed.
+    pub async fn run(self, manager: Addr<Manager>) {
+        let mut backoff = Duration::from_secs(1);
+        while manager.connected() {
+            if let Err(e) = self.listen(&manager, &mut backoff).await {
+                println!(
+                    "Warning: realtime listener disconnected, retrying in {:?}: {}",
+                    backoff, e
+                );
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    async fn listen(&self, manager: &Addr<Manager>, backoff: &mut Duration) -> Result<(), String> {
+        let (client, mut connection) = tokio_postgres::connect(&self.url, NoTls)
+            .await
+            .map_err(|e| format!("Postgres error: {}", e))?;
+
+        // The connection only delivers notifications while it's polled, including while
+        // LISTEN itself runs, so drive it on its own task and forward what it yields.
+        let (sender, mut messages) = mpsc::unbounded_channel();
+        tokio::spawn(
+            stream::poll_fn(move |cx| connection.poll_message(cx)).for_each(move |message| {
+                let _ = sender.send(message);
+                future::ready(())
+            }),
+        );
+
+        client
+            .batch_execute(&format!("LISTEN {}", CHANNEL))
+            .await
+            .map_err(|e| format!("Postgres error: {}", e))?;
+        println!("Realtime listener subscribed to {}", CHANNEL);
+        *backoff = Duration::from_secs(1);
+
+        while let Some(message) = messages.recv().await {
+            let message = message.map_err(|e| format!("Postgres error: {}", e))?;
+            if let AsyncMessage::Notification(notification) = message {
+                match serde_json::from_str::<RowChange>(notification.payload()) {
+                    Ok(change) => manager.do_send(RowChanged { change }),
+                    Err(e) => println!("Warning: ignoring malformed row change: {}", e),
+                }
+            }
+        }
+        Err("connection closed".to_string())
+    }
+}
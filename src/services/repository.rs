@@ -0,0 +1,371 @@
+use crate::services::models::{Table, TABLES};
+use futures::future::join_all;
+use reqwest::{Client, Method, RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Rows `fetch_all` asks for per request.
+const PAGE_ROWS: usize = 1000;
+
+/// Typed access to the Supabase tables through PostgREST. Reads select exactly `T::COLUMNS`
+/// and parse rows into `T`, so a column the code expects but the database lacks surfaces as
+/// an error right away.
+#[derive(Clone)]
+pub struct Repository {
+    http: Client,
+    url: String,
+    api_key: String,
+    bearer: String, // The service role key, or a user's access token
+}
+
+/// Filters, ordering and a limit for a select on one table. Start one with `Repository::query`.
+#[derive(Clone)]
+pub struct Query {
+    table: String,
+    params: Vec<(String, String)>,
+}
+
+impl Query {
+    pub fn new(table: &str, columns: &[&str]) -> Self {
+        Query {
+            table: table.to_string(),
+            params: vec![("select".to_string(), columns.join(","))],
+        }
+    }
+
+    pub fn eq(mut self, column: &str, value: &str) -> Self {
+        self.params
+            .push((column.to_string(), format!("eq.{}", value)));
+        self
+    }
+
+    /// Keeps rows whose `column` is at least `value`.
+    pub fn gte(mut self, column: &str, value: &str) -> Self {
+        self.params
+            .push((column.to_string(), format!("gte.{}", value)));
+        self
+    }
+
+    /// Keeps rows whose `column` is above `value`.
+    pub fn gt(mut self, column: &str, value: &str) -> Self {
+        self.params
+            .push((column.to_string(), format!("gt.{}", value)));
+        self
+    }
+
+    /// Keeps rows whose `column` is below `value`.
+    pub fn lt(mut self, column: &str, value: &str) -> Self {
+        self.params
+            .push((column.to_string(), format!("lt.{}", value)));
+        self
+    }
+
+    pub fn is_null(mut self, column: &str) -> Self {
+        self.params
+            .push((column.to_string(), "is.null".to_string()));
+        self
+    }
+
+    pub fn order(mut self, column: &str, ascending: bool) -> Self {
+        let direction = if ascending { "asc" } else { "desc" };
+        self.params
+            .push(("order".to_string(), format!("{}.{}", column, direction)));
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.params.push(("limit".to_string(), limit.to_string()));
+        self
+    }
+}
+
+impl Repository {
+    /// A repository acting with `api_key`, normally the service role key, which bypasses RLS.
+    pub fn new(http: Client, supabase_url: &str, api_key: &str) -> Self {
+        Repository {
+            http,
+            url: format!("{}/rest/v1", supabase_url.trim_end_matches('/')),
+            api_key: api_key.to_string(),
+            bearer: api_key.to_string(),
+        }
+    }
+
+    /// A copy whose requests carry the user's `access_token`, so PostgREST applies row-level
+    /// security as that user. `api_key` identifies the project to the gateway; use the anon key.
+    pub fn as_user(&self, api_key: &str, access_token: &str) -> Self {
+        Repository {
+            http: self.http.clone(),
+            url: self.url.clone(),
+            api_key: api_key.to_string(),
+            bearer: access_token.to_string(),
+        }
+    }
+
+    fn request(&self, method: Method, table: &str) -> RequestBuilder {
+        self.http
+            .request(method, format!("{}/{}", self.url, table))
+            .header("apikey", &self.api_key)
+            .bearer_auth(&self.bearer)
+    }
+
+    async fn send(request: RequestBuilder, table: &str) -> Result<Response, String> {
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("Supabase request to {} failed: {}", table, e))?;
+        if !response.status().is_success() {
+            return Err(format!(
+                "Supabase request to {} failed: {}",
+                table,
+                response.text().await.unwrap_or_default()
+            ));
+        }
+        Ok(response)
+    }
+
+    /// Starts a select on `T`'s table; add filters and pass the result to `fetch`.
+    pub fn query<T: Table>(&self) -> Query {
+        Query::new(T::NAME, T::COLUMNS)
+    }
+
+    async fn execute(&self, query: &Query) -> Result<Vec<Value>, String> {
+        let request = self.request(Method::GET, &query.table).query(&query.params);
+        Self::send(request, &query.table)
+            .await?
+            .json()
+            .await
+            .map_err(|e| format!("Failed to parse {} rows: {}", query.table, e))
+    }
+
+    pub async fn fetch<T: Table + DeserializeOwned>(&self, query: Query) -> Result<Vec<T>, String> {
+        parse_rows(self.execute(&query).await?)
+    }
+
+    /// Like `fetch`, but walks the matches in pages ordered by `id` until one comes back empty,
+    /// so PostgREST's `max-rows` cap can't cut the result short. `query` must select `id` and
+    /// carry no order or limit of its own.
+    pub async fn fetch_all<T: Table + DeserializeOwned>(
+        &self,
+        query: Query,
+    ) -> Result<Vec<T>, String> {
+        let mut rows = Vec::new();
+        let mut after: Option<String> = None;
+        loop {
+            let mut page = query.clone();
+            if let Some(id) = &after {
+                page = page.gt("id", id);
+            }
+            let page = self
+                .execute(&page.order("id", true).limit(PAGE_ROWS))
+                .await?;
+            let Some(last) = page.last() else {
+                return parse_rows(rows);
+            };
+            let id = last["id"]
+                .as_str()
+                .ok_or_else(|| format!("{} rows have no id to page by", T::NAME))?;
+            after = Some(id.to_string());
+            rows.extend(page);
+        }
+    }
+
+    /// Rows of `T` whose `column` equals `value`.
+    pub async fn find<T: Table + DeserializeOwned>(
+        &self,
+        column: &str,
+        value: &str,
+    ) -> Result<Vec<T>, String> {
+        self.fetch(self.query::<T>().eq(column, value)).await
+    }
+
+    pub async fn get<T: Table + DeserializeOwned>(&self, id: &str) -> Result<Option<T>, String> {
+        Ok(self.find(T::KEY, id).await?.into_iter().next())
+    }
+
+    pub async fn all<T: Table + DeserializeOwned>(&self) -> Result<Vec<T>, String> {
+        self.fetch(self.query::<T>()).await
+    }
+
+    /// Inserts a row and returns the key the database assigned.
+    pub async fn insert<T: Table + Serialize>(&self, row: &T) -> Result<String, String> {
+        let request = self
+            .request(Method::POST, T::NAME)
+            .query(&[("select", T::KEY)])
+            .header("Prefer", "return=representation")
+            .json(&to_json(row)?);
+        let rows: Vec<Value> = Self::send(request, T::NAME)
+            .await?
+            .json()
+            .await
+            .map_err(|e| format!("Failed to parse inserted {} row: {}", T::NAME, e))?;
+        rows.first()
+            .and_then(|row| row[T::KEY].as_str())
+            .map(str::to_string)
+            .ok_or_else(|| format!("Insert into {} returned no {}", T::NAME, T::KEY))
+    }
+
+    /// Inserts several rows in one request, which PostgREST applies as a single statement.
+    /// Every row must set the same columns.
+    pub async fn insert_all<T: Table + Serialize>(&self, rows: &[T]) -> Result<(), String> {
+        let request = self
+            .request(Method::POST, T::NAME)
+            .header("Prefer", "return=minimal")
+            .json(&to_json(&rows)?);
+        Self::send(request, T::NAME).await.map(|_| ())
+    }
+
+    /// Like `insert_all`, but returns the new rows' keys in order. Rows may set different
+    /// columns; those a row leaves out get their defaults.
+    pub async fn insert_all_returning<T: Table + Serialize>(
+        &self,
+        rows: &[T],
+    ) -> Result<Vec<String>, String> {
+        let rows = to_json(&rows)?;
+        let mut columns: Vec<&String> = Vec::new();
+        for key in rows
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_object)
+            .flat_map(|row| row.keys())
+        {
+            if !columns.contains(&key) {
+                columns.push(key);
+            }
+        }
+        let columns = columns
+            .iter()
+            .map(|column| column.as_str())
+            .collect::<Vec<_>>()
+            .join(",");
+        let request = self
+            .request(Method::POST, T::NAME)
+            .query(&[("select", T::KEY), ("columns", columns.as_str())])
+            .header("Prefer", "return=representation,missing=default")
+            .json(&rows);
+        let inserted: Vec<Value> = Self::send(request, T::NAME)
+            .await?
+            .json()
+            .await
+            .map_err(|e| format!("Failed to parse inserted {} rows: {}", T::NAME, e))?;
+        inserted
+            .iter()
+            .map(|row| {
+                row[T::KEY]
+                    .as_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| format!("Insert into {} returned no {}", T::NAME, T::KEY))
+            })
+            .collect()
+    }
+
+    /// Inserts a row or, when one with the same key exists, replaces the columns it sets.
+    pub async fn upsert<T: Table + Serialize>(&self, row: &T) -> Result<(), String> {
+        let request = self
+            .request(Method::POST, T::NAME)
+            .query(&[("on_conflict", T::KEY)])
+            .header("Prefer", "resolution=merge-duplicates,return=minimal")
+            .json(&to_json(row)?);
+        Self::send(request, T::NAME).await.map(|_| ())
+    }
+
+    /// Sets `changes` on the row with the given key.
+    pub async fn update<T: Table>(&self, id: &str, changes: Value) -> Result<(), String> {
+        self.update_where::<T>(T::KEY, id, changes).await
+    }
+
+    /// Sets `changes` on every row whose `column` equals `value`.
+    pub async fn update_where<T: Table>(
+        &self,
+        column: &str,
+        value: &str,
+        changes: Value,
+    ) -> Result<(), String> {
+        let request = self
+            .request(Method::PATCH, T::NAME)
+            .query(&[(column, format!("eq.{}", value))])
+            .header("Prefer", "return=minimal")
+            .json(&changes);
+        Self::send(request, T::NAME).await.map(|_| ())
+    }
+
+    pub async fn delete<T: Table>(&self, id: &str) -> Result<(), String> {
+        self.delete_where::<T>(T::KEY, id).await
+    }
+
+    pub async fn delete_where<T: Table>(&self, column: &str, value: &str) -> Result<(), String> {
+        let request = self
+            .request(Method::DELETE, T::NAME)
+            .query(&[(column, format!("eq.{}", value))]);
+        Self::send(request, T::NAME)
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("Failed to delete {}: {}", T::NAME, e))
+    }
+
+    /// Confirms every table the code uses exists with the columns it expects. Reports all
+    /// mismatches at once so drift can be fixed in one pass.
+    pub async fn check_schema(&self) -> Result<(), String> {
+        let checks = join_all(
+            TABLES
+                .iter()
+                .map(|(table, columns)| self.check_table(table, columns)),
+        )
+        .await;
+        schema_report(checks.into_iter().filter_map(Result::err).collect())
+    }
+
+    /// Selects each expected column on its own, so the error names the missing one.
+    async fn check_table(&self, table: &str, columns: &[&str]) -> Result<(), String> {
+        let all = Query::new(table, columns).limit(1);
+        if self.execute(&all).await.is_ok() {
+            return Ok(());
+        }
+
+        let results = join_all(columns.iter().map(|column| async move {
+            self.execute(&Query::new(table, &[column]).limit(1)).await
+        }))
+        .await;
+        let missing: Vec<&str> = columns
+            .iter()
+            .zip(results)
+            .filter(|(_, result)| result.is_err())
+            .map(|(column, _)| *column)
+            .collect();
+        Err(drift(table, columns, &missing))
+    }
+}
+
+/// Describes one table's drift: which expected columns are missing, or the whole table.
+pub(crate) fn drift(table: &str, columns: &[&str], missing: &[&str]) -> String {
+    if missing.len() == columns.len() {
+        format!("  {}: table missing or unreadable", table)
+    } else {
+        format!("  {}: missing columns {}", table, missing.join(", "))
+    }
+}
+
+/// Folds per-table problems into the startup schema check's result.
+pub(crate) fn schema_report(problems: Vec<String>) -> Result<(), String> {
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Database schema does not match the code:\n{}",
+            problems.join("\n")
+        ))
+    }
+}
+
+fn to_json<T: Serialize>(row: &T) -> Result<Value, String> {
+    serde_json::to_value(row).map_err(|e| format!("Failed to serialize row: {}", e))
+}
+
+fn parse_rows<T: Table + DeserializeOwned>(rows: Vec<Value>) -> Result<Vec<T>, String> {
+    rows.into_iter()
+        .map(|row| {
+            serde_json::from_value(row).map_err(|e| format!("Unexpected {} row: {}", T::NAME, e))
+        })
+        .collect()
+}
@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// How many times a reply that breaks its schema is sent back to the model for repair.
+pub const MAX_REPAIRS: usize = 2;
+/// Errors listed in a repair request; the rest are usually consequences of the first few.
+const MAX_REPORTED_ERRORS: usize = 5;
+
+/// A JSON schema the structured payload of a reply must follow, e.g. action cards or
+/// quick-reply buttons for the frontend to render.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseSchema {
+    pub name: String, // Letters, digits, underscores and dashes, as providers require
+    pub schema: Value,
+}
+
+/// A schema the reply as a whole follows: the text for the user plus the payload.
+pub struct ReplySchema {
+    name: String,
+    envelope: Value,
+    validator: jsonschema::Validator,
+}
+
+impl ReplySchema {
+    pub fn new(schema: &ResponseSchema) -> Result<Self, String> {
+        let envelope = json!({
+            "type": "object",
+            "properties": {
+                "text": { "type": "string", "description": "The reply shown to the user" },
+                "payload": schema.schema,
+            },
+            "required": ["text", "payload"],
+            "additionalProperties": false
+        });
+        let validator = jsonschema::validator_for(&envelope)
+            .map_err(|e| format!("Invalid response schema: {}", e))?;
+        Ok(ReplySchema {
+            name: schema.name.clone(),
+            envelope,
+            validator,
+        })
+    }
+
+    /// The `response_format` of a chat completions request. Providers without JSON schema
+    /// support get a JSON object request instead; see `LlmProvider::structured_output`.
+    pub fn response_format(&self) -> Value {
+        json!({
+            "type": "json_schema",
+            "json_schema": { "name": self.name, "schema": self.envelope }
+        })
+    }
+
+    /// Parses a reply and checks it against the schema, returning the text and payload or
+    /// what is wrong with it.
+    pub fn parse(&self, content: Option<&str>) -> Result<(String, Value), Vec<String>> {
+        let content = content.ok_or_else(|| vec!["The reply was empty".to_string()])?;
+        let mut reply: Value = serde_json::from_str(strip_code_fence(content))
+            .map_err(|e| vec![format!("The reply is not valid JSON: {}", e)])?;
+        let errors: Vec<String> = self
+            .validator
+            .iter_errors(&reply)
+            .take(MAX_REPORTED_ERRORS)
+            .map(|error| match error.instance_path.to_string() {
+                path if path.is_empty() => error.to_string(),
+                path => format!("{}: {}", path, error),
+            })
+            .collect();
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let text = reply["text"].as_str().unwrap_or_default().to_string();
+        Ok((text, reply["payload"].take()))
+    }
+}
+
+/// The user turn asking the model to fix a reply that broke the schema.
+pub fn repair_message(errors: &[String]) -> Value {
+    json!({
+        "role": "user",
+        "content": format!(
+            "Your last reply did not match the required JSON schema:\n- {}\n\
+            Reply again with only a JSON object that matches the schema.",
+            errors.join("\n- ")
+        )
+    })
+}
+
+/// Models without a JSON mode sometimes wrap JSON in a Markdown code fence.
+fn strip_code_fence(content: &str) -> &str {
+    let content = content.trim();
+    content
+        .strip_prefix("```json")
+        .or_else(|| content.strip_prefix("```"))
+        .and_then(|inner| inner.strip_suffix("```"))
+        .map(str::trim)
+        .unwrap_or(content)
+}
@@ -1,75 +1,467 @@
+use crate::services::audit::AuditQuery;
+use crate::services::database::{level_for_xp, Database};
+use crate::services::models::{
+    AccountErasure, ActorStateRow, AiAgent, AuditEntry, Goal, HistoricalInteraction, Interaction,
+    KnowledgeDocument, LlmUsage, Notification, NotificationPreferencesRow, Table, Task, User,
+    UserQuota,
+};
+use crate::services::repository::Repository;
+use crate::services::usage::{aggregate, UsageGroup, UsageQuery, UsageTotals};
+use async_trait::async_trait;
+use chrono::Utc;
+use reqwest::Client;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use serde_json::{json, Value};
 use std::env;
-use supabase_rs::SupabaseClient;
+use std::sync::Arc;
+use uuid::Uuid;
 
 pub struct SupabaseService {
-    client: SupabaseClient,
+    repo: Repository,
+    anon_key: String, // Sent as `apikey` on requests made with a user's access token
 }
 
 impl SupabaseService {
-    pub fn new() -> Result<Self, String> {
+    /// Connects with `SUPABASE_KEY`, the service role key, for system jobs. User-scoped
+    /// handles from `for_user` send `SUPABASE_ANON_KEY` (falling back to `SUPABASE_KEY`) as
+    /// the API key and the caller's JWT as the bearer token, so RLS applies.
+    pub fn from_env(http: Client) -> Result<Self, String> {
         let supabase_url = env::var("SUPABASE_URL")
             .map_err(|_| "SUPABASE_URL environment variable not set".to_string())?;
         let supabase_key = env::var("SUPABASE_KEY")
             .map_err(|_| "SUPABASE_KEY environment variable not set".to_string())?;
+        let anon_key = env::var("SUPABASE_ANON_KEY").unwrap_or_else(|_| supabase_key.clone());
 
-        let client = SupabaseClient::new(supabase_url, supabase_key).map_err(|e| e.to_string())?;
-        Ok(SupabaseService { client })
+        Ok(SupabaseService {
+            repo: Repository::new(http, &supabase_url, &supabase_key),
+            anon_key,
+        })
     }
 
-    pub fn get_client(&self) -> &SupabaseClient {
-        &self.client
+    pub fn repository(&self) -> &Repository {
+        &self.repo
     }
 
-    pub async fn add_user(&self, email: &str, name: &str) -> Result<String, String> {
-        self.client
-            .insert(
-                "users",
-                json!({
-                    "email": email,
-                    "name": name
-                }),
-            )
+    /// Creates the profile row for a Supabase auth user.
+    pub async fn add_user(&self, user_id: Uuid, display_name: &str) -> Result<String, String> {
+        self.repo
+            .insert(&User {
+                id: user_id,
+                display_name: Some(display_name.to_string()),
+                level: 1,
+                total_xp: 0,
+                last_active_at: None,
+                created_at: None,
+            })
             .await
     }
 
-    pub async fn add_actor(
-        &self,
-        user_id: &str,
-        name: &str,
-        personality: &str,
-        expertise: &str,
-        picture_url: Option<&str>,
-    ) -> Result<String, String> {
-        self.client
-            .insert(
-                "actors",
-                json!({
-                    "user_id": user_id,
-                    "name": name,
-                    "personality": personality,
-                    "expertise": expertise,
-                    "picture_url": picture_url,
-                }),
+    pub async fn add_actor(&self, agent: &AiAgent) -> Result<String, String> {
+        self.repo.insert(agent).await
+    }
+}
+
+/// Converts a typed row into the JSON shape the `Database` trait hands out.
+fn to_value<T: Serialize>(row: T) -> Result<Value, String> {
+    serde_json::to_value(row).map_err(|e| format!("Failed to serialize row: {}", e))
+}
+
+fn to_values<T: Serialize>(rows: Vec<T>) -> Result<Vec<Value>, String> {
+    rows.into_iter().map(to_value).collect()
+}
+
+/// Parses a row handed to the `Database` trait, so a malformed write fails before it's sent.
+fn from_value<T: DeserializeOwned>(row: Value, table: &str) -> Result<T, String> {
+    serde_json::from_value(row).map_err(|e| format!("Invalid {} row: {}", table, e))
+}
+
+#[async_trait]
+impl Database for SupabaseService {
+    async fn check_schema(&self) -> Result<(), String> {
+        self.repo.check_schema().await
+    }
+
+    fn for_user(&self, access_token: &str) -> Result<Arc<dyn Database>, String> {
+        Ok(Arc::new(SupabaseService {
+            repo: self.repo.as_user(&self.anon_key, access_token),
+            anon_key: self.anon_key.clone(),
+        }))
+    }
+
+    async fn get_user(&self, user_id: &str) -> Result<Option<Value>, String> {
+        self.repo
+            .get::<User>(user_id)
+            .await?
+            .map(to_value)
+            .transpose()
+    }
+
+    async fn list_users(&self) -> Result<Vec<Value>, String> {
+        to_values(self.repo.all::<User>().await?)
+    }
+
+    async fn touch_user(&self, user_id: &str) -> Result<(), String> {
+        self.repo
+            .update::<User>(
+                user_id,
+                json!({ "last_active_at": Utc::now().to_rfc3339() }),
             )
             .await
     }
 
-    pub async fn save_chat(
+    async fn list_goals(&self, user_id: &str) -> Result<Vec<Value>, String> {
+        to_values(self.repo.find::<Goal>("user_id", user_id).await?)
+    }
+
+    async fn get_goal(&self, goal_id: &str) -> Result<Option<Value>, String> {
+        self.repo
+            .get::<Goal>(goal_id)
+            .await?
+            .map(to_value)
+            .transpose()
+    }
+
+    async fn list_tasks(&self, goal_id: &str) -> Result<Vec<Value>, String> {
+        to_values(self.repo.find::<Task>("goal_id", goal_id).await?)
+    }
+
+    async fn get_task(&self, task_id: &str) -> Result<Option<Value>, String> {
+        self.repo
+            .get::<Task>(task_id)
+            .await?
+            .map(to_value)
+            .transpose()
+    }
+
+    async fn add_task(&self, task: Value) -> Result<String, String> {
+        let task: Task = from_value(task, Task::NAME)?;
+        self.repo.insert(&task).await
+    }
+
+    /// One request, which PostgREST applies as a single statement.
+    async fn add_tasks(&self, tasks: Vec<Value>) -> Result<Vec<String>, String> {
+        let tasks: Vec<Task> = tasks
+            .into_iter()
+            .map(|task| from_value(task, Task::NAME))
+            .collect::<Result<_, _>>()?;
+        self.repo.insert_all_returning(&tasks).await
+    }
+
+    async fn complete_task(&self, user_id: &str, task_id: &str) -> Result<(i64, i64), String> {
+        let task = self
+            .repo
+            .get::<Task>(task_id)
+            .await?
+            .ok_or_else(|| format!("Task {} not found", task_id))?;
+        if task.status.as_deref() == Some("completed") {
+            return Err(format!("Task {} is already completed", task_id));
+        }
+        let goal_id = task.goal_id.to_string();
+        let goal = self
+            .repo
+            .get::<Goal>(&goal_id)
+            .await?
+            .filter(|goal| goal.user_id.to_string() == user_id)
+            .ok_or_else(|| format!("Task {} does not belong to user {}", task_id, user_id))?;
+        let xp_reward = i64::from(task.xp_reward.unwrap_or(10));
+
+        self.repo
+            .update::<Task>(
+                task_id,
+                json!({ "status": "completed", "completed_at": Utc::now().to_rfc3339() }),
+            )
+            .await?;
+
+        let goal_xp = i64::from(goal.xp) + xp_reward;
+        self.repo
+            .update::<Goal>(
+                &goal_id,
+                json!({ "xp": goal_xp, "level": level_for_xp(goal_xp) }),
+            )
+            .await?;
+
+        let user = self.repo.get::<User>(user_id).await?;
+        let total_xp = user.map_or(0, |user| i64::from(user.total_xp)) + xp_reward;
+        let level = level_for_xp(total_xp);
+        self.repo
+            .update::<User>(user_id, json!({ "total_xp": total_xp, "level": level }))
+            .await?;
+
+        Ok((xp_reward, level))
+    }
+
+    async fn list_actor_templates(&self) -> Result<Vec<Value>, String> {
+        let query = self.repo.query::<AiAgent>().is_null("user_id");
+        to_values(self.repo.fetch::<AiAgent>(query).await?)
+    }
+
+    async fn get_actor_template(&self, agent_id: &str) -> Result<Option<Value>, String> {
+        let query = self
+            .repo
+            .query::<AiAgent>()
+            .eq("id", agent_id)
+            .is_null("user_id");
+        self.repo
+            .fetch::<AiAgent>(query)
+            .await?
+            .into_iter()
+            .next()
+            .map(to_value)
+            .transpose()
+    }
+
+    async fn save_actor(&self, actor: Value) -> Result<(), String> {
+        let agent: AiAgent = from_value(actor, AiAgent::NAME)?;
+        self.repo.upsert(&agent).await
+    }
+
+    async fn delete_actor(&self, actor_id: &str) -> Result<(), String> {
+        self.repo
+            .delete_where::<Interaction>("actor_id", actor_id)
+            .await?;
+        self.repo
+            .delete_where::<HistoricalInteraction>("actor_id", actor_id)
+            .await?;
+        self.repo
+            .delete_where::<ActorStateRow>("actor_id", actor_id)
+            .await?;
+        self.repo
+            .update_where::<Goal>("agent_id", actor_id, json!({ "agent_id": null }))
+            .await?;
+        self.repo.delete::<AiAgent>(actor_id).await
+    }
+
+    async fn list_user_actors(&self, user_id: &str) -> Result<Vec<Value>, String> {
+        to_values(self.repo.find::<AiAgent>("user_id", user_id).await?)
+    }
+
+    async fn add_interaction(&self, interaction: Value) -> Result<String, String> {
+        let interaction: Interaction = from_value(interaction, Interaction::NAME)?;
+        self.repo.insert(&interaction).await
+    }
+
+    async fn list_interactions(&self, actor_id: &str) -> Result<Vec<Value>, String> {
+        let query = self
+            .repo
+            .query::<Interaction>()
+            .eq("actor_id", actor_id)
+            .order("created_at", true);
+        to_values(self.repo.fetch::<Interaction>(query).await?)
+    }
+
+    async fn list_historical_interactions(&self, actor_id: &str) -> Result<Vec<Value>, String> {
+        let query = self
+            .repo
+            .query::<HistoricalInteraction>()
+            .eq("actor_id", actor_id)
+            .order("created_at", true);
+        to_values(self.repo.fetch::<HistoricalInteraction>(query).await?)
+    }
+
+    async fn save_knowledge_document(&self, document: Value) -> Result<(), String> {
+        let document: KnowledgeDocument = from_value(document, KnowledgeDocument::NAME)?;
+        self.repo.upsert(&document).await
+    }
+
+    async fn list_knowledge_documents(&self, actor_id: &str) -> Result<Vec<Value>, String> {
+        let query = self
+            .repo
+            .query::<KnowledgeDocument>()
+            .eq("actor_id", actor_id)
+            .order("created_at", true);
+        to_values(self.repo.fetch::<KnowledgeDocument>(query).await?)
+    }
+
+    async fn get_knowledge_document(
         &self,
         actor_id: &str,
-        message: &str,
-        embedding: Option<Value>,
-    ) -> Result<String, String> {
-        self.client
-            .insert(
-                "chats",
-                json!({
-                    "actor_id": actor_id,
-                    "message": message,
-                    "embedding": embedding,
-                }),
-            )
+        document_id: &str,
+    ) -> Result<Option<Value>, String> {
+        let query = self
+            .repo
+            .query::<KnowledgeDocument>()
+            .eq("id", document_id)
+            .eq("actor_id", actor_id);
+        self.repo
+            .fetch::<KnowledgeDocument>(query)
+            .await?
+            .into_iter()
+            .next()
+            .map(to_value)
+            .transpose()
+    }
+
+    async fn delete_knowledge_document(
+        &self,
+        actor_id: &str,
+        document_id: &str,
+    ) -> Result<(), String> {
+        if self
+            .get_knowledge_document(actor_id, document_id)
+            .await?
+            .is_none()
+        {
+            return Err(format!("Document {} not found", document_id));
+        }
+        self.repo.delete::<KnowledgeDocument>(document_id).await
+    }
+
+    async fn add_notification(&self, notification: Value) -> Result<String, String> {
+        let notification: Notification = from_value(notification, Notification::NAME)?;
+        self.repo.insert(&notification).await
+    }
+
+    async fn list_notifications(
+        &self,
+        user_id: &str,
+        unread_only: bool,
+        limit: usize,
+    ) -> Result<Vec<Value>, String> {
+        let mut query = self
+            .repo
+            .query::<Notification>()
+            .eq("user_id", user_id)
+            .order("created_at", false)
+            .limit(limit);
+        if unread_only {
+            query = query.is_null("read_at");
+        }
+        to_values(self.repo.fetch::<Notification>(query).await?)
+    }
+
+    async fn get_notification_preferences(&self, user_id: &str) -> Result<Option<Value>, String> {
+        self.repo
+            .find::<NotificationPreferencesRow>("user_id", user_id)
+            .await?
+            .into_iter()
+            .next()
+            .map(to_value)
+            .transpose()
+    }
+
+    async fn set_notification_preferences(
+        &self,
+        user_id: &str,
+        mut preferences: Value,
+    ) -> Result<(), String> {
+        preferences["user_id"] = json!(user_id);
+        preferences["updated_at"] = json!(Utc::now().to_rfc3339());
+        let preferences: NotificationPreferencesRow =
+            from_value(preferences, NotificationPreferencesRow::NAME)?;
+        self.repo.upsert(&preferences).await
+    }
+
+    async fn get_user_quota(&self, user_id: &str) -> Result<Option<Value>, String> {
+        self.repo
+            .find::<UserQuota>("user_id", user_id)
+            .await?
+            .into_iter()
+            .next()
+            .map(to_value)
+            .transpose()
+    }
+
+    async fn set_user_quota(&self, user_id: &str, mut quota: Value) -> Result<(), String> {
+        quota["user_id"] = json!(user_id);
+        quota["updated_at"] = json!(Utc::now().to_rfc3339());
+        let quota: UserQuota = from_value(quota, UserQuota::NAME)?;
+        self.repo.upsert(&quota).await
+    }
+
+    async fn update_notification(
+        &self,
+        user_id: &str,
+        notification_id: &str,
+        changes: Value,
+    ) -> Result<(), String> {
+        let query = self
+            .repo
+            .query::<Notification>()
+            .eq("id", notification_id)
+            .eq("user_id", user_id);
+        if self.repo.fetch::<Notification>(query).await?.is_empty() {
+            return Err(format!("Notification {} not found", notification_id));
+        }
+        self.repo
+            .update::<Notification>(notification_id, changes)
             .await
     }
+
+    /// PostgREST has no transactions, so a failure part way leaves some rows behind; every
+    /// step is safe to repeat, so retrying finishes the job.
+    async fn delete_user(&self, user_id: &str) -> Result<(), String> {
+        for agent in self.repo.find::<AiAgent>("user_id", user_id).await? {
+            self.delete_actor(&agent.id.to_string()).await?;
+        }
+        for goal in self.repo.find::<Goal>("user_id", user_id).await? {
+            self.repo
+                .delete_where::<Task>("goal_id", &goal.id.to_string())
+                .await?;
+        }
+        self.repo.delete_where::<Goal>("user_id", user_id).await?;
+        self.repo
+            .delete_where::<KnowledgeDocument>("user_id", user_id)
+            .await?;
+        self.repo
+            .delete_where::<Notification>("user_id", user_id)
+            .await?;
+        self.repo
+            .delete::<NotificationPreferencesRow>(user_id)
+            .await?;
+        self.repo.delete::<User>(user_id).await
+    }
+
+    async fn record_erasure(&self, record: Value) -> Result<(), String> {
+        let erasure: AccountErasure = from_value(record, AccountErasure::NAME)?;
+        self.repo.insert(&erasure).await.map(|_| ())
+    }
+
+    async fn add_audit_entry(&self, entry: Value) -> Result<(), String> {
+        let entry: AuditEntry = from_value(entry, AuditEntry::NAME)?;
+        self.repo.insert(&entry).await.map(|_| ())
+    }
+
+    async fn list_audit_entries(&self, query: &AuditQuery) -> Result<Vec<Value>, String> {
+        let mut select = self.repo.query::<AuditEntry>();
+        for (column, value) in query.equals() {
+            select = select.eq(column, value);
+        }
+        if let Some(since) = query.since {
+            select = select.gte("created_at", &since.to_rfc3339());
+        }
+        if let Some(until) = query.until {
+            select = select.lt("created_at", &until.to_rfc3339());
+        }
+        let select = select.order("created_at", false).limit(query.limit());
+        to_values(self.repo.fetch::<AuditEntry>(select).await?)
+    }
+
+    async fn add_llm_usage(&self, rows: Vec<Value>) -> Result<(), String> {
+        let rows: Vec<LlmUsage> = rows
+            .into_iter()
+            .map(|row| from_value(row, LlmUsage::NAME))
+            .collect::<Result<_, _>>()?;
+        self.repo.insert_all(&rows).await
+    }
+
+    /// PostgREST has no aggregates by default, so the matching rows are summed here.
+    async fn usage_report(
+        &self,
+        query: &UsageQuery,
+        groups: &[UsageGroup],
+    ) -> Result<Vec<UsageTotals>, String> {
+        let mut select = self.repo.query::<LlmUsage>();
+        for (column, value) in query.equals() {
+            select = select.eq(column, value);
+        }
+        if let Some(since) = query.since {
+            select = select.gte("created_at", &since.to_rfc3339());
+        }
+        if let Some(until) = query.until {
+            select = select.lt("created_at", &until.to_rfc3339());
+        }
+        let rows = to_values(self.repo.fetch_all::<LlmUsage>(select).await?)?;
+        Ok(aggregate(&rows, groups))
+    }
 }
@@ -0,0 +1,314 @@
+use crate::services::database::Database;
+use crate::services::llm::{ChatCompletion, TokenUsage};
+use crate::services::Services;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::env;
+
+/// Prices used when `LLM_PRICES` is not set, in USD per million tokens.
+const DEFAULT_PRICES: &str = "gpt-4o=2.5:10,gpt-4o-mini=0.15:0.6";
+
+/// What a model charges, in USD per million tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ModelPrice {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+/// Model prices from `LLM_PRICES`, formatted as `model=prompt:completion` pairs separated by
+/// commas, in USD per million tokens. Models without an entry use the price set through
+/// `LLM_PROMPT_PRICE_PER_1K` and `LLM_COMPLETION_PRICE_PER_1K`.
+#[derive(Debug, Clone)]
+pub struct PriceTable {
+    prices: Vec<(String, ModelPrice)>,
+    default: ModelPrice,
+}
+
+fn env_f64(name: &str, default: f64) -> f64 {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+impl PriceTable {
+    pub fn from_env() -> Self {
+        let default = ModelPrice {
+            prompt: env_f64("LLM_PROMPT_PRICE_PER_1K", 0.0025) * 1000.0,
+            completion: env_f64("LLM_COMPLETION_PRICE_PER_1K", 0.01) * 1000.0,
+        };
+        let spec = env::var("LLM_PRICES").unwrap_or_else(|_| DEFAULT_PRICES.to_string());
+        PriceTable::parse(&spec, default)
+    }
+
+    pub fn parse(spec: &str, default: ModelPrice) -> Self {
+        let prices = spec
+            .split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .filter_map(|entry| {
+                let parsed = entry.split_once('=').and_then(|(model, price)| {
+                    let (prompt, completion) = price.split_once(':')?;
+                    Some((
+                        model.trim().to_string(),
+                        ModelPrice {
+                            prompt: prompt.trim().parse().ok()?,
+                            completion: completion.trim().parse().ok()?,
+                        },
+                    ))
+                });
+                if parsed.is_none() {
+                    println!("Warning: ignoring invalid LLM price '{}'", entry);
+                }
+                parsed
+            })
+            .collect();
+        PriceTable { prices, default }
+    }
+
+    /// The entry for `model` or else the longest entry it starts with, so dated snapshots such
+    /// as `gpt-4o-mini-2024-07-18` use the `gpt-4o-mini` price.
+    pub fn price(&self, model: &str) -> ModelPrice {
+        self.prices
+            .iter()
+            .filter(|(name, _)| model.starts_with(name.as_str()))
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, price)| *price)
+            .unwrap_or(self.default)
+    }
+
+    pub fn cost(&self, model: &str, usage: &TokenUsage) -> f64 {
+        let price = self.price(model);
+        (usage.prompt_tokens as f64 * price.prompt
+            + usage.completion_tokens as f64 * price.completion)
+            / 1_000_000.0
+    }
+}
+
+/// One priced LLM call, as stored in `llm_usage`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LlmCall {
+    pub provider: String,
+    pub model: String,
+    pub usage: TokenUsage,
+    pub cost_usd: f64,
+}
+
+/// Prices a completion. Once stored with `store_calls`, the call counts against the user's
+/// monthly quota.
+pub fn meter(services: &Services, completion: &ChatCompletion) -> LlmCall {
+    let cost_usd = services.prices.cost(&completion.model, &completion.usage);
+    LlmCall {
+        provider: completion.provider.clone(),
+        model: completion.model.clone(),
+        usage: completion.usage.clone(),
+        cost_usd,
+    }
+}
+
+/// Stores calls made for the user in `llm_usage`. `purpose` says what they were for:
+/// interaction, background, routing, planning or huddle. Failures are printed rather than
+/// returned, so accounting never fails the request it accounts for.
+pub async fn store_calls(
+    services: &Services,
+    user_id: &str,
+    actor_id: Option<&str>,
+    interaction_id: Option<&str>,
+    purpose: &str,
+    calls: &[LlmCall],
+) {
+    if calls.is_empty() {
+        return;
+    }
+    let Some(db) = &services.db else {
+        println!("Warning: database not configured, LLM usage not stored");
+        return;
+    };
+    let rows = calls
+        .iter()
+        .map(|call| {
+            json!({
+                "user_id": user_id,
+                "actor_id": actor_id,
+                "interaction_id": interaction_id,
+                "purpose": purpose,
+                "provider": call.provider,
+                "model": call.model,
+                "prompt_tokens": call.usage.prompt_tokens,
+                "completion_tokens": call.usage.completion_tokens,
+                "total_tokens": call.usage.total_tokens,
+                "cost_usd": call.cost_usd,
+            })
+        })
+        .collect();
+    if let Err(e) = db.add_llm_usage(rows).await {
+        println!("Warning: failed to store LLM usage: {}", e);
+    }
+}
+
+/// A dimension usage reports can be broken down by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsageGroup {
+    User,
+    Actor,
+    Model,
+    Day, // UTC
+}
+
+impl UsageGroup {
+    fn parse(name: &str) -> Option<Self> {
+        match name.trim() {
+            "user" => Some(UsageGroup::User),
+            "actor" => Some(UsageGroup::Actor),
+            "model" => Some(UsageGroup::Model),
+            "day" => Some(UsageGroup::Day),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            UsageGroup::User => "user",
+            UsageGroup::Actor => "actor",
+            UsageGroup::Model => "model",
+            UsageGroup::Day => "day",
+        }
+    }
+
+    /// The value of this dimension for an `llm_usage` row.
+    fn value_of(&self, row: &Value) -> Option<String> {
+        match self {
+            UsageGroup::User => row["user_id"].as_str().map(str::to_string),
+            UsageGroup::Actor => row["actor_id"].as_str().map(str::to_string),
+            UsageGroup::Model => row["model"].as_str().map(str::to_string),
+            UsageGroup::Day => row["created_at"]
+                .as_str()
+                .and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+                .map(|at| at.with_timezone(&Utc).format("%Y-%m-%d").to_string()),
+        }
+    }
+}
+
+/// Filters and breakdown for `GET /admin/usage` and `GET /me/usage`. `group_by` lists
+/// dimensions separated by commas and defaults to `day`; `since` is inclusive and `until`
+/// exclusive.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UsageQuery {
+    pub group_by: Option<String>,
+    pub user_id: Option<String>,
+    pub actor_id: Option<String>,
+    pub model: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl UsageQuery {
+    pub fn groups(&self) -> Result<Vec<UsageGroup>, String> {
+        let mut groups = Vec::new();
+        for name in self.group_by.as_deref().unwrap_or("day").split(',') {
+            let group = UsageGroup::parse(name).ok_or_else(|| {
+                format!(
+                    "Unknown group_by '{}'; use user, actor, model or day",
+                    name.trim()
+                )
+            })?;
+            if !groups.contains(&group) {
+                groups.push(group);
+            }
+        }
+        Ok(groups)
+    }
+
+    /// The columns that must equal a given value.
+    pub fn equals(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("user_id", &self.user_id),
+            ("actor_id", &self.actor_id),
+            ("model", &self.model),
+        ]
+        .into_iter()
+        .filter_map(|(column, value)| value.as_deref().map(|value| (column, value)))
+        .collect()
+    }
+}
+
+/// Usage summed over the calls sharing the dimensions that are set.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UsageTotals {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actor_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub day: Option<String>,
+    pub calls: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+    pub cost_usd: f64,
+}
+
+impl UsageTotals {
+    fn add(&mut self, other: &UsageTotals) {
+        self.calls += other.calls;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+        self.cost_usd += other.cost_usd;
+    }
+
+    pub fn set(&mut self, group: UsageGroup, value: Option<String>) {
+        match group {
+            UsageGroup::User => self.user_id = value,
+            UsageGroup::Actor => self.actor_id = value,
+            UsageGroup::Model => self.model = value,
+            UsageGroup::Day => self.day = value,
+        }
+    }
+}
+
+/// Sums `llm_usage` rows by `groups`, ordered by the group values. For backends that can't
+/// aggregate in the database.
+pub fn aggregate(rows: &[Value], groups: &[UsageGroup]) -> Vec<UsageTotals> {
+    let number = |row: &Value, column: &str| row[column].as_u64().unwrap_or_default();
+    let mut totals: BTreeMap<Vec<Option<String>>, UsageTotals> = BTreeMap::new();
+    for row in rows {
+        let key: Vec<Option<String>> = groups.iter().map(|group| group.value_of(row)).collect();
+        let entry = totals.entry(key.clone()).or_insert_with(|| {
+            let mut entry = UsageTotals::default();
+            for (group, value) in groups.iter().zip(key) {
+                entry.set(*group, value);
+            }
+            entry
+        });
+        entry.add(&UsageTotals {
+            calls: 1,
+            prompt_tokens: number(row, "prompt_tokens"),
+            completion_tokens: number(row, "completion_tokens"),
+            total_tokens: number(row, "total_tokens"),
+            cost_usd: row["cost_usd"].as_f64().unwrap_or_default(),
+            ..Default::default()
+        });
+    }
+    totals.into_values().collect()
+}
+
+/// The usage report for `query`: totals per group and overall.
+pub async fn usage_report(db: &dyn Database, query: &UsageQuery) -> Result<Value, String> {
+    let groups = query.groups()?;
+    let rows = db.usage_report(query, &groups).await?;
+    let mut total = UsageTotals::default();
+    for row in &rows {
+        total.add(row);
+    }
+    Ok(json!({
+        "group_by": groups.iter().map(UsageGroup::name).collect::<Vec<_>>(),
+        "since": query.since,
+        "until": query.until,
+        "rows": rows,
+        "total": total,
+    }))
+}
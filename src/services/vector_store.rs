@@ -0,0 +1,57 @@
+use async_trait::async_trait;
+use serde::Serialize;
+use serde_json::Value;
+
+/// A single match returned by a vector query.
+#[derive(Debug, Clone, Serialize)]
+pub struct VectorMatch {
+    pub id: String,
+    pub score: f32,
+    pub metadata: Value,
+}
+
+/// Namespace holding every user's conversation history.
+pub const CHAT_NAMESPACE: &str = "";
+
+/// Id prefix shared by all of a user's vectors in `CHAT_NAMESPACE`.
+pub fn chat_id_prefix(user_id: &str) -> String {
+    format!("chat-{}-", user_id)
+}
+
+/// Namespace holding the knowledge documents of one actor.
+pub fn knowledge_namespace(actor_id: &str) -> String {
+    format!("knowledge-{}", actor_id)
+}
+
+/// Stores embeddings with metadata and finds the closest ones to a query vector. Vectors live
+/// in namespaces; queries and deletes only see their own namespace.
+#[async_trait]
+pub trait VectorStore: Send + Sync {
+    async fn upsert(
+        &self,
+        namespace: &str,
+        id: &str,
+        values: Vec<f32>,
+        metadata: Value,
+    ) -> Result<(), String>;
+
+    /// Returns the `top_k` closest vectors whose metadata matches `filter` (exact equality on
+    /// every key).
+    async fn query(
+        &self,
+        namespace: &str,
+        values: Vec<f32>,
+        top_k: usize,
+        filter: Value,
+    ) -> Result<Vec<VectorMatch>, String>;
+
+    /// Every vector whose id starts with `prefix`, with its metadata. Scores are zero since
+    /// nothing is compared.
+    async fn list(&self, namespace: &str, prefix: &str) -> Result<Vec<VectorMatch>, String>;
+
+    /// Deletes every vector whose metadata matches `filter`.
+    async fn delete(&self, namespace: &str, filter: Value) -> Result<(), String>;
+
+    /// Deletes the vectors with the given ids; ids that don't exist are ignored.
+    async fn delete_ids(&self, namespace: &str, ids: &[String]) -> Result<(), String>;
+}
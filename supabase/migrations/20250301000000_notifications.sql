@@ -0,0 +1,33 @@
+-- Track when users last talked to a coach, for inactivity segments
+ALTER TABLE users
+ADD COLUMN last_active_at TIMESTAMPTZ;
+
+-- Per-user notification inbox
+CREATE TABLE notifications (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    title TEXT NOT NULL,
+    body TEXT NOT NULL,
+    kind TEXT NOT NULL DEFAULT 'broadcast'
+      CHECK (kind IN ('broadcast', 'reminder', 'actor')),
+    actor_id UUID,
+    data JSONB NOT NULL DEFAULT '{}',
+    delivered_at TIMESTAMPTZ,
+    read_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT now()
+);
+
+CREATE INDEX notifications_user_id_created_at_idx
+  ON notifications (user_id, created_at DESC);
+
+ALTER TABLE notifications ENABLE ROW LEVEL SECURITY;
+
+CREATE POLICY "Users can read own notifications"
+  ON notifications FOR SELECT
+  TO authenticated
+  USING (auth.uid() = user_id);
+
+CREATE POLICY "Users can update own notifications"
+  ON notifications FOR UPDATE
+  TO authenticated
+  USING (auth.uid() = user_id);
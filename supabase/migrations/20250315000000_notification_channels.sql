@@ -0,0 +1,20 @@
+-- Outbound channels each user wants notifications delivered through
+CREATE TABLE notification_preferences (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    channels TEXT[] NOT NULL DEFAULT '{}',
+    email TEXT,
+    push_subscription JSONB,
+    webhook_url TEXT,
+    updated_at TIMESTAMPTZ DEFAULT now()
+);
+
+ALTER TABLE notification_preferences ENABLE ROW LEVEL SECURITY;
+
+CREATE POLICY "Users can manage own notification preferences"
+  ON notification_preferences FOR ALL
+  TO authenticated
+  USING (auth.uid() = user_id);
+
+-- Outcome of each channel delivery, e.g. {"email": "sent", "push": "Push subscription has expired"}
+ALTER TABLE notifications
+ADD COLUMN channel_deliveries JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,31 @@
+-- Personal actors are stored alongside the seeded agents; seeded agents have no owner
+ALTER TABLE ai_agents
+ADD COLUMN user_id UUID REFERENCES users(id) ON DELETE CASCADE;
+
+CREATE INDEX ai_agents_user_id_idx ON ai_agents (user_id);
+
+CREATE POLICY "Users can manage own agents"
+  ON ai_agents FOR ALL
+  TO authenticated
+  USING (auth.uid() = user_id);
+
+-- Deleting an agent removes everything recorded about it
+ALTER TABLE interactions
+DROP CONSTRAINT interactions_actor_id_fkey,
+ADD CONSTRAINT interactions_actor_id_fkey
+  FOREIGN KEY (actor_id) REFERENCES ai_agents(id) ON DELETE CASCADE;
+
+ALTER TABLE actor_states
+DROP CONSTRAINT actor_states_actor_id_fkey,
+ADD CONSTRAINT actor_states_actor_id_fkey
+  FOREIGN KEY (actor_id) REFERENCES ai_agents(id) ON DELETE CASCADE;
+
+ALTER TABLE historical_interactions
+DROP CONSTRAINT historical_interactions_actor_id_fkey,
+ADD CONSTRAINT historical_interactions_actor_id_fkey
+  FOREIGN KEY (actor_id) REFERENCES ai_agents(id) ON DELETE CASCADE;
+
+ALTER TABLE goals
+DROP CONSTRAINT goals_agent_id_fkey,
+ADD CONSTRAINT goals_agent_id_fkey
+  FOREIGN KEY (agent_id) REFERENCES ai_agents(id) ON DELETE SET NULL;
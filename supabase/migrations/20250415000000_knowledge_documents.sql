@@ -0,0 +1,22 @@
+-- Documents uploaded to an agent; their chunks live in the vector store under the
+-- `knowledge-<agent id>` namespace
+CREATE TABLE knowledge_documents (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    actor_id UUID REFERENCES ai_agents(id) ON DELETE CASCADE NOT NULL,
+    user_id UUID REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    title TEXT NOT NULL,
+    format TEXT NOT NULL CHECK (format IN ('text', 'markdown', 'html', 'pdf')),
+    size_bytes INTEGER NOT NULL,
+    chunk_count INTEGER NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT now(),
+    updated_at TIMESTAMPTZ DEFAULT now()
+);
+
+CREATE INDEX knowledge_documents_actor_id_idx ON knowledge_documents (actor_id);
+
+ALTER TABLE knowledge_documents ENABLE ROW LEVEL SECURITY;
+
+CREATE POLICY "Users can manage own knowledge documents"
+  ON knowledge_documents FOR ALL
+  TO authenticated
+  USING (auth.uid() = user_id);
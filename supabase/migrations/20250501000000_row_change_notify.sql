@@ -0,0 +1,48 @@
+-- Announce task and goal changes on the `row_changes` channel so the backend's realtime
+-- listener can hand them to the user's coaches, whichever client made the change.
+-- Payloads carry the owning user, the goal's assigned agent and the changed rows without
+-- their descriptions, which keeps them under pg_notify's 8000 byte limit.
+CREATE OR REPLACE FUNCTION notify_row_change()
+RETURNS trigger
+LANGUAGE plpgsql
+AS $$
+DECLARE
+  changed jsonb := to_jsonb(CASE WHEN TG_OP = 'DELETE' THEN OLD ELSE NEW END);
+  owner_id uuid;
+  assigned_agent_id uuid;
+  goal_category text;
+  goal_title text;
+BEGIN
+  IF TG_TABLE_NAME = 'tasks' THEN
+    SELECT g.user_id, g.agent_id, g.category, g.title
+      INTO owner_id, assigned_agent_id, goal_category, goal_title
+      FROM goals g
+     WHERE g.id = (changed->>'goal_id')::uuid;
+  ELSE
+    owner_id := (changed->>'user_id')::uuid;
+    assigned_agent_id := (changed->>'agent_id')::uuid;
+    goal_category := changed->>'category';
+    goal_title := changed->>'title';
+  END IF;
+
+  PERFORM pg_notify('row_changes', jsonb_build_object(
+    'table', TG_TABLE_NAME,
+    'type', TG_OP,
+    'user_id', owner_id,
+    'agent_id', assigned_agent_id,
+    'goal_category', goal_category,
+    'goal_title', goal_title,
+    'record', CASE WHEN TG_OP = 'DELETE' THEN NULL ELSE to_jsonb(NEW) - 'description' END,
+    'old_record', CASE WHEN TG_OP = 'INSERT' THEN NULL ELSE to_jsonb(OLD) - 'description' END
+  )::text);
+  RETURN NULL;
+END;
+$$;
+
+CREATE TRIGGER tasks_notify_row_change
+  AFTER INSERT OR UPDATE OR DELETE ON tasks
+  FOR EACH ROW EXECUTE FUNCTION notify_row_change();
+
+CREATE TRIGGER goals_notify_row_change
+  AFTER INSERT OR UPDATE OR DELETE ON goals
+  FOR EACH ROW EXECUTE FUNCTION notify_row_change();
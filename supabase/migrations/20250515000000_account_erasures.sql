@@ -0,0 +1,12 @@
+-- One row per erased account. user_id has no foreign key: the row must outlive the user.
+-- Only the service role writes or reads it, so RLS is enabled without policies.
+CREATE TABLE account_erasures (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL,
+    summary JSONB NOT NULL DEFAULT '{}'::jsonb,
+    erased_at TIMESTAMPTZ DEFAULT now()
+);
+
+CREATE INDEX account_erasures_user_id_idx ON account_erasures (user_id);
+
+ALTER TABLE account_erasures ENABLE ROW LEVEL SECURITY;
@@ -0,0 +1,39 @@
+-- Append-only record of administrative and actor-mutating actions: who did what, to
+-- what, when and from where. performed_by has no foreign key so entries outlive the user.
+CREATE TABLE audit_log (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    action TEXT NOT NULL,
+    role TEXT NOT NULL CHECK (role IN ('user', 'admin', 'system')),
+    performed_by TEXT,
+    target_type TEXT,
+    target_id TEXT,
+    details JSONB NOT NULL DEFAULT '{}'::jsonb,
+    ip TEXT,
+    user_agent TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX audit_log_created_at_idx ON audit_log (created_at DESC);
+CREATE INDEX audit_log_action_idx ON audit_log (action, created_at DESC);
+CREATE INDEX audit_log_performed_by_idx ON audit_log (performed_by, created_at DESC);
+CREATE INDEX audit_log_target_idx ON audit_log (target_type, target_id);
+
+-- Only the service role reads or writes the log
+ALTER TABLE audit_log ENABLE ROW LEVEL SECURITY;
+
+CREATE OR REPLACE FUNCTION reject_audit_log_change()
+RETURNS trigger
+LANGUAGE plpgsql
+AS $$
+BEGIN
+  RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$;
+
+CREATE TRIGGER audit_log_append_only
+  BEFORE UPDATE OR DELETE ON audit_log
+  FOR EACH ROW EXECUTE FUNCTION reject_audit_log_change();
+
+CREATE TRIGGER audit_log_no_truncate
+  BEFORE TRUNCATE ON audit_log
+  FOR EACH STATEMENT EXECUTE FUNCTION reject_audit_log_change();
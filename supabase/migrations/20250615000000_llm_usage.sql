@@ -0,0 +1,29 @@
+-- One row per LLM call: tokens, model and cost, linked to the interaction it served when
+-- there was one. Routing, planning and background calls have no interaction.
+CREATE TABLE llm_usage (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    actor_id UUID, -- No foreign key, so usage outlives a deleted actor
+    interaction_id UUID REFERENCES interactions(id) ON DELETE SET NULL,
+    purpose TEXT NOT NULL, -- interaction, background, routing, planning or huddle
+    provider TEXT NOT NULL,
+    model TEXT NOT NULL,
+    prompt_tokens INTEGER NOT NULL DEFAULT 0,
+    completion_tokens INTEGER NOT NULL DEFAULT 0,
+    total_tokens INTEGER NOT NULL DEFAULT 0,
+    cost_usd NUMERIC(12, 6) NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX llm_usage_created_at_idx ON llm_usage (created_at);
+CREATE INDEX llm_usage_user_idx ON llm_usage (user_id, created_at);
+CREATE INDEX llm_usage_actor_idx ON llm_usage (actor_id, created_at);
+CREATE INDEX llm_usage_interaction_idx ON llm_usage (interaction_id);
+
+ALTER TABLE llm_usage ENABLE ROW LEVEL SECURITY;
+
+-- Rows are written by the service role; users can only read their own
+CREATE POLICY "Users can view their LLM usage"
+  ON llm_usage FOR SELECT
+  TO authenticated
+  USING (user_id = auth.uid());
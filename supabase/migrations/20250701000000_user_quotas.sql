@@ -0,0 +1,16 @@
+-- Each user's quota plan and when their monthly count was last reset. Users without a row
+-- are on the default plan. Monthly usage itself is summed from llm_usage.
+CREATE TABLE user_quotas (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    plan TEXT NOT NULL,
+    reset_at TIMESTAMPTZ,
+    updated_at TIMESTAMPTZ DEFAULT now()
+);
+
+ALTER TABLE user_quotas ENABLE ROW LEVEL SECURITY;
+
+-- Plans are assigned by the service role; users can only read theirs
+CREATE POLICY "Users can view their quota"
+  ON user_quotas FOR SELECT
+  TO authenticated
+  USING (user_id = auth.uid());
@@ -0,0 +1,46 @@
+-- Row change notifications carry only what the listener reads: the row's id, status and a
+-- shortened title, plus the owning user, the goal's assigned agent and the goal's category
+-- and title. Large rows can't push the payload past pg_notify's 8000 byte limit, and should a
+-- notification fail anyway, the write that caused it still goes through.
+CREATE OR REPLACE FUNCTION notify_row_change()
+RETURNS trigger
+LANGUAGE plpgsql
+AS $$
+DECLARE
+  changed jsonb := to_jsonb(CASE WHEN TG_OP = 'DELETE' THEN OLD ELSE NEW END);
+  owner_id uuid;
+  assigned_agent_id uuid;
+  goal_category text;
+  goal_title text;
+BEGIN
+  IF TG_TABLE_NAME = 'tasks' THEN
+    SELECT g.user_id, g.agent_id, g.category, g.title
+      INTO owner_id, assigned_agent_id, goal_category, goal_title
+      FROM goals g
+     WHERE g.id = (changed->>'goal_id')::uuid;
+  ELSE
+    owner_id := (changed->>'user_id')::uuid;
+    assigned_agent_id := (changed->>'agent_id')::uuid;
+    goal_category := changed->>'category';
+    goal_title := changed->>'title';
+  END IF;
+
+  BEGIN
+    PERFORM pg_notify('row_changes', jsonb_build_object(
+      'table', TG_TABLE_NAME,
+      'type', TG_OP,
+      'user_id', owner_id,
+      'agent_id', assigned_agent_id,
+      'goal_category', left(goal_category, 100),
+      'goal_title', left(goal_title, 200),
+      'record', CASE WHEN TG_OP = 'DELETE' THEN NULL ELSE jsonb_build_object(
+        'id', NEW.id, 'status', NEW.status, 'title', left(NEW.title, 200)) END,
+      'old_record', CASE WHEN TG_OP = 'INSERT' THEN NULL ELSE jsonb_build_object(
+        'id', OLD.id, 'status', OLD.status, 'title', left(OLD.title, 200)) END
+    )::text);
+  EXCEPTION WHEN others THEN
+    RAISE WARNING 'row change notification for % % failed: %', TG_TABLE_NAME, changed->>'id', SQLERRM;
+  END;
+  RETURN NULL;
+END;
+$$;
@@ -0,0 +1,893 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, App, HttpResponse, HttpServer};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use procuvita_backend::services::audit::AuditQuery;
+use procuvita_backend::services::channels::{NotificationChannel, NotificationPreferences};
+use procuvita_backend::services::database::{level_for_xp, Database};
+use procuvita_backend::services::llm::{ChatCompletion, EmbeddingBackend, LlmBackend, TokenUsage};
+use procuvita_backend::services::moderation::{ModerationCategory, ModerationClassifier};
+use procuvita_backend::services::postgres::PostgresService;
+use procuvita_backend::services::usage::{aggregate, UsageGroup, UsageQuery, UsageTotals};
+use procuvita_backend::services::vector_store::{VectorMatch, VectorStore};
+use procuvita_backend::{Backends, Services};
+use serde_json::{json, Value};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, Weak};
+use uuid::Uuid;
+
+/// Chat backend that plays back scripted assistant messages, then echoes the last user message.
+#[derive(Default)]
+pub struct MockLlm {
+    script: Mutex<VecDeque<Value>>,
+    pub requests: Mutex<Vec<Value>>,
+}
+
+impl MockLlm {
+    /// Queues an assistant message that requests a single tool call.
+    pub fn push_tool_call(&self, name: &str, arguments: Value) {
+        let mut script = self.script.lock().unwrap();
+        let id = format!("call_{}", script.len() + 1);
+        script.push_back(json!({
+            "role": "assistant",
+            "content": null,
+            "tool_calls": [{
+                "id": id,
+                "type": "function",
+                "function": { "name": name, "arguments": arguments.to_string() }
+            }]
+        }));
+    }
+
+    pub fn push_reply(&self, content: &str) {
+        self.script
+            .lock()
+            .unwrap()
+            .push_back(json!({ "role": "assistant", "content": content }));
+    }
+
+    /// Queues a failed completion, as if every provider had errored.
+    pub fn push_error(&self, error: &str) {
+        self.script
+            .lock()
+            .unwrap()
+            .push_back(json!({ "error": error }));
+    }
+}
+
+#[async_trait]
+impl LlmBackend for MockLlm {
+    async fn chat(&self, body: Value) -> Result<ChatCompletion, String> {
+        self.requests.lock().unwrap().push(body.clone());
+        let message = self.script.lock().unwrap().pop_front().unwrap_or_else(|| {
+            let last_user = body["messages"]
+                .as_array()
+                .and_then(|messages| messages.iter().rev().find(|m| m["role"] == "user"))
+                .and_then(|m| m["content"].as_str())
+                .unwrap_or_default()
+                .to_string();
+            json!({ "role": "assistant", "content": format!("Mock reply to: {}", last_user) })
+        });
+        if let Some(error) = message["error"].as_str() {
+            return Err(error.to_string());
+        }
+
+        Ok(ChatCompletion {
+            content: message["content"].as_str().map(str::to_string),
+            message,
+            usage: TokenUsage {
+                prompt_tokens: 20,
+                completion_tokens: 10,
+                total_tokens: 30,
+            },
+            provider: "mock".to_string(),
+            model: "mock-model".to_string(),
+        })
+    }
+}
+
+/// An error status to answer with and the `Retry-After` header to send along.
+type ScriptedError = (u16, Option<String>);
+
+/// OpenAI-compatible chat endpoints served over HTTP at `{base}/{provider}`, for exercising
+/// `LlmClient`. Each provider answers with its scripted error statuses first, then with a
+/// completion from model `{provider}-model`.
+#[derive(Default)]
+pub struct ProviderServer {
+    script: Mutex<HashMap<String, VecDeque<ScriptedError>>>,
+    hits: Mutex<HashMap<String, usize>>,
+}
+
+impl ProviderServer {
+    /// Queues `times` error responses, with a `Retry-After` header when given.
+    pub fn fail(&self, provider: &str, status: u16, retry_after: Option<&str>, times: usize) {
+        let mut script = self.script.lock().unwrap();
+        let queue = script.entry(provider.to_string()).or_default();
+        for _ in 0..times {
+            queue.push_back((status, retry_after.map(str::to_string)));
+        }
+    }
+
+    /// Requests the provider has received.
+    pub fn hits(&self, provider: &str) -> usize {
+        self.hits
+            .lock()
+            .unwrap()
+            .get(provider)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    async fn respond(server: web::Data<ProviderServer>, path: web::Path<String>) -> HttpResponse {
+        let provider = path.into_inner();
+        *server
+            .hits
+            .lock()
+            .unwrap()
+            .entry(provider.clone())
+            .or_default() += 1;
+        let scripted = server
+            .script
+            .lock()
+            .unwrap()
+            .get_mut(&provider)
+            .and_then(VecDeque::pop_front);
+        if let Some((status, retry_after)) = scripted {
+            let mut response = HttpResponse::build(StatusCode::from_u16(status).unwrap());
+            if let Some(retry_after) = retry_after {
+                response.insert_header(("Retry-After", retry_after));
+            }
+            return response.json(json!({ "error": { "message": "scripted failure" } }));
+        }
+        HttpResponse::Ok().json(json!({
+            "model": format!("{}-model", provider),
+            "choices": [{ "message": { "role": "assistant", "content": "Hello" } }],
+            "usage": { "prompt_tokens": 5, "completion_tokens": 1, "total_tokens": 6 }
+        }))
+    }
+
+    /// Starts serving on a free local port and returns the base URL.
+    pub fn start(self: &Arc<Self>) -> String {
+        let server = web::Data::from(self.clone());
+        let http = HttpServer::new(move || {
+            App::new().app_data(server.clone()).route(
+                "/{provider}/chat/completions",
+                web::post().to(Self::respond),
+            )
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let addr = http.addrs()[0];
+        actix_web::rt::spawn(http.run());
+        format!("http://{}", addr)
+    }
+}
+
+/// Deterministic bag-of-words embeddings, so texts sharing words end up close together.
+pub struct MockEmbeddings;
+
+const EMBEDDING_DIMENSIONS: usize = 64;
+
+#[async_trait]
+impl EmbeddingBackend for MockEmbeddings {
+    async fn embed(&self, text: &str) -> Result<Vec<f32>, String> {
+        let mut embedding = vec![0.0; EMBEDDING_DIMENSIONS];
+        for word in text
+            .to_lowercase()
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| word.len() > 2)
+        {
+            let mut hasher = DefaultHasher::new();
+            word.hash(&mut hasher);
+            embedding[hasher.finish() as usize % EMBEDDING_DIMENSIONS] += 1.0;
+        }
+        Ok(embedding)
+    }
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
+
+/// Id, values, metadata and namespace of a stored vector.
+pub type StoredVector = (String, Vec<f32>, Value, String);
+
+#[derive(Default)]
+pub struct InMemoryVectorStore {
+    pub vectors: Mutex<Vec<StoredVector>>,
+    pub upserts_before_failure: Mutex<Option<usize>>, // Fails every upsert once it reaches zero
+}
+
+impl InMemoryVectorStore {
+    pub fn in_namespace(&self, namespace: &str) -> Vec<Value> {
+        self.vectors
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, _, _, ns)| ns == namespace)
+            .map(|(_, _, metadata, _)| metadata.clone())
+            .collect()
+    }
+}
+
+#[async_trait]
+impl VectorStore for InMemoryVectorStore {
+    async fn upsert(
+        &self,
+        namespace: &str,
+        id: &str,
+        values: Vec<f32>,
+        metadata: Value,
+    ) -> Result<(), String> {
+        if let Some(left) = self.upserts_before_failure.lock().unwrap().as_mut() {
+            if *left == 0 {
+                return Err("Vector store unavailable".to_string());
+            }
+            *left -= 1;
+        }
+        let mut vectors = self.vectors.lock().unwrap();
+        vectors.retain(|(existing, _, _, ns)| existing != id || ns != namespace);
+        vectors.push((id.to_string(), values, metadata, namespace.to_string()));
+        Ok(())
+    }
+
+    async fn query(
+        &self,
+        namespace: &str,
+        values: Vec<f32>,
+        top_k: usize,
+        filter: Value,
+    ) -> Result<Vec<VectorMatch>, String> {
+        let filter = filter.as_object().cloned().unwrap_or_default();
+        let mut matches: Vec<VectorMatch> = self
+            .vectors
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, _, metadata, ns)| {
+                ns == namespace && filter.iter().all(|(key, value)| &metadata[key] == value)
+            })
+            .map(|(id, stored, metadata, _)| VectorMatch {
+                id: id.clone(),
+                score: cosine_similarity(&values, stored),
+                metadata: metadata.clone(),
+            })
+            .collect();
+        matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+        matches.truncate(top_k);
+        Ok(matches)
+    }
+
+    async fn list(&self, namespace: &str, prefix: &str) -> Result<Vec<VectorMatch>, String> {
+        Ok(self
+            .vectors
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(id, _, _, ns)| ns == namespace && id.starts_with(prefix))
+            .map(|(id, _, metadata, _)| VectorMatch {
+                id: id.clone(),
+                score: 0.0,
+                metadata: metadata.clone(),
+            })
+            .collect())
+    }
+
+    async fn delete(&self, namespace: &str, filter: Value) -> Result<(), String> {
+        let filter = filter.as_object().cloned().unwrap_or_default();
+        self.vectors.lock().unwrap().retain(|(_, _, metadata, ns)| {
+            ns != namespace || !filter.iter().all(|(key, value)| &metadata[key] == value)
+        });
+        Ok(())
+    }
+
+    async fn delete_ids(&self, namespace: &str, ids: &[String]) -> Result<(), String> {
+        self.vectors
+            .lock()
+            .unwrap()
+            .retain(|(id, _, _, ns)| ns != namespace || !ids.contains(id));
+        Ok(())
+    }
+}
+
+/// Users, goals, tasks, notifications and knowledge documents kept in memory. Ids are handed out as `task-1`,
+/// `notification-1`, `interaction-1`, ...
+#[derive(Default)]
+pub struct InMemoryDatabase {
+    pub users: Mutex<Vec<Value>>,
+    pub goals: Mutex<Vec<Value>>,
+    pub tasks: Mutex<Vec<Value>>,
+    pub notifications: Mutex<Vec<Value>>,
+    pub preferences: Mutex<HashMap<String, Value>>,
+    pub actors: Mutex<Vec<Value>>,
+    pub documents: Mutex<Vec<Value>>,
+    pub user_tokens: Mutex<Vec<String>>, // Tokens handed to `for_user`
+    me: Weak<InMemoryDatabase>,          // Handed out by `for_user`, so user handles share rows
+    pub erasures: Mutex<Vec<Value>>,
+    pub audit: Mutex<Vec<Value>>,
+    pub interactions: Mutex<Vec<Value>>,
+    pub llm_usage: Mutex<Vec<Value>>,
+    pub quotas: Mutex<HashMap<String, Value>>,
+    pub usage_error: Mutex<Option<String>>, // Returned by `usage_report` while set
+}
+
+impl InMemoryDatabase {
+    pub fn add_user(&self, user_id: &str) {
+        self.users.lock().unwrap().push(json!({
+            "id": user_id,
+            "level": 1,
+            "total_xp": 0,
+            "last_active_at": null,
+        }));
+    }
+
+    /// Adds a seeded agent with no owner, like Coach Alex in the initial migration.
+    pub fn add_template(&self, agent_id: &str, name: &str, personality: &str, specialty: &str) {
+        self.actors.lock().unwrap().push(json!({
+            "id": agent_id,
+            "user_id": null,
+            "name": name,
+            "personality": personality,
+            "specialty": specialty,
+            "avatar_url": "https://images.unsplash.com/photo-1594824476967-48c8b964273f",
+            "goals": [],
+            "knowledge_base": null,
+        }));
+    }
+
+    pub fn set_user_field(&self, user_id: &str, field: &str, value: Value) {
+        for user in self.users.lock().unwrap().iter_mut() {
+            if user["id"] == user_id {
+                user[field] = value.clone();
+            }
+        }
+    }
+
+    pub fn add_goal(&self, user_id: &str, goal_id: &str, title: &str, category: &str) {
+        self.goals.lock().unwrap().push(json!({
+            "id": goal_id,
+            "user_id": user_id,
+            "title": title,
+            "category": category,
+            "xp": 0,
+            "level": 1,
+            "status": "active",
+        }));
+    }
+}
+
+fn find(rows: &Mutex<Vec<Value>>, id: &str) -> Option<Value> {
+    rows.lock()
+        .unwrap()
+        .iter()
+        .find(|row| row["id"] == id)
+        .cloned()
+}
+
+#[async_trait]
+impl Database for InMemoryDatabase {
+    fn for_user(&self, access_token: &str) -> Result<Arc<dyn Database>, String> {
+        self.user_tokens
+            .lock()
+            .unwrap()
+            .push(access_token.to_string());
+        self.me
+            .upgrade()
+            .map(|db| db as Arc<dyn Database>)
+            .ok_or_else(|| "Database was dropped".to_string())
+    }
+
+    async fn get_user(&self, user_id: &str) -> Result<Option<Value>, String> {
+        Ok(find(&self.users, user_id))
+    }
+
+    async fn list_users(&self) -> Result<Vec<Value>, String> {
+        Ok(self.users.lock().unwrap().clone())
+    }
+
+    async fn touch_user(&self, user_id: &str) -> Result<(), String> {
+        self.set_user_field(user_id, "last_active_at", json!(Utc::now().to_rfc3339()));
+        Ok(())
+    }
+
+    async fn list_goals(&self, user_id: &str) -> Result<Vec<Value>, String> {
+        Ok(self
+            .goals
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|goal| goal["user_id"] == user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_goal(&self, goal_id: &str) -> Result<Option<Value>, String> {
+        Ok(find(&self.goals, goal_id))
+    }
+
+    async fn list_tasks(&self, goal_id: &str) -> Result<Vec<Value>, String> {
+        Ok(self
+            .tasks
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|task| task["goal_id"] == goal_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_task(&self, task_id: &str) -> Result<Option<Value>, String> {
+        Ok(find(&self.tasks, task_id))
+    }
+
+    async fn add_task(&self, mut task: Value) -> Result<String, String> {
+        let mut tasks = self.tasks.lock().unwrap();
+        let id = format!("task-{}", tasks.len() + 1);
+        task["id"] = json!(id);
+        task["status"] = json!("pending");
+        if task["xp_reward"].is_null() {
+            task["xp_reward"] = json!(10);
+        }
+        tasks.push(task);
+        Ok(id)
+    }
+
+    async fn add_tasks(&self, tasks: Vec<Value>) -> Result<Vec<String>, String> {
+        let mut ids = Vec::new();
+        for task in tasks {
+            ids.push(self.add_task(task).await?);
+        }
+        Ok(ids)
+    }
+
+    async fn complete_task(&self, user_id: &str, task_id: &str) -> Result<(i64, i64), String> {
+        let task =
+            find(&self.tasks, task_id).ok_or_else(|| format!("Task {} not found", task_id))?;
+        let goal_id = task["goal_id"].as_str().unwrap_or_default().to_string();
+        let goal = find(&self.goals, &goal_id).filter(|goal| goal["user_id"] == user_id);
+        if goal.is_none() {
+            return Err(format!(
+                "Task {} does not belong to user {}",
+                task_id, user_id
+            ));
+        }
+        if task["status"] == "completed" {
+            return Err(format!("Task {} is already completed", task_id));
+        }
+        let xp = task["xp_reward"].as_i64().unwrap_or(0);
+
+        for task in self.tasks.lock().unwrap().iter_mut() {
+            if task["id"] == task_id {
+                task["status"] = json!("completed");
+            }
+        }
+        for goal in self.goals.lock().unwrap().iter_mut() {
+            if goal["id"] == goal_id.as_str() {
+                let total = goal["xp"].as_i64().unwrap_or(0) + xp;
+                goal["xp"] = json!(total);
+                goal["level"] = json!(level_for_xp(total));
+            }
+        }
+        let mut level = 1;
+        for user in self.users.lock().unwrap().iter_mut() {
+            if user["id"] == user_id {
+                let total = user["total_xp"].as_i64().unwrap_or(0) + xp;
+                level = level_for_xp(total);
+                user["total_xp"] = json!(total);
+                user["level"] = json!(level);
+            }
+        }
+        Ok((xp, level))
+    }
+
+    async fn list_actor_templates(&self) -> Result<Vec<Value>, String> {
+        Ok(self
+            .actors
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|actor| actor["user_id"].is_null())
+            .cloned()
+            .collect())
+    }
+
+    async fn get_actor_template(&self, agent_id: &str) -> Result<Option<Value>, String> {
+        Ok(find(&self.actors, agent_id).filter(|actor| actor["user_id"].is_null()))
+    }
+
+    async fn save_actor(&self, actor: Value) -> Result<(), String> {
+        let mut actors = self.actors.lock().unwrap();
+        actors.retain(|existing| existing["id"] != actor["id"]);
+        actors.push(actor);
+        Ok(())
+    }
+
+    async fn delete_actor(&self, actor_id: &str) -> Result<(), String> {
+        self.interactions
+            .lock()
+            .unwrap()
+            .retain(|interaction| interaction["actor_id"] != actor_id);
+        self.actors
+            .lock()
+            .unwrap()
+            .retain(|actor| actor["id"] != actor_id);
+        Ok(())
+    }
+
+    async fn list_user_actors(&self, user_id: &str) -> Result<Vec<Value>, String> {
+        Ok(self
+            .actors
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|actor| actor["user_id"] == user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn add_interaction(&self, mut interaction: Value) -> Result<String, String> {
+        let mut interactions = self.interactions.lock().unwrap();
+        let id = format!("interaction-{}", interactions.len() + 1);
+        interaction["id"] = json!(id);
+        interaction["created_at"] = json!(Utc::now().to_rfc3339());
+        interactions.push(interaction);
+        Ok(id)
+    }
+
+    async fn list_interactions(&self, actor_id: &str) -> Result<Vec<Value>, String> {
+        Ok(self
+            .interactions
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|interaction| interaction["actor_id"] == actor_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn list_historical_interactions(&self, _actor_id: &str) -> Result<Vec<Value>, String> {
+        Ok(Vec::new())
+    }
+
+    async fn save_knowledge_document(&self, document: Value) -> Result<(), String> {
+        let mut documents = self.documents.lock().unwrap();
+        documents.retain(|existing| existing["id"] != document["id"]);
+        documents.push(document);
+        Ok(())
+    }
+
+    async fn list_knowledge_documents(&self, actor_id: &str) -> Result<Vec<Value>, String> {
+        Ok(self
+            .documents
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|document| document["actor_id"] == actor_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_knowledge_document(
+        &self,
+        actor_id: &str,
+        document_id: &str,
+    ) -> Result<Option<Value>, String> {
+        Ok(find(&self.documents, document_id).filter(|document| document["actor_id"] == actor_id))
+    }
+
+    async fn delete_knowledge_document(
+        &self,
+        actor_id: &str,
+        document_id: &str,
+    ) -> Result<(), String> {
+        let mut documents = self.documents.lock().unwrap();
+        let before = documents.len();
+        documents.retain(|d| d["id"] != document_id || d["actor_id"] != actor_id);
+        if documents.len() == before {
+            return Err(format!("Document {} not found", document_id));
+        }
+        Ok(())
+    }
+
+    async fn add_notification(&self, mut notification: Value) -> Result<String, String> {
+        let mut notifications = self.notifications.lock().unwrap();
+        let id = format!("notification-{}", notifications.len() + 1);
+        notification["id"] = json!(id);
+        notifications.push(notification);
+        Ok(id)
+    }
+
+    async fn list_notifications(
+        &self,
+        user_id: &str,
+        unread_only: bool,
+        limit: usize,
+    ) -> Result<Vec<Value>, String> {
+        Ok(self
+            .notifications
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|n| n["user_id"] == user_id && (!unread_only || n["read_at"].is_null()))
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_notification_preferences(&self, user_id: &str) -> Result<Option<Value>, String> {
+        Ok(self.preferences.lock().unwrap().get(user_id).cloned())
+    }
+
+    async fn set_notification_preferences(
+        &self,
+        user_id: &str,
+        preferences: Value,
+    ) -> Result<(), String> {
+        self.preferences
+            .lock()
+            .unwrap()
+            .insert(user_id.to_string(), preferences);
+        Ok(())
+    }
+
+    async fn update_notification(
+        &self,
+        user_id: &str,
+        notification_id: &str,
+        changes: Value,
+    ) -> Result<(), String> {
+        let mut notifications = self.notifications.lock().unwrap();
+        let notification = notifications
+            .iter_mut()
+            .find(|n| n["id"] == notification_id && n["user_id"] == user_id)
+            .ok_or_else(|| format!("Notification {} not found", notification_id))?;
+        for (key, value) in changes.as_object().cloned().unwrap_or_default() {
+            notification[key] = value;
+        }
+        Ok(())
+    }
+
+    async fn delete_user(&self, user_id: &str) -> Result<(), String> {
+        let goal_ids: Vec<Value> = self
+            .goals
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|goal| goal["user_id"] == user_id)
+            .map(|goal| goal["id"].clone())
+            .collect();
+        self.tasks
+            .lock()
+            .unwrap()
+            .retain(|task| !goal_ids.contains(&task["goal_id"]));
+        self.goals
+            .lock()
+            .unwrap()
+            .retain(|goal| goal["user_id"] != user_id);
+        for rows in [
+            &self.actors,
+            &self.documents,
+            &self.notifications,
+            &self.llm_usage,
+        ] {
+            rows.lock().unwrap().retain(|row| row["user_id"] != user_id);
+        }
+        self.preferences.lock().unwrap().remove(user_id);
+        self.quotas.lock().unwrap().remove(user_id);
+        self.users
+            .lock()
+            .unwrap()
+            .retain(|user| user["id"] != user_id);
+        Ok(())
+    }
+
+    async fn get_user_quota(&self, user_id: &str) -> Result<Option<Value>, String> {
+        Ok(self.quotas.lock().unwrap().get(user_id).cloned())
+    }
+
+    async fn set_user_quota(&self, user_id: &str, mut quota: Value) -> Result<(), String> {
+        quota["user_id"] = json!(user_id);
+        self.quotas
+            .lock()
+            .unwrap()
+            .insert(user_id.to_string(), quota);
+        Ok(())
+    }
+
+    async fn record_erasure(&self, record: Value) -> Result<(), String> {
+        self.erasures.lock().unwrap().push(record);
+        Ok(())
+    }
+
+    async fn add_audit_entry(&self, mut entry: Value) -> Result<(), String> {
+        entry["created_at"] = json!(Utc::now().to_rfc3339());
+        self.audit.lock().unwrap().push(entry);
+        Ok(())
+    }
+
+    async fn list_audit_entries(&self, query: &AuditQuery) -> Result<Vec<Value>, String> {
+        Ok(self
+            .audit
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|entry| {
+                query
+                    .equals()
+                    .iter()
+                    .all(|(column, value)| entry[*column] == *value)
+            })
+            .take(query.limit())
+            .cloned()
+            .collect())
+    }
+
+    async fn add_llm_usage(&self, rows: Vec<Value>) -> Result<(), String> {
+        let mut usage = self.llm_usage.lock().unwrap();
+        for mut row in rows {
+            if row["created_at"].is_null() {
+                row["created_at"] = json!(Utc::now().to_rfc3339());
+            }
+            usage.push(row);
+        }
+        Ok(())
+    }
+
+    async fn usage_report(
+        &self,
+        query: &UsageQuery,
+        groups: &[UsageGroup],
+    ) -> Result<Vec<UsageTotals>, String> {
+        if let Some(error) = self.usage_error.lock().unwrap().clone() {
+            return Err(error);
+        }
+        let rows: Vec<Value> = self
+            .llm_usage
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|row| {
+                let created_at = row["created_at"]
+                    .as_str()
+                    .and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+                    .map(|at| at.with_timezone(&Utc));
+                query
+                    .equals()
+                    .iter()
+                    .all(|(column, value)| row[*column] == *value)
+                    && query.since.is_none_or(|since| created_at >= Some(since))
+                    && query.until.is_none_or(|until| created_at < Some(until))
+            })
+            .cloned()
+            .collect();
+        Ok(aggregate(&rows, groups))
+    }
+}
+
+/// Outbound channel that records what it was asked to deliver.
+pub struct RecordingChannel {
+    pub sent: Mutex<Vec<(NotificationPreferences, Value)>>,
+}
+
+#[async_trait]
+impl NotificationChannel for RecordingChannel {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    async fn deliver(
+        &self,
+        preferences: &NotificationPreferences,
+        notification: &Value,
+    ) -> Result<(), String> {
+        self.sent
+            .lock()
+            .unwrap()
+            .push((preferences.clone(), notification.clone()));
+        Ok(())
+    }
+}
+
+/// Stands in for a provider moderation API: flags text containing `keyword`.
+pub struct KeywordClassifier {
+    pub keyword: &'static str,
+    pub category: ModerationCategory,
+}
+
+#[async_trait]
+impl ModerationClassifier for KeywordClassifier {
+    fn name(&self) -> &'static str {
+        "keyword"
+    }
+
+    async fn classify(&self, text: &str) -> Result<Vec<ModerationCategory>, String> {
+        Ok(if text.contains(self.keyword) {
+            vec![self.category]
+        } else {
+            Vec::new()
+        })
+    }
+}
+
+/// In-memory doubles wired into a `Services` bundle, kept around so tests can inspect them.
+pub struct TestBackends {
+    pub llm: Arc<MockLlm>,
+    pub vectors: Arc<InMemoryVectorStore>,
+    pub db: Arc<InMemoryDatabase>,
+    pub channel: Arc<RecordingChannel>,
+}
+
+pub fn test_services() -> (Services, TestBackends) {
+    let backends = TestBackends {
+        llm: Arc::new(MockLlm::default()),
+        vectors: Arc::new(InMemoryVectorStore::default()),
+        db: Arc::new_cyclic(|me| InMemoryDatabase {
+            me: me.clone(),
+            ..Default::default()
+        }),
+        channel: Arc::new(RecordingChannel {
+            sent: Mutex::new(Vec::new()),
+        }),
+    };
+    let services = Services::new(
+        reqwest::Client::new(),
+        Backends {
+            llm: backends.llm.clone(),
+            embeddings: Arc::new(MockEmbeddings),
+            vectors: Some(backends.vectors.clone()),
+            db: Some(backends.db.clone()),
+            channels: vec![backends.channel.clone()],
+            moderation: Some(Arc::new(KeywordClassifier {
+                keyword: "FORBIDDEN",
+                category: ModerationCategory::Violence,
+            })),
+        },
+    );
+    (services, backends)
+}
+
+/// The Postgres backend at `DATABASE_URL`, which must have the migrations applied. Tests that
+/// need it are ignored by default; run them with `cargo test -- --ignored` against a database.
+pub fn postgres() -> PostgresService {
+    assert!(
+        std::env::var("DATABASE_URL").is_ok(),
+        "DATABASE_URL must point at a migrated database to run the Postgres tests"
+    );
+    PostgresService::new().unwrap()
+}
+
+/// Inserts a fresh user with one goal, returning their ids.
+pub async fn seed_postgres_user(db: &PostgresService) -> (String, String) {
+    let client = db.pool().get().await.unwrap();
+    let user_id = Uuid::new_v4();
+    client
+        .execute("INSERT INTO auth.users (id) VALUES ($1)", &[&user_id])
+        .await
+        .unwrap();
+    client
+        .execute("INSERT INTO users (id) VALUES ($1)", &[&user_id])
+        .await
+        .unwrap();
+    let row = client
+        .query_one(
+            "INSERT INTO goals (user_id, category, title) VALUES ($1, 'fitness', 'Run a 10k') \
+             RETURNING id",
+            &[&user_id],
+        )
+        .await
+        .unwrap();
+    let goal_id: Uuid = row.get(0);
+    (user_id.to_string(), goal_id.to_string())
+}